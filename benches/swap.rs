@@ -0,0 +1,164 @@
+extern crate criterion;
+extern crate fs_swap;
+extern crate tempdir;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use criterion::measurement::{Measurement, ValueFormatter};
+use fs_swap::SwapOptions;
+use tempdir::TempDir;
+
+/// System allocator counting the allocations, measured by `Allocations`.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.realloc(ptr, layout, new_size)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Measures the number of heap allocations instead of the time.
+struct Allocations;
+
+impl Measurement for Allocations {
+	type Intermediate = u64;
+	type Value = u64;
+
+	fn start(&self) -> u64 {
+		ALLOCATIONS.load(Ordering::Relaxed)
+	}
+
+	fn end(&self, start: u64) -> u64 {
+		ALLOCATIONS.load(Ordering::Relaxed) - start
+	}
+
+	fn add(&self, v1: &u64, v2: &u64) -> u64 {
+		v1 + v2
+	}
+
+	fn zero(&self) -> u64 {
+		0
+	}
+
+	fn to_f64(&self, value: &u64) -> f64 {
+		*value as f64
+	}
+
+	fn formatter(&self) -> &dyn ValueFormatter {
+		self
+	}
+}
+
+impl ValueFormatter for Allocations {
+	fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+		"allocs"
+	}
+
+	fn scale_throughputs(&self, _typical_value: f64, _throughput: &Throughput, _values: &mut [f64]) -> &'static str {
+		"allocs"
+	}
+
+	fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+		"allocs"
+	}
+}
+
+fn create_dir(path: &Path, files: usize) {
+	fs::create_dir(path).unwrap();
+	for i in 0..files {
+		fs::write(path.join(format!("file_{}", i)), b"content").unwrap();
+	}
+}
+
+fn bench_files(c: &mut Criterion) {
+	let dir = TempDir::new("bench").unwrap();
+	let a = dir.path().join("a");
+	let b = dir.path().join("b");
+	fs::write(&a, b"foo").unwrap();
+	fs::write(&b, b"bar").unwrap();
+
+	c.bench_function("swap files", |bench| bench.iter(|| fs_swap::swap(&a, &b).unwrap()));
+	c.bench_function("swap_nonatomic files", |bench| bench.iter(|| fs_swap::swap_nonatomic(&a, &b).unwrap()));
+}
+
+fn bench_dirs(c: &mut Criterion) {
+	for &(name, files) in &[("small", 10), ("large", 10_000)] {
+		let dir = TempDir::new("bench").unwrap();
+		let a = dir.path().join("a");
+		let b = dir.path().join("b");
+		create_dir(&a, files);
+		create_dir(&b, files);
+
+		c.bench_function(&format!("swap {} dirs", name), |bench| bench.iter(|| fs_swap::swap(&a, &b).unwrap()));
+		c.bench_function(&format!("swap_nonatomic {} dirs", name), |bench| bench.iter(|| fs_swap::swap_nonatomic(&a, &b).unwrap()));
+	}
+}
+
+/// Swaps of files on different filesystems, copied chunk by chunk with each buffer size.
+///
+/// One of the files is in `/dev/shm`, a tmpfs on Linux, so the benchmark is skipped where it does
+/// not exist.
+fn bench_copy_buffer_sizes(c: &mut Criterion) {
+	const LEN: usize = 16 * 1024 * 1024;
+
+	let shm = Path::new("/dev/shm");
+	if !shm.is_dir() {
+		return;
+	}
+	let dir = TempDir::new("bench").unwrap();
+	let shm_dir = TempDir::new_in(shm, "bench").unwrap();
+	let a = dir.path().join("a");
+	let b = shm_dir.path().join("b");
+	fs::write(&a, vec![1; LEN]).unwrap();
+	fs::write(&b, vec![2; LEN]).unwrap();
+
+	let mut group = c.benchmark_group("copy buffer size");
+	group.throughput(Throughput::Bytes(2 * LEN as u64)).sample_size(10);
+	for &size in &[16 * 1024, 64 * 1024, 128 * 1024, 1024 * 1024, 4 * 1024 * 1024] {
+		// the progress callback makes the files copied chunk by chunk
+		let options = SwapOptions::new().copy_buffer_size(size).progress(|_, _| ());
+		group.bench_function(BenchmarkId::from_parameter(format!("{} KiB", size / 1024)), |bench| {
+			bench.iter(|| fs_swap::swap_with(&a, &b, &options).unwrap())
+		});
+	}
+	group.finish();
+}
+
+/// Allocations per swap of two files, when swapping them over and over.
+fn bench_allocations(c: &mut Criterion<Allocations>) {
+	let dir = TempDir::new("bench").unwrap();
+	let a = dir.path().join("a");
+	let b = dir.path().join("b");
+	fs::write(&a, b"foo").unwrap();
+	fs::write(&b, b"bar").unwrap();
+
+	c.bench_function("swap allocations", |bench| bench.iter(|| fs_swap::swap(&a, &b).unwrap()));
+	c.bench_function("swap_nonatomic allocations", |bench| bench.iter(|| fs_swap::swap_nonatomic(&a, &b).unwrap()));
+}
+
+criterion_group!(benches, bench_files, bench_dirs, bench_copy_buffer_sizes);
+criterion_group! {
+	// the counts are always the same, so there is no distribution to plot
+	name = allocations;
+	config = Criterion::default().with_measurement(Allocations).without_plots();
+	targets = bench_allocations
+}
+criterion_main!(benches, allocations);
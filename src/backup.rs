@@ -0,0 +1,119 @@
+use std::ffi::OsString;
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use copy::{copy_to_tmp, remove_leftover, remove_path, CopyControl, CopyStats};
+use error::SwapError;
+use filesystem::StdFs;
+use {swap, swap_auto, tmp_path_in_dir};
+
+/// Backups of the original content of the paths swapped by `swap_with_backup`.
+#[derive(Debug)]
+#[must_use = "the backups are kept until they are restored or discarded"]
+pub struct BackupHandle {
+	a: PathBuf,
+	b: PathBuf,
+	backup_of_a: PathBuf,
+	backup_of_b: PathBuf,
+}
+
+impl BackupHandle {
+	/// Path of the backup of the original content of `a`.
+	pub fn backup_of_a(&self) -> &Path {
+		&self.backup_of_a
+	}
+
+	/// Path of the backup of the original content of `b`.
+	pub fn backup_of_b(&self) -> &Path {
+		&self.backup_of_b
+	}
+
+	/// Moves the backups back to `a` and `b`, and removes what replaced them.
+	pub fn restore(self) -> Result<(), SwapError> {
+		for &(path, backup) in &[(&self.a, &self.backup_of_a), (&self.b, &self.backup_of_b)] {
+			swap_auto(path, backup)?;
+			remove_leftover(&StdFs, backup)?;
+		}
+		Ok(())
+	}
+
+	/// Removes the backups.
+	pub fn discard(self) -> io::Result<()> {
+		remove_leftover(&StdFs, &self.backup_of_a)?;
+		remove_leftover(&StdFs, &self.backup_of_b)
+	}
+}
+
+/// Returns an unused path in `dir` for the backup of `path`, named after it and the current time.
+fn backup_path(dir: &Path, path: &Path) -> io::Result<PathBuf> {
+	let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+	let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+	name.push(format!(".{}", secs));
+
+	for i in 0.. {
+		let mut candidate = name.clone();
+		if i > 0 {
+			candidate.push(format!(".{}", i));
+		}
+		candidate.push(".bak");
+		let backup = dir.join(candidate);
+		match fs::symlink_metadata(&backup) {
+			Ok(_) => continue,
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(backup),
+			Err(err) => return Err(err),
+		}
+	}
+	unreachable!()
+}
+
+/// Copies `path` to a new backup in `dir`. The copy is made in a temp path first and renamed
+/// once it's complete, so a crash never leaves a partial backup.
+fn backup(dir: &Path, path: &Path) -> io::Result<PathBuf> {
+	let tmp = tmp_path_in_dir(dir)?;
+	copy_to_tmp(&StdFs, path, &tmp, &mut CopyStats::default(), &mut CopyControl::default())?;
+
+	let backup = backup_path(dir, path)?;
+	if let Err(err) = fs::rename(&tmp, &backup) {
+		let _ = remove_path(&StdFs, &tmp);
+		return Err(err);
+	}
+	Ok(backup)
+}
+
+/// Copies the content of paths `a` and `b` to timestamped backups in `backup_dir`, then swaps
+/// them with `swap`.
+///
+/// If the swap fails, the backups are removed, unless the paths were left corrupted.
+pub fn swap_with_backup<A, B, D>(a: A, b: B, backup_dir: D) -> Result<BackupHandle, SwapError> where A: AsRef<Path>, B: AsRef<Path>, D: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	let backup_dir = backup_dir.as_ref();
+
+	let backup_of_a = backup(backup_dir, a).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	let backup_of_b = match backup(backup_dir, b) {
+		Ok(backup) => backup,
+		Err(err) => {
+			let _ = remove_path(&StdFs, &backup_of_a);
+			return Err(SwapError::from_unmodified(err, a, b));
+		},
+	};
+
+	let handle = BackupHandle {
+		a: a.to_path_buf(),
+		b: b.to_path_buf(),
+		backup_of_a,
+		backup_of_b,
+	};
+
+	match swap(a, b) {
+		Ok(_) => Ok(handle),
+		Err(err @ SwapError::Corrupted { .. }) => {
+			swap_log!(warn, "Swap failed, original content is kept in backups"; backup_of_a = ?handle.backup_of_a, backup_of_b = ?handle.backup_of_b);
+			Err(err)
+		},
+		Err(err) => {
+			let _ = handle.discard();
+			Err(err)
+		},
+	}
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+use error::SwapError;
+use options::SwapOptions;
+use {swap, swap_with, validate, SwapKind};
+
+/// Outcome of the swap of one pair of a batch.
+#[derive(Debug)]
+pub enum PairOutcome {
+	/// The pair was swapped.
+	Swapped(SwapKind),
+	/// The pair was not swapped, because a previous pair failed or the batch failed validation.
+	Skipped,
+	/// The pair could not be swapped.
+	Failed(SwapError),
+}
+
+/// Outcomes of a batch of swaps, one per pair, in the order of the pairs.
+#[must_use = "some pairs may not have been swapped"]
+#[derive(Debug)]
+pub struct BatchOutcome {
+	pairs: Vec<PairOutcome>,
+}
+
+impl BatchOutcome {
+	/// Returns the outcome of each pair.
+	pub fn pairs(&self) -> &[PairOutcome] {
+		&self.pairs
+	}
+
+	/// Returns true if all pairs were swapped.
+	pub fn is_success(&self) -> bool {
+		self.pairs.iter().all(|outcome| matches!(*outcome, PairOutcome::Swapped(_)))
+	}
+
+	/// Returns the index and the error of each pair which could not be swapped.
+	pub fn failures(&self) -> impl Iterator<Item = (usize, &SwapError)> {
+		self.pairs.iter().enumerate().filter_map(|(i, outcome)| match *outcome {
+			PairOutcome::Failed(ref err) => Some((i, err)),
+			_ => None,
+		})
+	}
+
+	/// Returns the index and the error of the first pair which could not be swapped, like
+	/// `swap_many`.
+	pub fn into_result(self) -> Result<(), (usize, SwapError)> {
+		match self.pairs.into_iter().enumerate().find(|(_, outcome)| matches!(*outcome, PairOutcome::Failed(_))) {
+			Some((i, PairOutcome::Failed(err))) => Err((i, err)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Swaps each pair of paths in order with `swap`.
+///
+/// Stops at the first pair which could not be swapped and returns its index together with the
+/// error. Pairs swapped before it are left swapped, pairs after it are left untouched, so the
+/// caller can resume from the returned index. Use `swap_many_with` to get the outcome of each
+/// pair.
+///
+/// Before swapping anything, fails with an error of kind `InvalidInput` if two pairs refer to the
+/// same path, even through a different spelling or a symlinked parent directory.
+pub fn swap_many<P, Q>(pairs: &[(P, Q)]) -> Result<(), (usize, SwapError)> where P: AsRef<Path>, Q: AsRef<Path> {
+	validate::check_distinct(pairs.iter().map(|(a, b)| vec![a.as_ref(), b.as_ref()]))?;
+
+	for (i, (a, b)) in pairs.iter().enumerate() {
+		swap(a, b).map_err(|err| (i, err))?;
+	}
+	Ok(())
+}
+
+/// Swaps each pair of paths in order with `swap_with`, and returns the outcome of each pair.
+///
+/// By default, pairs after the first one which could not be swapped are skipped. With
+/// `SwapOptions::continue_on_error`, the remaining pairs are swapped anyway.
+///
+/// Pairs are validated like with `swap_many`. If two pairs refer to the same path, the second
+/// one fails and all others are skipped.
+pub fn swap_many_with<P, Q>(pairs: &[(P, Q)], options: &SwapOptions) -> BatchOutcome where P: AsRef<Path>, Q: AsRef<Path> {
+	let mut outcomes: Vec<_> = pairs.iter().map(|_| PairOutcome::Skipped).collect();
+	if let Err((i, err)) = validate::check_distinct(pairs.iter().map(|(a, b)| vec![a.as_ref(), b.as_ref()])) {
+		outcomes[i] = PairOutcome::Failed(err);
+		return BatchOutcome { pairs: outcomes };
+	}
+
+	for (i, (a, b)) in pairs.iter().enumerate() {
+		match swap_with(a, b, options) {
+			Ok(kind) => outcomes[i] = PairOutcome::Swapped(kind),
+			Err(err) => {
+				outcomes[i] = PairOutcome::Failed(err);
+				if !options.continue_on_error {
+					break;
+				}
+			},
+		}
+	}
+	BatchOutcome { pairs: outcomes }
+}
@@ -0,0 +1,68 @@
+use std::{fs, io};
+use std::path::Path;
+use {platform, tmp_path_in_dir};
+
+/// Swap mechanisms supported by a filesystem, returned by `capabilities`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+	/// Paths can be swapped atomically, so `swap` does not fall back to `swap_nonatomic`.
+	pub atomic_exchange: bool,
+	/// Files can be cloned, so copying them to another directory of the filesystem is cheap.
+	pub reflink: bool,
+	/// Paths can be renamed within the filesystem, which `swap_nonatomic` relies on.
+	pub same_device_rename: bool,
+}
+
+/// Creates an empty scratch file in `dir`, runs `f` with its path and removes it.
+fn with_scratch_file<T, F>(dir: &Path, f: F) -> io::Result<T> where F: FnOnce(&Path) -> io::Result<T> {
+	let path = tmp_path_in_dir(dir)?;
+	fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+	let result = f(&path);
+	let _ = fs::remove_file(&path);
+	result
+}
+
+fn reflink_supported(dir: &Path) -> io::Result<bool> {
+	with_scratch_file(dir, |from| {
+		let to = tmp_path_in_dir(dir)?;
+		let supported = platform::reflink(from, &to).is_ok();
+		let _ = fs::remove_file(&to);
+		Ok(supported)
+	})
+}
+
+fn rename_supported(dir: &Path) -> io::Result<bool> {
+	with_scratch_file(dir, |from| {
+		let to = tmp_path_in_dir(dir)?;
+		match fs::rename(from, &to) {
+			Ok(_) => {
+				let _ = fs::rename(&to, from);
+				Ok(true)
+			},
+			Err(_) => Ok(false),
+		}
+	})
+}
+
+/// Returns the swap mechanisms supported by the filesystem of `path`, which may be a directory
+/// or a file.
+///
+/// Nothing is swapped, but the filesystem is probed with scratch files created in the directory
+/// and removed afterwards, so it must be writable.
+pub fn capabilities<P>(path: P) -> io::Result<Capabilities> where P: AsRef<Path> {
+	let path = path.as_ref();
+	let dir = if fs::metadata(path)?.is_dir() {
+		path
+	} else {
+		match path.parent() {
+			Some(parent) if parent != Path::new("") => parent,
+			_ => Path::new("."),
+		}
+	};
+
+	Ok(Capabilities {
+		atomic_exchange: platform::atomic_supported(dir)?,
+		reflink: reflink_supported(dir)?,
+		same_device_rename: rename_supported(dir)?,
+	})
+}
@@ -0,0 +1,25 @@
+//! Waiting used by the retries and the timeouts, replaceable in tests.
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Source of the waits of the retry backoff and of `SwapOptions::timeout`.
+pub(crate) trait Clock {
+	/// Blocks the current thread for `duration`.
+	fn sleep(&self, duration: Duration);
+
+	/// Waits at most `timeout` for a value sent to `receiver`.
+	fn recv_timeout<T>(&self, receiver: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
+		receiver.recv_timeout(timeout)
+	}
+}
+
+/// Clock waiting in real time.
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+	fn sleep(&self, duration: Duration) {
+		thread::sleep(duration)
+	}
+}
@@ -0,0 +1,75 @@
+//! Skipping swaps of identical files.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read};
+use std::path::Path;
+use error::SwapError;
+use swap;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads from `reader` until `buf` is full or the end of the file is reached.
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+	let mut len = 0;
+	while len < buf.len() {
+		match reader.read(&mut buf[len..]) {
+			Ok(0) => break,
+			Ok(n) => len += n,
+			Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+			Err(err) => return Err(err),
+		}
+	}
+	Ok(len)
+}
+
+/// Returns the hash of the content of file `path`, read chunk by chunk with a hasher built by
+/// `state`.
+fn content_hash(path: &Path, state: &RandomState) -> io::Result<u64> {
+	let mut file = File::open(path)?;
+	let mut hasher = state.build_hasher();
+	let mut buf = vec![0; CHUNK_SIZE];
+	loop {
+		let len = read_chunk(&mut file, &mut buf)?;
+		if len == 0 {
+			return Ok(hasher.finish());
+		}
+		hasher.write(&buf[..len]);
+	}
+}
+
+/// Returns true if files `a` and `b` have the same content.
+///
+/// Sizes are compared first, then the hashes of the contents, which are streamed so that files
+/// are never loaded in memory. The hasher is keyed randomly for each comparison, so that files
+/// crafted to have the same hash cannot be mistaken for identical ones.
+pub(crate) fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+	let meta_a = fs::symlink_metadata(a)?;
+	let meta_b = fs::symlink_metadata(b)?;
+	if !meta_a.is_file() || !meta_b.is_file() {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only files can be compared"));
+	}
+
+	if meta_a.len() != meta_b.len() {
+		return Ok(false);
+	}
+
+	let state = RandomState::new();
+	Ok(content_hash(a, &state)? == content_hash(b, &state)?)
+}
+
+/// Swaps files `a` and `b` with `swap`, unless they have the same content.
+///
+/// Returns true if the files were swapped. Directories and symlinks are not compared, an error
+/// of kind `InvalidInput` is returned for them.
+pub fn swap_if_different<A, B>(a: A, b: B) -> Result<bool, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+
+	if same_content(a, b).map_err(|err| SwapError::from_unmodified(err, a, b))? {
+		return Ok(false);
+	}
+
+	swap(a, b).map(|_| true)
+}
@@ -0,0 +1,217 @@
+//! Swapping the content of directories, keeping the directories themselves in place.
+
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use compare::same_content;
+use error::SwapError;
+use filesystem::{FileSystem, StdFs};
+use rollback::rename_all;
+use {is_same_path, platform, tmp_path_in_dir};
+
+/// Entries of a deduplicated swap of directory contents, relative to the directories.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DedupReport {
+	/// Entries which differed and were exchanged. An entry present in only one of the
+	/// directories was moved to the other one.
+	pub exchanged: Vec<PathBuf>,
+	/// Files and symlinks which were identical in both directories and were left in place.
+	pub skipped: Vec<PathBuf>,
+}
+
+fn check_dir(path: &Path, a: &Path, b: &Path) -> Result<(), SwapError> {
+	let metadata = fs::symlink_metadata(path).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	if !metadata.is_dir() {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only the content of directories can be swapped").into());
+	}
+	Ok(())
+}
+
+fn entries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+	fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect()
+}
+
+/// Swaps the entries of directories `a` and `b`, so that the directories themselves stay in
+/// place. Open handles to the directories and mount points are not affected.
+///
+/// The entries of `a` are moved to a temp directory inside `a`, so that they stay on its
+/// filesystem even if `a` is a mount point, the entries of `b` are moved to `a` and the entries of
+/// the temp directory to `b`. Directories must be on the same filesystem, and in the same mount
+/// of it, otherwise `SwapError::CrossDevice` is returned once the moves were reverted.
+/// If any move fails, the completed ones are reverted in reverse order and
+/// `SwapError::RolledBack` is returned. If reverting also fails, `SwapError::Corrupted` is
+/// returned and some entries may be left in the temp directory.
+pub fn swap_dir_contents<A, B>(a: A, b: B) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	swap_span!("swap_dir_contents", a, b);
+	swap_dir_contents_in(&StdFs, a, b)
+}
+
+pub(crate) fn swap_dir_contents_in<F>(fs: &F, a: &Path, b: &Path) -> Result<(), SwapError> where F: FileSystem {
+	if is_same_path(a, b) {
+		return Ok(());
+	}
+
+	check_dir(a, a, b)?;
+	check_dir(b, a, b)?;
+	if !platform::same_device(a, b)? {
+		return Err(SwapError::CrossDevice);
+	}
+
+	let tmp = tmp_dir_in(a, b)?;
+	let entries_a = entries(a)?;
+	let entries_b = entries(b)?;
+	fs::create_dir(&tmp)?;
+
+	let mut moves = Vec::with_capacity(2 * entries_a.len() + entries_b.len());
+	for entry in &entries_a {
+		let name = entry.file_name().expect("entries have a file name");
+		moves.push((entry.clone(), tmp.join(name)));
+	}
+	for entry in &entries_b {
+		let name = entry.file_name().expect("entries have a file name");
+		moves.push((entry.clone(), a.join(name)));
+	}
+	for entry in &entries_a {
+		let name = entry.file_name().expect("entries have a file name");
+		moves.push((tmp.join(name), b.join(name)));
+	}
+
+	rename_through(fs, &tmp, &moves)
+}
+
+/// Returns a temp path inside directory `a`, whose name is not used in directory `b` either, so
+/// that no entry of `b` is moved over it.
+fn tmp_dir_in(a: &Path, b: &Path) -> io::Result<PathBuf> {
+	loop {
+		let tmp = tmp_path_in_dir(a)?;
+		let name = tmp.file_name().expect("temp paths have a file name");
+		if optional_metadata(&b.join(name))?.is_none() {
+			return Ok(tmp);
+		}
+	}
+}
+
+/// Renames each `(from, to)` pair of `moves` with `rename_all`, `tmp` being the temp directory
+/// they go through, which is removed afterwards unless it may still hold some entries.
+fn rename_through<F>(fs: &F, tmp: &Path, moves: &[(PathBuf, PathBuf)]) -> Result<(), SwapError> where F: FileSystem {
+	let renames: Vec<_> = moves.iter().map(|(from, to)| (from.as_path(), to.as_path())).collect();
+	match rename_all(fs, &renames) {
+		Ok(_) => (),
+		// the temp directory may hold some of the entries
+		Err(err @ SwapError::Corrupted { .. }) => return Err(err),
+		Err(err) => {
+			let _ = fs::remove_dir(tmp);
+			return Err(match err {
+				// paths in different mounts of one filesystem share its device, but entries
+				// cannot be renamed from one mount to the other
+				SwapError::Io(ref err) | SwapError::RolledBack(ref err) if platform::is_cross_device(err) => SwapError::CrossDevice,
+				err => err,
+			});
+		},
+	}
+
+	if let Err(err) = fs::remove_dir(tmp) {
+		swap_log!(warn, "Removing temp directory failed"; path = ?tmp, error = %err);
+	}
+	Ok(())
+}
+
+fn optional_metadata(path: &Path) -> io::Result<Option<fs::Metadata>> {
+	match fs::symlink_metadata(path) {
+		Ok(metadata) => Ok(Some(metadata)),
+		Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Compares the entries of directories `a/rel` and `b/rel`, recursing into the directories
+/// present in both, and adds them to `report`.
+fn diff(a: &Path, b: &Path, rel: &Path, report: &mut DedupReport) -> io::Result<()> {
+	let mut names = BTreeSet::<OsString>::new();
+	for dir in &[a.join(rel), b.join(rel)] {
+		for entry in fs::read_dir(dir)? {
+			names.insert(entry?.file_name());
+		}
+	}
+
+	for name in names {
+		let rel = rel.join(name);
+		let path_a = a.join(&rel);
+		let path_b = b.join(&rel);
+		let identical = match (optional_metadata(&path_a)?, optional_metadata(&path_b)?) {
+			(Some(ref meta_a), Some(ref meta_b)) if meta_a.is_dir() && meta_b.is_dir() => {
+				diff(a, b, &rel, report)?;
+				continue;
+			},
+			(Some(ref meta_a), Some(ref meta_b)) if meta_a.is_file() && meta_b.is_file() => same_content(&path_a, &path_b)?,
+			(Some(ref meta_a), Some(ref meta_b)) if meta_a.file_type().is_symlink() && meta_b.file_type().is_symlink() => {
+				fs::read_link(&path_a)? == fs::read_link(&path_b)?
+			},
+			_ => false,
+		};
+
+		if identical {
+			report.skipped.push(rel);
+		} else {
+			report.exchanged.push(rel);
+		}
+	}
+	Ok(())
+}
+
+/// Swaps the content of directories `a` and `b` like `swap_dir_contents`, but leaves in place
+/// the files which are identical in both, which is much faster for two trees which barely differ.
+///
+/// The trees are compared recursively: directories present in both are kept and compared entry
+/// by entry, files are compared by size and then by content, and symlinks by target. Only the
+/// entries which differ are exchanged, through a temp directory inside `a`. If a move fails,
+/// the completed ones are reverted and `SwapError::RolledBack` is returned. If reverting also
+/// fails, `SwapError::Corrupted` is returned and some entries may be left in the temp directory.
+///
+/// Since directories present in both trees stay in place, their own permissions are not swapped.
+pub fn swap_dir_contents_dedup<A, B>(a: A, b: B) -> Result<DedupReport, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	swap_span!("swap_dir_contents_dedup", a, b);
+
+	if is_same_path(a, b) {
+		return Ok(DedupReport::default());
+	}
+
+	check_dir(a, a, b)?;
+	check_dir(b, a, b)?;
+	if !platform::same_device(a, b)? {
+		return Err(SwapError::CrossDevice);
+	}
+
+	let mut report = DedupReport::default();
+	diff(a, b, Path::new(""), &mut report).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	if report.exchanged.is_empty() {
+		return Ok(report);
+	}
+
+	let tmp = tmp_dir_in(a, b)?;
+	let mut moves = Vec::with_capacity(3 * report.exchanged.len());
+	for (i, rel) in report.exchanged.iter().enumerate() {
+		let path_a = a.join(rel);
+		let path_b = b.join(rel);
+		let aside = tmp.join(i.to_string());
+		let in_a = optional_metadata(&path_a)?.is_some();
+		if in_a {
+			moves.push((path_a.clone(), aside.clone()));
+		}
+		if optional_metadata(&path_b)?.is_some() {
+			moves.push((path_b.clone(), path_a));
+		}
+		if in_a {
+			moves.push((aside, path_b));
+		}
+	}
+	fs::create_dir(&tmp)?;
+
+	rename_through(&StdFs, &tmp, &moves)?;
+	Ok(report)
+}
@@ -0,0 +1,416 @@
+//! Swapping paths located on different filesystems.
+
+use std::{fs, io};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use error::SwapError;
+use filesystem::{FileSystem, StdFs};
+use options::{Progress, SwapOptions, DEFAULT_COPY_BUFFER_SIZE};
+use platform;
+use retry::Retry;
+use rollback::rename_all;
+use tmp_path_in_dir;
+
+/// Summary of copied paths.
+#[derive(Debug, Default)]
+pub(crate) struct CopyStats {
+	/// Number of files cloned instead of being copied.
+	pub(crate) reflinked_files: usize,
+	/// Number of files copied byte by byte, because they could not be cloned.
+	pub(crate) copied_files: usize,
+	/// Number of bytes copied byte by byte, cloned files are not counted.
+	pub(crate) copied_bytes: u64,
+	/// Number of files copied byte by byte whose holes were kept in the copy.
+	pub(crate) sparse_files: usize,
+}
+
+/// Cancellation flag, progress callback and buffer size of the copies, taken from `SwapOptions`.
+pub(crate) struct CopyControl<'a> {
+	cancel: Option<&'a AtomicBool>,
+	progress: Option<&'a Progress>,
+	buffer_size: usize,
+	done: u64,
+	total: u64,
+}
+
+impl<'a> Default for CopyControl<'a> {
+	fn default() -> Self {
+		CopyControl {
+			cancel: None,
+			progress: None,
+			buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+			done: 0,
+			total: 0,
+		}
+	}
+}
+
+impl<'a> CopyControl<'a> {
+	pub(crate) fn new(options: &'a SwapOptions) -> Self {
+		CopyControl {
+			cancel: options.cancel.as_deref(),
+			progress: options.progress.as_ref(),
+			buffer_size: options.copy_buffer_size,
+			..CopyControl::default()
+		}
+	}
+
+	fn is_cancelled(&self) -> bool {
+		self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+	}
+
+	/// Fails with an `Interrupted` error if the cancellation flag is set.
+	fn check_cancelled(&self) -> io::Result<()> {
+		if self.is_cancelled() {
+			return Err(io::Error::new(io::ErrorKind::Interrupted, "Swap was cancelled"));
+		}
+		Ok(())
+	}
+
+	/// Returns whether files must be copied chunk by chunk, to be cancelled or report progress.
+	fn is_chunked(&self) -> bool {
+		self.cancel.is_some() || self.progress.is_some()
+	}
+
+	/// Adds the size of the files in `path` to the total reported with the progress.
+	pub(crate) fn add_total(&mut self, path: &Path) -> io::Result<()> {
+		if self.progress.is_some() {
+			self.total += files_size(path)?;
+		}
+		Ok(())
+	}
+
+	fn advance(&mut self, len: u64) {
+		self.done += len;
+		if let Some(progress) = self.progress {
+			progress.report(self.done, self.total);
+		}
+	}
+
+	/// Converts an error returned for paths `a` and `b` before any of them was modified, which is
+	/// `SwapError::Cancelled` if the cancellation flag is set.
+	pub(crate) fn unmodified_error(&self, err: io::Error, a: &Path, b: &Path) -> SwapError {
+		if self.is_cancelled() {
+			return SwapError::Cancelled;
+		}
+		SwapError::from_unmodified(err, a, b)
+	}
+}
+
+/// Returns the total size of the files in `path`, symlinks are not followed.
+fn files_size(path: &Path) -> io::Result<u64> {
+	let metadata = fs::symlink_metadata(path)?;
+	if !metadata.is_dir() {
+		return Ok(if metadata.is_file() { metadata.len() } else { 0 });
+	}
+
+	let mut size = 0;
+	for entry in fs::read_dir(path)? {
+		size += files_size(&entry?.path())?;
+	}
+	Ok(size)
+}
+
+/// Copies file, directory or symlink `from` to `to`, which must not exist.
+///
+/// With the `xattr` feature, extended attributes are copied on Linux and macOS. With the `acl`
+/// feature, ACLs of files and directories are copied on Windows. With the `selinux` feature, the
+/// SELinux security context is copied on Linux, which requires the permission to relabel files.
+///
+/// If the cancellation flag of `control` is set during the copy, it fails with an `Interrupted`
+/// error. It is checked before each path and between the chunks of large files.
+pub(crate) fn copy_path(from: &Path, to: &Path, stats: &mut CopyStats, control: &mut CopyControl) -> io::Result<()> {
+	control.check_cancelled()?;
+	copy_content(from, to, stats, control)?;
+
+	#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+	platform::copy_xattrs(from, to)?;
+
+	#[cfg(all(feature = "selinux", target_os = "linux"))]
+	platform::copy_selinux_context(from, to)?;
+
+	#[cfg(all(feature = "acl", windows))]
+	{
+		if !fs::symlink_metadata(from)?.file_type().is_symlink() {
+			platform::copy_security(from, to)?;
+		}
+	}
+
+	Ok(())
+}
+
+fn copy_content(from: &Path, to: &Path, stats: &mut CopyStats, control: &mut CopyControl) -> io::Result<()> {
+	let file_type = fs::symlink_metadata(from)?.file_type();
+	if file_type.is_symlink() {
+		copy_symlink(from, to)
+	} else if file_type.is_dir() {
+		fs::create_dir(to)?;
+		for entry in fs::read_dir(from)? {
+			let entry = entry?;
+			copy_path(&entry.path(), &to.join(entry.file_name()), stats, control)?;
+		}
+		fs::set_permissions(to, fs::metadata(from)?.permissions())
+	} else if file_type.is_file() {
+		copy_file(from, to, stats, control)
+	} else {
+		// FIFOs, sockets and device nodes are recreated, reading them may block
+		copy_special(from, to)
+	}
+}
+
+/// Copies `from` to temp path `to` like `copy_path`, and checks the cancellation flag of `control`
+/// once the copy is complete.
+///
+/// If it fails, the partial copy is removed. `to` is created exclusively before anything is
+/// copied into it, so if it fails with an `AlreadyExists` error, `to` was not created by the copy
+/// and is left untouched.
+pub(crate) fn copy_to_tmp<F>(fs: &F, from: &Path, to: &Path, stats: &mut CopyStats, control: &mut CopyControl) -> io::Result<()> where F: FileSystem {
+	let copied = copy_path(from, to, stats, control).and_then(|_| control.check_cancelled());
+	if let Err(err) = copied {
+		if err.kind() != io::ErrorKind::AlreadyExists {
+			let _ = remove_path(fs, to);
+		}
+		return Err(err);
+	}
+	Ok(())
+}
+
+/// Copies file `from` to `to`, cloning it if the filesystem supports it.
+///
+/// Otherwise, the holes of sparse files are kept on Linux and Windows, so that the copy does not
+/// take more space than the original.
+fn copy_file(from: &Path, to: &Path, stats: &mut CopyStats, control: &mut CopyControl) -> io::Result<()> {
+	match platform::reflink(from, to) {
+		Ok(_) => {
+			stats.reflinked_files += 1;
+			let metadata = fs::metadata(from)?;
+			control.advance(metadata.len());
+			return fs::set_permissions(to, metadata.permissions());
+		},
+		// `to` was not created by the copy
+		Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Err(err),
+		Err(_) => (),
+	}
+
+	stats.copied_files += 1;
+	let reader = fs::File::open(from)?;
+	if let Some(ranges) = platform::data_ranges(&reader)? {
+		stats.sparse_files += 1;
+		stats.copied_bytes += copy_sparse(reader, to, &ranges, control)?;
+		return Ok(());
+	}
+	drop(reader);
+
+	stats.copied_bytes += if control.is_chunked() {
+		copy_chunks(from, to, control)?
+	} else {
+		copy_new(from, to)?
+	};
+	Ok(())
+}
+
+/// Copies file `from` to `to` like `fs::copy`, but fails if `to` exists instead of overwriting it.
+fn copy_new(from: &Path, to: &Path) -> io::Result<u64> {
+	let mut reader = fs::File::open(from)?;
+	let mut writer = fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+	let copied = io::copy(&mut reader, &mut writer)?;
+	fs::set_permissions(to, reader.metadata()?.permissions())?;
+	Ok(copied)
+}
+
+/// Copies file `from` to `to` like `fs::copy`, but chunk by chunk, so that it fails as soon as
+/// the cancellation flag of `control` is set, and reports progress after each chunk.
+fn copy_chunks(from: &Path, to: &Path, control: &mut CopyControl) -> io::Result<u64> {
+	let mut reader = fs::File::open(from)?;
+	let mut writer = fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+	let copied = copy_stream(&mut reader, &mut writer, control)?;
+	fs::set_permissions(to, reader.metadata()?.permissions())?;
+	Ok(copied)
+}
+
+/// Copies sparse file `reader` to `to` like `copy_chunks`, but only its `ranges` holding data, so
+/// that the holes between them are left unallocated in the copy.
+fn copy_sparse(mut reader: fs::File, to: &Path, ranges: &[Range<u64>], control: &mut CopyControl) -> io::Result<u64> {
+	let metadata = reader.metadata()?;
+	let mut writer = fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+	platform::set_sparse(&writer)?;
+	let mut copied = 0;
+	let mut offset = 0;
+	for range in ranges {
+		// holes are not copied, but they count in the progress
+		if range.start > offset {
+			control.advance(range.start - offset);
+		}
+		reader.seek(SeekFrom::Start(range.start))?;
+		writer.seek(SeekFrom::Start(range.start))?;
+		let len = copy_stream(&mut (&mut reader).take(range.end - range.start), &mut writer, control)?;
+		copied += len;
+		offset = range.start + len;
+	}
+	if metadata.len() > offset {
+		control.advance(metadata.len() - offset);
+	}
+	// a trailing hole is only created by the length
+	writer.set_len(metadata.len())?;
+	fs::set_permissions(to, metadata.permissions())?;
+	Ok(copied)
+}
+
+/// Copies `reader` to `writer` until its end, chunk by chunk, checking the cancellation flag of
+/// `control` and reporting progress after each chunk. The chunks are the size of the buffer of
+/// `control`.
+pub(crate) fn copy_stream<R, W>(reader: &mut R, writer: &mut W, control: &mut CopyControl) -> io::Result<u64> where R: Read, W: Write {
+	let mut buf = vec![0; control.buffer_size];
+	let mut copied = 0;
+	loop {
+		control.check_cancelled()?;
+		let len = match reader.read(&mut buf) {
+			Ok(0) => break,
+			Ok(len) => len,
+			Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+			Err(err) => return Err(err),
+		};
+		writer.write_all(&buf[..len])?;
+		copied += len as u64;
+		control.advance(len as u64);
+	}
+	Ok(copied)
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+	::std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+/// Recreates symlink `from` at `to`. Whether it's a directory link is read from the link itself,
+/// so that it's kept even if its target is missing. Junctions are recreated as directory
+/// symlinks.
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+	use std::os::windows::fs::{symlink_dir, symlink_file};
+
+	let target = fs::read_link(from)?;
+	if platform::is_dir_link(&fs::symlink_metadata(from)?) {
+		symlink_dir(target, to)
+	} else {
+		symlink_file(target, to)
+	}
+}
+
+#[cfg(unix)]
+fn copy_special(from: &Path, to: &Path) -> io::Result<()> {
+	platform::copy_special(from, to)?;
+	fs::set_permissions(to, fs::symlink_metadata(from)?.permissions())
+}
+
+#[cfg(not(unix))]
+fn copy_special(_from: &Path, _to: &Path) -> io::Result<()> {
+	Err(io::Error::other("Copying special files is not supported by the current platform"))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn copy_symlink(_from: &Path, _to: &Path) -> io::Result<()> {
+	Err(io::Error::other("Copying symlinks is not supported by the current platform"))
+}
+
+/// Removes file, directory or symlink at `path`.
+pub(crate) fn remove_path<F>(fs: &F, path: &Path) -> io::Result<()> where F: FileSystem {
+	if fs.metadata(path)?.is_dir() {
+		fs.remove_dir_all(path)
+	} else {
+		fs.remove_file(path)
+	}
+}
+
+/// Number of retries of `remove_leftover`, waiting 10ms before the first one and twice as long
+/// before each next one.
+const LEFTOVER_RETRIES: u32 = 5;
+
+/// Removes `path` like `remove_path`, once it has been renamed out of the swapped paths.
+///
+/// On Windows, a file which was just renamed may still be open for a short time by another
+/// process, like an antivirus or an indexing service, which makes the removal fail with a sharing
+/// violation or leave the directory partially removed. Since the removal is needed to finish the
+/// swap, it is retried on transient errors even without `SwapOptions::retries`.
+pub(crate) fn remove_leftover<F>(fs: &F, path: &Path) -> io::Result<()> where F: FileSystem {
+	Retry::new(LEFTOVER_RETRIES, Duration::from_millis(10), platform::is_transient).run(|| remove_path(fs, path))
+}
+
+fn parent_dir(path: &Path) -> io::Result<&Path> {
+	path.parent().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))
+}
+
+/// Swaps `a` and `b` by copying them, used when they are on different filesystems.
+///
+/// Each path is first copied next to the other one. Then, on each filesystem, the original is
+/// renamed to a backup and the copy is renamed in its place. If a copy or a rename fails, the
+/// original state is restored. Backups are removed once both paths are swapped.
+///
+/// Files are cloned when the filesystems support it. Returns what had to be copied byte by byte.
+///
+/// If the cancellation flag of `control` is set before the copies are complete, they are removed
+/// and `SwapError::Cancelled` is returned. The paths are never modified then.
+pub(crate) fn swap_copied<F>(fs: &F, a: &Path, b: &Path, control: &mut CopyControl) -> Result<CopyStats, SwapError> where F: FileSystem {
+	let dir_a = parent_dir(a)?;
+	let dir_b = parent_dir(b)?;
+	let copy_of_b = tmp_path_in_dir(dir_a)?;
+	let backup_of_a = tmp_path_in_dir(dir_a)?;
+	let copy_of_a = tmp_path_in_dir(dir_b)?;
+	let backup_of_b = tmp_path_in_dir(dir_b)?;
+
+	control.add_total(a).and_then(|_| control.add_total(b)).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+
+	let mut stats = CopyStats::default();
+	copy_to_tmp(fs, a, &copy_of_a, &mut stats, control).map_err(|err| control.unmodified_error(err, a, b))?;
+	if let Err(err) = copy_to_tmp(fs, b, &copy_of_b, &mut stats, control) {
+		let _ = remove_path(fs, &copy_of_a);
+		return Err(control.unmodified_error(err, a, b));
+	}
+
+	match rename_all(fs, &[(a, &backup_of_a), (&copy_of_b, a), (b, &backup_of_b), (&copy_of_a, b)]) {
+		Ok(_) => (),
+		// copies may be the only remaining content of the paths
+		Err(err @ SwapError::Corrupted { .. }) => return Err(err),
+		Err(err) => {
+			let _ = remove_path(fs, &copy_of_a);
+			let _ = remove_path(fs, &copy_of_b);
+			return Err(match err {
+				SwapError::Io(err) => SwapError::from_unmodified(err, a, b),
+				err => err,
+			});
+		},
+	}
+
+	for backup in &[backup_of_a, backup_of_b] {
+		if let Err(err) = remove_leftover(fs, backup) {
+			swap_log!(warn, "Removing backup failed"; path = ?backup, error = %err);
+		}
+	}
+
+	Ok(stats)
+}
+
+/// Moves `from` to `to`, which must not exist, by copying it, used when they are on different
+/// filesystems.
+///
+/// The copy is made in a temp path next to `to` and renamed in place once it's complete, unless
+/// the cancellation flag of `control` is set before. If `to` was created meanwhile, the copy is
+/// removed and an `AlreadyExists` error is returned. `from` is removed afterwards.
+pub(crate) fn move_copied(from: &Path, to: &Path, control: &mut CopyControl) -> io::Result<CopyStats> {
+	let tmp = tmp_path_in_dir(parent_dir(to)?)?;
+	control.add_total(from)?;
+	let mut stats = CopyStats::default();
+	copy_to_tmp(&StdFs, from, &tmp, &mut stats, control)?;
+	if let Err(err) = StdFs.rename_noreplace(&tmp, to) {
+		let _ = remove_path(&StdFs, &tmp);
+		return Err(err);
+	}
+
+	if let Err(err) = remove_leftover(&StdFs, from) {
+		swap_log!(warn, "Removing moved path failed"; path = ?from, error = %err);
+	}
+	Ok(stats)
+}
@@ -0,0 +1,180 @@
+use std::{error, fmt, io};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use platform;
+
+/// One of the swapped paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+	/// The first path, `a`.
+	A,
+	/// The second path, `b`.
+	B,
+}
+
+/// State of the paths left by a swap which failed and could not be rolled back, describing what
+/// has to be moved to finish or revert it manually.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorruptedState {
+	/// Paths whose original content was left at another path, with the path now holding it.
+	pub moved: Vec<(PathBuf, PathBuf)>,
+	/// Temp path used by the swap.
+	pub temp_path: Option<PathBuf>,
+	/// Rename `(from, to)` whose failure made the swap fail.
+	pub failed_rename: Option<(PathBuf, PathBuf)>,
+	/// Rename `(from, to)` whose failure made the rollback fail.
+	pub failed_revert: Option<(PathBuf, PathBuf)>,
+}
+
+/// Error returned when paths could not be swapped.
+#[derive(Debug)]
+pub enum SwapError {
+	/// Paths are on different filesystems and cannot be swapped atomically.
+	CrossDevice,
+	/// The platform or the filesystem does not support atomic swaps, and a nonatomic swap was not
+	/// allowed. Nothing was modified.
+	AtomicUnsupported,
+	/// One of the paths does not exist. Nothing was modified.
+	NotFound {
+		/// Path which does not exist.
+		which: Side,
+	},
+	/// One of the paths is a directory and the other is not. Nothing was modified.
+	TypeMismatch,
+	/// The swap failed after modifying the paths, but their original state was restored.
+	RolledBack(io::Error),
+	/// The swap failed and the original state could not be restored.
+	Corrupted {
+		/// Error which made the swap fail.
+		error: io::Error,
+		/// Error which made the rollback fail.
+		rollback_error: io::Error,
+		/// Where the content of the paths was left.
+		state: Box<CorruptedState>,
+	},
+	/// The swap was cancelled with `SwapOptions::cancel_flag`. Nothing was modified.
+	Cancelled,
+	/// The swap did not complete within `SwapOptions::timeout`. It may still be running in the
+	/// background, so the paths may be modified afterwards.
+	TimedOut,
+	/// The paths were swapped, or the destination replaced, but a step following it failed, like
+	/// restoring permissions or syncing the paths with `SwapOptions::durable`. The paths are left
+	/// swapped.
+	Swapped(io::Error),
+	/// Any other I/O error, usually returned before the paths were modified.
+	Io(io::Error),
+}
+
+impl SwapError {
+	/// Returns the corresponding `io::ErrorKind` for this error.
+	pub fn kind(&self) -> io::ErrorKind {
+		match *self {
+			SwapError::CrossDevice | SwapError::AtomicUnsupported => io::ErrorKind::Other,
+			SwapError::NotFound { .. } => io::ErrorKind::NotFound,
+			SwapError::TypeMismatch => io::ErrorKind::InvalidInput,
+			SwapError::Cancelled => io::ErrorKind::Interrupted,
+			SwapError::TimedOut => io::ErrorKind::TimedOut,
+			SwapError::RolledBack(ref err) | SwapError::Swapped(ref err) | SwapError::Io(ref err) => err.kind(),
+			SwapError::Corrupted { ref error, .. } => error.kind(),
+		}
+	}
+
+	/// Returns true if the paths are known to be left as they were before the swap, because it
+	/// failed before modifying them or was rolled back, so that it can be retried.
+	///
+	/// `SwapError::Corrupted` needs the paths to be restored manually, and `SwapError::TimedOut`
+	/// may still modify them. `SwapError::Swapped` leaves the paths swapped, and `SwapError::Io`
+	/// is not known to leave them unmodified.
+	pub fn is_unmodified(&self) -> bool {
+		match *self {
+			SwapError::CrossDevice | SwapError::AtomicUnsupported | SwapError::NotFound { .. } | SwapError::TypeMismatch | SwapError::RolledBack(_) | SwapError::Cancelled => true,
+			SwapError::Corrupted { .. } | SwapError::TimedOut | SwapError::Swapped(_) | SwapError::Io(_) => false,
+		}
+	}
+
+	/// Converts an error returned for paths `a` and `b` before any of them was modified, unless it
+	/// holds a `SwapError`, which is returned as is.
+	///
+	/// On unix, an `EPERM` caused by the sticky bit of a parent directory is replaced with an
+	/// error explaining it.
+	pub(crate) fn from_unmodified(err: io::Error, a: &Path, b: &Path) -> Self {
+		// a platform swap which failed to revert its moves returns `SwapError::Corrupted` in an
+		// `io::Error`
+		if err.get_ref().is_some_and(|inner| inner.is::<SwapError>()) {
+			let inner = err.into_inner().expect("the error has an inner error; qed");
+			return *inner.downcast::<SwapError>().expect("the inner error is a SwapError; qed");
+		}
+
+		if err.kind() != io::ErrorKind::NotFound {
+			#[cfg(unix)]
+			let err = platform::explain_sticky(err, a, b);
+			return SwapError::Io(err);
+		}
+
+		if a.symlink_metadata().is_err() {
+			SwapError::NotFound { which: Side::A }
+		} else if b.symlink_metadata().is_err() {
+			SwapError::NotFound { which: Side::B }
+		} else {
+			SwapError::Io(err)
+		}
+	}
+
+	/// Converts an error returned for paths `a` and `b` once their modifications were reverted,
+	/// like `from_unmodified` but returning `SwapError::RolledBack` for other errors.
+	pub(crate) fn from_rolled_back(err: io::Error, a: &Path, b: &Path) -> Self {
+		match SwapError::from_unmodified(err, a, b) {
+			SwapError::Io(err) => SwapError::RolledBack(err),
+			err => err,
+		}
+	}
+}
+
+impl fmt::Display for SwapError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SwapError::CrossDevice => write!(f, "Paths are on different filesystems"),
+			SwapError::AtomicUnsupported => write!(f, "Atomic swaps are not supported"),
+			SwapError::NotFound { which: Side::A } => write!(f, "Path `a` does not exist"),
+			SwapError::NotFound { which: Side::B } => write!(f, "Path `b` does not exist"),
+			SwapError::TypeMismatch => write!(f, "Cannot swap a directory with a path which is not a directory"),
+			SwapError::RolledBack(ref err) => write!(f, "Swap failed and was rolled back: {}", err),
+			SwapError::Corrupted { ref error, ref rollback_error, ref state } => {
+				write!(f, "Swap failed: {}, rollback failed: {}", error, rollback_error)?;
+				for (original, current) in &state.moved {
+					write!(f, ", content of {:?} is in {:?}", original, current)?;
+				}
+				Ok(())
+			},
+			SwapError::Cancelled => write!(f, "Swap was cancelled"),
+			SwapError::TimedOut => write!(f, "Swap timed out"),
+			SwapError::Swapped(ref err) => write!(f, "Paths were swapped, but a following step failed: {}", err),
+			SwapError::Io(ref err) => write!(f, "{}", err),
+		}
+	}
+}
+
+impl error::Error for SwapError {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match *self {
+			SwapError::CrossDevice | SwapError::AtomicUnsupported | SwapError::NotFound { .. } | SwapError::TypeMismatch | SwapError::Cancelled | SwapError::TimedOut => None,
+			SwapError::RolledBack(ref err) | SwapError::Swapped(ref err) | SwapError::Io(ref err) => Some(err),
+			SwapError::Corrupted { ref error, .. } => Some(error),
+		}
+	}
+}
+
+impl From<io::Error> for SwapError {
+	fn from(err: io::Error) -> Self {
+		SwapError::Io(err)
+	}
+}
+
+impl From<SwapError> for io::Error {
+	fn from(err: SwapError) -> Self {
+		match err {
+			SwapError::Io(err) => err,
+			err => io::Error::new(err.kind(), err),
+		}
+	}
+}
@@ -0,0 +1,174 @@
+//! Swaps which avoid resolving the same paths multiple times.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use error::{Side, SwapError};
+use {platform, swap, validate};
+
+/// Swaps the directory entries of open files `a` and `b` with `swap`.
+///
+/// The paths are looked up from the file descriptors right before the swap, so the files are
+/// swapped even if they were renamed since they were opened. Unlike with the path based
+/// functions, a path which was replaced by another file after it was opened is not swapped. The
+/// lookup and the swap are still separate syscalls, so a rename happening in between is not
+/// detected.
+///
+/// Available on Linux, with `/proc/self/fd`, and on macOS, with `fcntl(F_GETPATH)`.
+pub fn swap_fds(a: &File, b: &File) -> Result<(), SwapError> {
+	let a = platform::fd_path(a)?;
+	let b = platform::fd_path(b)?;
+	swap(a, b)
+}
+
+/// Swaps the content of paths `a` and `b` atomically, without following a symlink in the last
+/// component of their parent directories.
+///
+/// The parents are opened once, with `O_NOFOLLOW`, and the paths are swapped relative to the
+/// opened directories, so a parent replaced after it was opened is not used. The entries are
+/// also checked with `fstatat` relative to them. Other components of the parents are still
+/// resolved normally.
+///
+/// There is no nonatomic fallback, since it would resolve the paths again. Returns
+/// `SwapError::AtomicUnsupported` if the filesystem does not support atomic swaps.
+///
+/// Available on Linux and macOS.
+pub fn swap_hardened<A, B>(a: A, b: B) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	swap_span!("swap_hardened", a, b);
+
+	validate::check_path(a)?;
+	validate::check_path(b)?;
+	let parent_a = platform::ParentDir::open(a, true).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	let parent_b = platform::ParentDir::open(b, true).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+
+	// the entries are read relative to the opened parents, which the swap uses too
+	let stat_a = stat(&parent_a, Side::A)?;
+	let stat_b = stat(&parent_b, Side::B)?;
+	if stat_a.is_dir != stat_b.is_dir {
+		return Err(SwapError::TypeMismatch);
+	}
+	if stat_a.dev == stat_b.dev && stat_a.ino == stat_b.ino {
+		return Ok(());
+	}
+	if stat_a.is_dir {
+		validate::check_nested(a, b)?;
+	}
+
+	match platform::swap_parents(&parent_a, &parent_b) {
+		Ok(_) => Ok(()),
+		Err(ref err) if platform::is_cross_device(err) => Err(SwapError::CrossDevice),
+		Err(ref err) if platform::is_atomic_unsupported(err) => Err(SwapError::AtomicUnsupported),
+		Err(err) => Err(SwapError::from_unmodified(err, a, b)),
+	}
+}
+
+/// Reads the metadata of the entry of `parent`, located on `side`.
+fn stat(parent: &platform::ParentDir, side: Side) -> Result<platform::EntryStat, SwapError> {
+	parent.stat().map_err(|err| match err.kind() {
+		io::ErrorKind::NotFound => SwapError::NotFound { which: side },
+		_ => SwapError::Io(err),
+	})
+}
+
+/// Directory opened once, whose entries are swapped atomically relative to it.
+///
+/// Swapping many entries of the same directory this way does not resolve and open the directory
+/// for each swap, and keeps using the same directory even if its path is replaced afterwards.
+///
+/// Available on Linux and macOS.
+#[derive(Debug)]
+pub struct DirSwapper {
+	dir: File,
+	path: PathBuf,
+}
+
+impl DirSwapper {
+	/// Opens directory `path`.
+	pub fn open<P>(path: P) -> Result<Self, SwapError> where P: AsRef<Path> {
+		let path = path.as_ref();
+		Ok(DirSwapper {
+			dir: platform::open_dir(path, false)?,
+			path: path.to_path_buf(),
+		})
+	}
+
+	/// Returns the path the directory was opened with.
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Swaps entries `a` and `b` of the directory atomically, `a` and `b` being file names.
+	///
+	/// There is no nonatomic fallback. Returns `SwapError::AtomicUnsupported` if the filesystem
+	/// does not support atomic swaps.
+	pub fn swap<A, B>(&self, a: A, b: B) -> Result<(), SwapError> where A: AsRef<OsStr>, B: AsRef<OsStr> {
+		let a = a.as_ref();
+		let b = b.as_ref();
+		swap_span!("dir_swap", self.path.join(a), self.path.join(b));
+
+		check_name(a)?;
+		check_name(b)?;
+		if a == b {
+			return Ok(());
+		}
+
+		match platform::swap_in_dir(&self.dir, a, b) {
+			Ok(_) => Ok(()),
+			Err(ref err) if platform::is_atomic_unsupported(err) => Err(SwapError::AtomicUnsupported),
+			Err(err) => Err(SwapError::from_unmodified(err, &self.path.join(a), &self.path.join(b))),
+		}
+	}
+}
+
+/// Swaps entry `a` of open directory `dir_a` and entry `b` of open directory `dir_b` atomically,
+/// `a` and `b` being file names.
+///
+/// Neither parent is resolved again, so the swap is not affected by their paths being replaced
+/// after they were opened. The directories must be on the same filesystem, otherwise an
+/// `Unsupported` error is returned. There is no nonatomic fallback. Returns
+/// `SwapError::AtomicUnsupported` if the filesystem does not support atomic swaps.
+///
+/// Available on Linux and macOS.
+pub fn swap_at<A, B>(dir_a: &File, a: A, dir_b: &File, b: B) -> Result<(), SwapError> where A: AsRef<OsStr>, B: AsRef<OsStr> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	let path_a = entry_path(dir_a, a);
+	let path_b = entry_path(dir_b, b);
+	swap_span!("swap_at", path_a, path_b);
+
+	check_name(a)?;
+	check_name(b)?;
+	let meta_a = dir_a.metadata()?;
+	let meta_b = dir_b.metadata()?;
+	if meta_a.dev() != meta_b.dev() {
+		return Err(io::Error::new(io::ErrorKind::Unsupported, "Directories are on different filesystems").into());
+	}
+	if meta_a.ino() == meta_b.ino() && a == b {
+		return Ok(());
+	}
+
+	match platform::swap_in_dirs(dir_a, a, dir_b, b) {
+		Ok(_) => Ok(()),
+		Err(ref err) if platform::is_cross_device(err) => Err(SwapError::CrossDevice),
+		Err(ref err) if platform::is_atomic_unsupported(err) => Err(SwapError::AtomicUnsupported),
+		Err(err) => Err(SwapError::from_unmodified(err, &path_a, &path_b)),
+	}
+}
+
+/// Returns the path of entry `name` of open directory `dir`, used in logs and errors.
+fn entry_path(dir: &File, name: &OsStr) -> PathBuf {
+	platform::fd_path(dir).unwrap_or_default().join(name)
+}
+
+/// Rejects names which are not a single component, which would not be relative to the directory.
+fn check_name(name: &OsStr) -> Result<(), SwapError> {
+	let mut components = Path::new(name).components();
+	match (components.next(), components.next()) {
+		(Some(Component::Normal(component)), None) if component == name => Ok(()),
+		_ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Name must be a single path component").into()),
+	}
+}
@@ -0,0 +1,162 @@
+//! Filesystem operations used by the nonatomic swaps, so that tests can make them fail.
+
+#[cfg(any(test, feature = "testing"))]
+use std::cell::RefCell;
+use std::{fs, io};
+use std::path::Path;
+use platform;
+
+/// Operations which move or remove the swapped paths.
+pub(crate) trait FileSystem {
+	/// Renames `from` to `to`, like `fs::rename`.
+	fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+	/// Renames `from` to `to`, failing with `AlreadyExists` if `to` exists, even as a symlink.
+	fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+	/// Returns the metadata of `path` without following symlinks, like `fs::symlink_metadata`.
+	fn metadata(&self, path: &Path) -> io::Result<fs::Metadata>;
+
+	/// Creates `to` as a hardlink to `from`, like `fs::hard_link`.
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+	/// Removes file or symlink `path`, like `fs::remove_file`. On Windows, symlinks to directories
+	/// and junctions are removed as well.
+	fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+	/// Removes directory `path` with its content, like `fs::remove_dir_all`.
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+}
+
+/// The filesystem of `std::fs`.
+#[derive(Clone, Copy)]
+pub(crate) struct StdFs;
+
+impl FileSystem for StdFs {
+	fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+		fs::rename(from, to)
+	}
+
+	fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+		match platform::rename_noreplace(from, to) {
+			Err(ref err) if platform::is_atomic_unsupported(err) => rename_exclusive(from, to),
+			result => result,
+		}
+	}
+
+	fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+		fs::symlink_metadata(path)
+	}
+
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+		fs::hard_link(from, to)
+	}
+
+	fn remove_file(&self, path: &Path) -> io::Result<()> {
+		#[cfg(windows)]
+		{
+			if platform::is_dir_link(&fs::symlink_metadata(path)?) {
+				return fs::remove_dir(path);
+			}
+		}
+		fs::remove_file(path)
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+		fs::remove_dir_all(path)
+	}
+}
+
+/// Renames `from` to `to` on a filesystem which cannot rename without replacing.
+///
+/// Files and symlinks are linked to `to`, which fails if it exists, and unlinked from `from`.
+/// Directories cannot be linked, so `to` is checked just before the rename, which fails anyway if
+/// `to` is created meanwhile, unless it is an empty directory.
+pub(crate) fn rename_exclusive(from: &Path, to: &Path) -> io::Result<()> {
+	if !fs::symlink_metadata(from)?.is_dir() {
+		match fs::hard_link(from, to) {
+			Ok(()) => {
+				return fs::remove_file(from).or_else(|err| {
+					fs::remove_file(to)?;
+					Err(err)
+				});
+			},
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+				return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Target path already exists"));
+			},
+			// hardlinks are not supported by every filesystem
+			Err(_) => (),
+		}
+	}
+
+	if fs::symlink_metadata(to).is_ok() {
+		return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Target path already exists"));
+	}
+	fs::rename(from, to)
+}
+
+/// Filesystem whose renames fail or succeed in the planned order. Renames past the end of the
+/// plan succeed.
+#[cfg(any(test, feature = "testing"))]
+pub(crate) struct FailingFs {
+	renames: RefCell<Vec<bool>>,
+	os_error: Option<i32>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl FailingFs {
+	pub(crate) fn new(renames: &[bool]) -> Self {
+		FailingFs { renames: RefCell::new(renames.to_vec()), os_error: None }
+	}
+
+	/// Like `new`, but failing renames return OS error `code`, like a real rename would.
+	#[cfg(all(test, unix))]
+	pub(crate) fn with_os_error(renames: &[bool], code: i32) -> Self {
+		FailingFs { renames: RefCell::new(renames.to_vec()), os_error: Some(code) }
+	}
+
+	/// Returns the error of the next rename if it has to fail.
+	fn failure(&self) -> Option<io::Error> {
+		let mut renames = self.renames.borrow_mut();
+		if renames.is_empty() || !renames.remove(0) {
+			return None;
+		}
+		Some(match self.os_error {
+			Some(code) => io::Error::from_raw_os_error(code),
+			None => io::Error::other("injected rename failure"),
+		})
+	}
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl FileSystem for FailingFs {
+	fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+		if let Some(err) = self.failure() {
+			return Err(err);
+		}
+		StdFs.rename(from, to)
+	}
+
+	fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+		if let Some(err) = self.failure() {
+			return Err(err);
+		}
+		StdFs.rename_noreplace(from, to)
+	}
+
+	fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+		StdFs.metadata(path)
+	}
+
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+		StdFs.hard_link(from, to)
+	}
+
+	fn remove_file(&self, path: &Path) -> io::Result<()> {
+		StdFs.remove_file(path)
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+		StdFs.remove_dir_all(path)
+	}
+}
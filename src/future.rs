@@ -0,0 +1,78 @@
+//! Asynchronous swaps, available with the `async` feature.
+//!
+//! The swaps are performed by the synchronous functions on the blocking thread pool of tokio.
+//! Dropping a returned future does not cancel a swap which has already started, it is still
+//! completed in the background.
+
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{io, panic};
+use tokio::task::{self, JoinHandle};
+use error::SwapError;
+use {swap, swap_nonatomic};
+
+type Task<T> = Box<dyn FnOnce() -> Result<T, SwapError> + Send>;
+
+enum State<T> {
+	Idle(Option<Task<T>>),
+	Running(JoinHandle<Result<T, SwapError>>),
+}
+
+/// Future returned by asynchronous swaps.
+///
+/// Like an `async fn`, the swap starts only when the future is first polled.
+pub struct SwapFuture<T> {
+	state: State<T>,
+}
+
+impl<T> Future for SwapFuture<T> where T: Send + 'static {
+	type Output = Result<T, SwapError>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let this = self.get_mut();
+		if let State::Idle(ref mut task) = this.state {
+			let task = task.take().expect("state is replaced once the task is taken; qed");
+			this.state = State::Running(task::spawn_blocking(task));
+		}
+
+		let handle = match this.state {
+			State::Running(ref mut handle) => handle,
+			State::Idle(_) => unreachable!(),
+		};
+
+		match Pin::new(handle).poll(cx) {
+			Poll::Pending => Poll::Pending,
+			Poll::Ready(Ok(result)) => Poll::Ready(result),
+			Poll::Ready(Err(err)) => match err.try_into_panic() {
+				Ok(payload) => panic::resume_unwind(payload),
+				Err(err) => Poll::Ready(Err(SwapError::Io(io::Error::other(err)))),
+			},
+		}
+	}
+}
+
+fn spawn<T, F>(f: F) -> SwapFuture<T> where F: FnOnce() -> Result<T, SwapError> + Send + 'static {
+	SwapFuture {
+		state: State::Idle(Some(Box::new(f))),
+	}
+}
+
+/// Asynchronous version of `swap`.
+///
+/// The returned future must be polled within a tokio runtime.
+pub fn swap_async<A, B>(a: A, b: B) -> SwapFuture<()> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref().to_path_buf();
+	let b = b.as_ref().to_path_buf();
+	spawn(move || swap(a, b))
+}
+
+/// Asynchronous version of `swap_nonatomic`.
+///
+/// The returned future must be polled within a tokio runtime.
+pub fn swap_nonatomic_async<A, B>(a: A, b: B) -> SwapFuture<()> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref().to_path_buf();
+	let b = b.as_ref().to_path_buf();
+	spawn(move || swap_nonatomic(a, b))
+}
@@ -0,0 +1,87 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use error::{CorruptedState, SwapError};
+use swap;
+
+/// Guard returned by `swap_guarded`, which swaps the paths back when dropped.
+#[derive(Debug)]
+#[must_use = "the paths are swapped back as soon as the guard is dropped"]
+pub struct SwapGuard {
+	a: PathBuf,
+	b: PathBuf,
+	armed: bool,
+}
+
+impl SwapGuard {
+	/// Keeps the paths swapped.
+	pub fn commit(mut self) {
+		self.armed = false;
+	}
+
+	/// Keeps the paths swapped when the guard is dropped.
+	pub fn disarm(&mut self) {
+		self.armed = false;
+	}
+
+	/// Swaps the paths back, returning an error if it fails.
+	pub fn restore(mut self) -> Result<(), SwapError> {
+		self.armed = false;
+		swap(&self.a, &self.b)
+	}
+}
+
+impl Drop for SwapGuard {
+	fn drop(&mut self) {
+		if !self.armed {
+			return;
+		}
+
+		if let Err(err) = swap(&self.a, &self.b) {
+			swap_log!(error, "Swapping back failed"; a = ?self.a, b = ?self.b, error = %err);
+		}
+	}
+}
+
+/// Swaps the content of paths `a` and `b` with `swap`, and swaps them back when the returned
+/// guard is dropped, unless it's committed or disarmed.
+pub fn swap_guarded<A, B>(a: A, b: B) -> Result<SwapGuard, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	swap(a, b)?;
+
+	Ok(SwapGuard {
+		a: a.to_path_buf(),
+		b: b.to_path_buf(),
+		armed: true,
+	})
+}
+
+/// Swaps the content of paths `a` and `b` with `swap`, then runs `f`.
+///
+/// If `f` fails, the paths are swapped back and `SwapError::RolledBack` is returned with the
+/// error of `f`. If swapping back also fails, `SwapError::Corrupted` is returned.
+pub fn swap_then<A, B, F>(a: A, b: B, f: F) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path>, F: FnOnce() -> io::Result<()> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	let guard = swap_guarded(a, b)?;
+	match f() {
+		Ok(_) => {
+			guard.commit();
+			Ok(())
+		},
+		Err(err) => match guard.restore() {
+			Ok(_) => Err(SwapError::RolledBack(err)),
+			Err(rollback_error) => {
+				// the paths are still swapped, unless swapping back left them corrupted as well
+				let state = match rollback_error {
+					SwapError::Corrupted { .. } => CorruptedState::default(),
+					_ => CorruptedState {
+						moved: vec![(a.to_path_buf(), b.to_path_buf()), (b.to_path_buf(), a.to_path_buf())],
+						..CorruptedState::default()
+					},
+				};
+				Err(SwapError::Corrupted { error: err, rollback_error: rollback_error.into(), state: Box::new(state) })
+			},
+		},
+	}
+}
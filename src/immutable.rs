@@ -0,0 +1,113 @@
+//! Immutable and append-only files on Linux, which cannot be renamed, see
+//! `SwapOptions::clear_immutable`.
+
+use std::{fs, io};
+use std::path::Path;
+use error::SwapError;
+use options::SwapOptions;
+#[cfg(target_os = "linux")]
+use platform;
+use validate::Entries;
+
+/// Immutable and append-only flags of the swapped paths, cleared during the swap.
+pub(crate) struct Protected {
+	a: u32,
+	b: u32,
+}
+
+impl Protected {
+	/// Reads the flags of `a` and `b`, and clears them if `options.clear_immutable` is set.
+	/// Otherwise none are read, and a swap failing because of them is explained by `explain`.
+	pub(crate) fn clear(a: &Path, b: &Path, entries: &Entries, options: &SwapOptions) -> Result<Self, SwapError> {
+		if !options.clear_immutable {
+			return Ok(Protected { a: 0, b: 0 });
+		}
+
+		let protected = Protected::read(a, b, entries).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+		set_flags(a, protected.a, 0).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+		if let Err(err) = set_flags(b, protected.b, 0) {
+			let _ = set_flags(a, 0, protected.a);
+			return Err(SwapError::from_unmodified(err, a, b));
+		}
+		Ok(protected)
+	}
+
+	fn read(a: &Path, b: &Path, entries: &Entries) -> io::Result<Self> {
+		Ok(Protected {
+			a: flags(a, &entries.a)?,
+			b: flags(b, &entries.b)?,
+		})
+	}
+
+	/// Returns a `PermissionDenied` error naming the first path with flags, if any.
+	fn reject(&self, a: &Path, b: &Path) -> Option<io::Error> {
+		for &(path, flags) in &[(a, self.a), (b, self.b)] {
+			if flags != 0 {
+				let message = format!("{:?} is immutable or append-only, see `SwapOptions::clear_immutable`", path);
+				return Some(io::Error::new(io::ErrorKind::PermissionDenied, message));
+			}
+		}
+		None
+	}
+
+	/// Sets the flags again once the paths were swapped, so that they follow the content.
+	pub(crate) fn restore_swapped(&self, a: &Path, b: &Path) -> io::Result<()> {
+		set_flags(a, 0, self.b)?;
+		set_flags(b, 0, self.a)
+	}
+
+	/// Sets the flags again on the original paths, after the swap failed without modifying them.
+	pub(crate) fn restore(&self, a: &Path, b: &Path) {
+		for &(path, flags) in &[(a, self.a), (b, self.b)] {
+			if let Err(err) = set_flags(path, 0, flags) {
+				swap_log!(warn, "Restoring immutable flags failed"; path = ?path, error = %err);
+			}
+		}
+	}
+}
+
+/// Replaces `err`, returned by a swap of `a` and `b` which failed without modifying them, with an
+/// error naming `a` or `b` if it was denied because one of them is immutable or append-only.
+///
+/// The flags are only read once a swap failed, so that the other swaps don't pay for it.
+pub(crate) fn explain(err: SwapError, a: &Path, b: &Path, entries: &Entries) -> SwapError {
+	let explained = match err {
+		SwapError::Io(ref err) | SwapError::RolledBack(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+			Protected::read(a, b, entries).ok().and_then(|protected| protected.reject(a, b))
+		},
+		_ => None,
+	};
+	match (explained, err) {
+		(Some(explained), SwapError::RolledBack(_)) => SwapError::RolledBack(explained),
+		(Some(explained), _) => SwapError::Io(explained),
+		(None, err) => err,
+	}
+}
+
+/// Returns the immutable and append-only flags of `path`. Only files and directories have any.
+#[cfg(target_os = "linux")]
+fn flags(path: &Path, metadata: &fs::Metadata) -> io::Result<u32> {
+	if !metadata.is_file() && !metadata.is_dir() {
+		return Ok(0);
+	}
+	platform::protected_flags(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn flags(_path: &Path, _metadata: &fs::Metadata) -> io::Result<u32> {
+	Ok(0)
+}
+
+/// Changes the immutable and append-only flags of `path` from `current` to `flags`.
+#[cfg(target_os = "linux")]
+fn set_flags(path: &Path, current: u32, flags: u32) -> io::Result<()> {
+	if current == flags {
+		return Ok(());
+	}
+	platform::set_protected_flags(path, flags)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_flags(_path: &Path, _current: u32, _flags: u32) -> io::Result<()> {
+	Ok(())
+}
@@ -0,0 +1,125 @@
+//! Swapping the data of files, keeping the files themselves in place.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::Path;
+use error::{CorruptedState, SwapError};
+use {is_same_path, tmp_path_in_dir};
+
+fn check_file(path: &Path, a: &Path, b: &Path) -> Result<(), SwapError> {
+	let metadata = fs::symlink_metadata(path).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	if !metadata.is_file() {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only the data of files can be swapped").into());
+	}
+	Ok(())
+}
+
+/// Replaces the data of open file `to` with the data of file `from` and flushes it to disk.
+pub(crate) fn rewrite(to: &mut File, from: &Path) -> io::Result<()> {
+	to.set_len(0)?;
+	// the handle may be at the end of data written by a previous rewrite
+	to.seek(SeekFrom::Start(0))?;
+	io::copy(&mut File::open(from)?, to)?;
+	to.sync_all()
+}
+
+fn open_for_rewrite(path: &Path) -> io::Result<File> {
+	OpenOptions::new().write(true).open(path)
+}
+
+/// Returns the error of a swap which failed with `err`, `rollback` being the result of the
+/// rewrites restoring the files. The temp file is removed only if they were restored, otherwise
+/// `moved` lists the files whose original data is held by another file.
+fn rolled_back(err: io::Error, rollback: io::Result<()>, moved: &[(&Path, &Path)], tmp: &Path) -> SwapError {
+	swap_metric!("fs_swap.recovery");
+	match rollback {
+		Ok(_) => {
+			let _ = fs::remove_file(tmp);
+			SwapError::RolledBack(err)
+		},
+		Err(rollback_error) => {
+			swap_log!(error, "Restoring the files failed"; moved = ?moved, tmp = ?tmp, error = %rollback_error);
+			let state = CorruptedState {
+				moved: moved.iter().map(|&(original, current)| (original.to_path_buf(), current.to_path_buf())).collect(),
+				temp_path: Some(tmp.to_path_buf()),
+				..CorruptedState::default()
+			};
+			SwapError::Corrupted { error: err, rollback_error, state: Box::new(state) }
+		},
+	}
+}
+
+/// Swaps the data of files `a` and `b` by rewriting them, so that both files keep their inode.
+/// Open handles and watches on the files are not affected, but readers may see partial data
+/// during the swap.
+///
+/// The data of `a` is first copied to a temp file next to `a` and flushed to disk. `a` is then
+/// rewritten with the data of `b`, and `b` with the data of the temp file, each being flushed
+/// before the next step. If a crash happens in the middle, the original data of `a` is kept in
+/// the temp file. If a rewrite fails, the files are rewritten back and `SwapError::RolledBack` is
+/// returned. If this also fails, `SwapError::Corrupted` is returned and the temp file is kept.
+///
+/// Unlike the other swaps, each file is written twice, which may take a long time for large
+/// files. Only files can be swapped this way.
+pub fn swap_contents<A, B>(a: A, b: B) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	swap_span!("swap_contents", a, b);
+	swap_contents_in(a, b, rewrite)
+}
+
+/// Swaps the data of files `a` and `b` like `swap_contents`, each rewrite being done by `rewrite`.
+pub(crate) fn swap_contents_in<R>(a: &Path, b: &Path, mut rewrite: R) -> Result<(), SwapError> where R: FnMut(&mut File, &Path) -> io::Result<()> {
+	if is_same_path(a, b) {
+		return Ok(());
+	}
+
+	check_file(a, a, b)?;
+	check_file(b, a, b)?;
+
+	let parent_dir = a.parent()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))?;
+	let tmp = tmp_path_in_dir(parent_dir)?;
+
+	// copy a to tmp
+	// if it fails, the files are unchanged
+	let backup = OpenOptions::new().write(true).create_new(true).open(&tmp)
+		.and_then(|mut backup| rewrite(&mut backup, a));
+	if let Err(err) = backup {
+		let _ = fs::remove_file(&tmp);
+		return Err(SwapError::from_unmodified(err, a, b));
+	}
+
+	let files = open_for_rewrite(a).and_then(|file_a| Ok((file_a, open_for_rewrite(b)?)));
+	let (mut file_a, mut file_b) = match files {
+		Ok(files) => files,
+		Err(err) => {
+			let _ = fs::remove_file(&tmp);
+			return Err(SwapError::from_unmodified(err, a, b));
+		},
+	};
+
+	// rewrite a with b
+	// if it fails, only a was modified, so rewriting it with tmp restores the previous state
+	if let Err(err) = rewrite(&mut file_a, b) {
+		swap_log!(warn, "Rewriting a failed, restoring a"; a = ?a, b = ?b, tmp = ?tmp, error = %err);
+		return Err(rolled_back(err, rewrite(&mut file_a, &tmp), &[(a, &tmp)], &tmp));
+	}
+
+	// rewrite b with tmp
+	// if it fails, a holds the data of b and tmp the data of a, so b is rewritten with a first
+	// and then a with tmp
+	if let Err(err) = rewrite(&mut file_b, &tmp) {
+		swap_log!(warn, "Rewriting b failed, restoring a and b"; a = ?a, b = ?b, tmp = ?tmp, error = %err);
+		// until b is restored, a holds the only copy of the data of b
+		if let Err(rollback_error) = rewrite(&mut file_b, a) {
+			return Err(rolled_back(err, Err(rollback_error), &[(a, &tmp), (b, a)], &tmp));
+		}
+		return Err(rolled_back(err, rewrite(&mut file_a, &tmp), &[(a, &tmp)], &tmp));
+	}
+
+	if let Err(err) = fs::remove_file(&tmp) {
+		swap_log!(warn, "Removing temp file failed"; path = ?tmp, error = %err);
+	}
+	Ok(())
+}
@@ -1,77 +1,1000 @@
 //! Cross-platform implementation of path swap.
 
+extern crate filetime;
 #[cfg(target_os = "macos")]
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(all(feature = "logging", not(feature = "tracing")))]
+extern crate log;
+#[cfg(feature = "metrics")]
+extern crate metrics;
 
+#[macro_use]
+mod macros;
+
+mod backup;
+mod batch;
+mod capabilities;
+mod clock;
+mod compare;
+mod contents;
+mod copy;
+mod error;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod fd;
+mod filesystem;
+#[cfg(feature = "async")]
+mod future;
+mod guard;
+mod immutable;
+mod inplace;
+mod lock;
+mod moving;
+mod options;
+mod overwrite;
+mod pending;
+mod plan;
 mod platform;
+mod preserve;
+mod readonly;
+mod replace;
+mod rollback;
+mod retry;
+mod rotate;
+mod snapshot;
+mod timeout;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod validate;
+
+pub use backup::{BackupHandle, swap_with_backup};
+pub use batch::{BatchOutcome, PairOutcome, swap_many, swap_many_with};
+pub use capabilities::{Capabilities, capabilities};
+pub use compare::swap_if_different;
+pub use contents::{DedupReport, swap_dir_contents, swap_dir_contents_dedup};
+pub use error::{CorruptedState, SwapError, Side};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub use fd::{DirSwapper, swap_at, swap_fds, swap_hardened};
+#[cfg(feature = "async")]
+pub use future::{SwapFuture, swap_async, swap_nonatomic_async};
+pub use guard::{SwapGuard, swap_guarded, swap_then};
+pub use inplace::swap_contents;
+pub use moving::{MoveOrSwap, move_or_swap};
+pub use options::{SwapOptions, TempStrategy};
+pub use pending::PendingSwap;
+pub use plan::{SwapPlan, plan, plan_with};
+pub use replace::{replace, replace_with};
+pub use rotate::{swap3, rotate, promote};
+pub use snapshot::{PathState, Snapshot};
+
+use std::borrow::Cow;
+use std::fmt::Write as FmtWrite;
+use std::{env, fmt, fs, io, process};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use filesystem::{FileSystem, StdFs};
+use options::TempName;
+use retry::{Retry, RetryingFs};
+use rollback::Renames;
+
+/// Strategy that was used to swap the paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapKind {
+	/// Paths were swapped atomically.
+	Atomic,
+	/// Paths were swapped with `swap_nonatomic`.
+	Nonatomic,
+	/// Files were swapped with a temp hardlink, so that `a` was never missing.
+	Linked,
+	/// Paths were on different filesystems and were swapped by copying them.
+	Copied {
+		/// `true` if there were files to copy and all of them were cloned (reflinked) instead of
+		/// being copied byte by byte. Files can only be cloned within a filesystem, so this
+		/// happens with two bind mounts of one filesystem on Linux 5.18 and later.
+		reflink: bool,
+	},
+	/// One path was on a read-only filesystem and the other path was overwritten with a copy of
+	/// its content, see `SwapOptions::overwrite_from_read_only`.
+	Overwritten {
+		/// The read-only path, whose content was copied.
+		source: Side,
+	},
+}
+
+/// Reason why the atomic swap of the platform was not used, see `SwapReport::fallback`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackReason {
+	/// Atomic swaps were disabled with `SwapOptions::atomic(false)`.
+	Disabled,
+	/// The paths are on different filesystems.
+	CrossDevice {
+		/// Error returned by the atomic swap or the rename, like `EXDEV` on unix.
+		error: String,
+	},
+	/// The platform, the kernel or the filesystem does not support atomic swaps.
+	AtomicUnsupported {
+		/// Error returned by the atomic swap, like `ENOSYS` or `EINVAL` from `renameat2` on Linux.
+		error: String,
+	},
+}
+
+impl FallbackReason {
+	fn cross_device(err: &io::Error) -> Self {
+		FallbackReason::CrossDevice { error: err.to_string() }
+	}
+
+	fn atomic_unsupported(err: &io::Error) -> Self {
+		FallbackReason::AtomicUnsupported { error: err.to_string() }
+	}
+}
+
+impl fmt::Display for FallbackReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			FallbackReason::Disabled => write!(f, "atomic swaps disabled by the options"),
+			FallbackReason::CrossDevice { ref error } => write!(f, "paths on different filesystems: {}", error),
+			FallbackReason::AtomicUnsupported { ref error } => write!(f, "atomic swaps unsupported: {}", error),
+		}
+	}
+}
 
-use std::{fs, io};
-use std::path::Path;
+/// Description of a completed swap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapReport {
+	/// Strategy that was used to swap the paths.
+	pub kind: SwapKind,
+	/// Number of bytes copied by a swap of paths on different filesystems. Cloned files are not
+	/// counted.
+	pub bytes_copied: u64,
+	/// Number of sparse files copied by a swap of paths on different filesystems whose holes were
+	/// preserved, on Linux and Windows. Other copied files were dense or lost their holes.
+	pub sparse_files: usize,
+	/// Number of times the operations of the swap were retried after a transient error, see
+	/// `SwapOptions::retries`.
+	pub retries: u32,
+	/// Reason why the paths were not swapped atomically, or `None` if they were, or if a missing
+	/// path was replaced with a single rename.
+	pub fallback: Option<FallbackReason>,
+	/// Temp path used by a nonatomic swap of paths on the same filesystem.
+	pub temp_path: Option<PathBuf>,
+	/// States of the paths before the swap.
+	pub before: Snapshot,
+	/// States of the paths after the swap, recorded only by `swap_verbose`.
+	pub after: Snapshot,
+}
+
+impl SwapReport {
+	fn new(kind: SwapKind) -> Self {
+		SwapReport {
+			kind,
+			bytes_copied: 0,
+			sparse_files: 0,
+			retries: 0,
+			fallback: None,
+			temp_path: None,
+			before: Snapshot::default(),
+			after: Snapshot::default(),
+		}
+	}
+
+	fn atomic() -> Self {
+		SwapReport::new(SwapKind::Atomic)
+	}
+
+	fn copied(stats: &copy::CopyStats) -> Self {
+		SwapReport {
+			bytes_copied: stats.copied_bytes,
+			sparse_files: stats.sparse_files,
+			..SwapReport::new(SwapKind::Copied { reflink: stats.reflinked_files > 0 && stats.copied_files == 0 })
+		}
+	}
+}
 
 /// Swaps the content of paths `a` and `b`.
-pub fn swap<A, B>(a: A, b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
-	platform::swap(a, b)
+///
+/// If the platform supports atomic swaps, but the kernel or the filesystem does not, the paths
+/// are swapped with `swap_nonatomic`. WASI has no atomic exchange, so paths are always swapped
+/// nonatomically there, like on the other platforms without a specialized implementation, such as
+/// the BSDs.
+///
+/// Relative paths are resolved against the current directory before anything else, so a relative
+/// and an absolute path can be mixed, and trailing separators are ignored.
+///
+/// A directory cannot be swapped with a path inside it, which fails with an `InvalidInput` error.
+///
+/// Paths sharing their device and inode, like two hardlinks to a file or a directory reached
+/// through two bind mounts on Linux, are the same file, and are left as they are. Distinct paths
+/// in two bind mounts also share the device of their filesystem, but cannot be renamed from one
+/// mount to the other, so they fail with `SwapError::CrossDevice`, and `swap_auto` copies them.
+pub fn swap<A, B>(a: A, b: B) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = &*validate::resolve(a.as_ref())?;
+	let b = &*validate::resolve(b.as_ref())?;
+	swap_span!("swap", a, b);
+
+	if is_same_path(a, b) {
+		return Ok(());
+	}
+
+	let entries = validate::check(a, b)?;
+	if entries.same_file {
+		return Ok(());
+	}
+
+	readonly::cleared(a, b, || match platform::swap(a, b) {
+		Ok(_) => {
+			swap_metric!("fs_swap.atomic");
+			Ok(())
+		},
+		Err(ref err) if platform::is_atomic_unsupported(err) => {
+			swap_log!(debug, "Swapping nonatomically"; a = ?a, b = ?b, reason = %FallbackReason::atomic_unsupported(err));
+			let options = SwapOptions::new();
+			let result = match linked(&StdFs, a, b, &entries, &options) {
+				Some(result) => result,
+				None => nonatomic(&StdFs, a, b, &entries, &options),
+			};
+			result.map(|_| ()).map_err(|err| immutable::explain(err, a, b, &entries))
+		},
+		Err(ref err) if platform::is_cross_device(err) => Err(SwapError::CrossDevice),
+		Err(err) => Err(SwapError::from_unmodified(err, a, b)),
+	})
+}
+
+/// Exchanges paths `a` and `b` in a single atomic syscall, `renameat2(RENAME_EXCHANGE)` on Linux
+/// and `renamex_np(RENAME_SWAP)` on macOS.
+///
+/// Unlike `swap`, there is no fallback and no validation: no temp path is created, nothing is
+/// removed, and the paths are either exchanged or left unchanged. Fails with
+/// `io::ErrorKind::Unsupported` if the platform, the kernel or the filesystem cannot exchange
+/// them, which is always the case on Windows.
+pub fn exchange<A, B>(a: A, b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
+	match platform::exchange(a.as_ref(), b.as_ref()) {
+		Err(ref err) if platform::is_atomic_unsupported(err) => {
+			Err(io::Error::new(io::ErrorKind::Unsupported, "Atomic exchanges are not supported by the filesystem"))
+		},
+		result => result,
+	}
+}
+
+/// Swaps the content of paths `a` and `b` atomically, or nonatomically if they are on
+/// different filesystems or atomic swaps are not supported.
+///
+/// Any other error returned by the atomic swap is not retried.
+pub fn swap_auto<A, B>(a: A, b: B) -> Result<SwapKind, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	swap_with(a, b, &SwapOptions::new())
+}
+
+/// Swaps the content of paths `a` and `b` like `swap_auto`, and flushes them to disk.
+pub fn swap_durable<A, B>(a: A, b: B) -> Result<SwapKind, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	swap_with(a, b, &SwapOptions::new().durable(true))
+}
+
+/// Swaps the content of paths `a` and `b` using given `options`.
+///
+/// Swapping a path with itself, with another hardlink to the same file, or with a spelling of it
+/// differing only by case on a case-insensitive filesystem, does nothing and returns
+/// `SwapKind::Atomic`. Paths are resolved like with `swap`.
+pub fn swap_with<A, B>(a: A, b: B, options: &SwapOptions) -> Result<SwapKind, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = &*validate::resolve(a.as_ref())?;
+	let b = &*validate::resolve(b.as_ref())?;
+	swap_span!("swap_with", a, b);
+	swap_report_with(a, b, options).map(|report| report.kind)
+}
+
+/// Swaps the content of paths `a` and `b` like `swap_auto`, and describes how it was done.
+///
+/// The report includes the sizes and modification times of the paths before and after the swap,
+/// so that a caller can check, for instance, that `a` now has the size `b` used to have.
+pub fn swap_verbose<A, B>(a: A, b: B) -> Result<SwapReport, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = &*validate::resolve(a.as_ref())?;
+	let b = &*validate::resolve(b.as_ref())?;
+	swap_span!("swap_verbose", a, b);
+	let mut report = swap_report_with(a, b, &SwapOptions::new())?;
+	report.after = Snapshot::capture(a, b);
+	Ok(report)
+}
+
+fn swap_report_with(a: &Path, b: &Path, options: &SwapOptions) -> Result<SwapReport, SwapError> {
+	let start = Instant::now();
+	let result = swap_report_unlogged(a, b, options);
+	match result {
+		Ok(ref report) => swap_log!(debug, "Swapped paths"; a = ?a, b = ?b, strategy = ?report.kind, bytes_copied = %report.bytes_copied, retries = %report.retries, elapsed = ?start.elapsed()),
+		Err(ref err) => swap_log!(warn, "Swap failed"; a = ?a, b = ?b, size_a = ?path_size(a), size_b = ?path_size(b), elapsed = ?start.elapsed(), error = %err),
+	}
+	result
+}
+
+/// Returns the size of `path`, or `None` if it cannot be read.
+fn path_size(path: &Path) -> Option<u64> {
+	fs::symlink_metadata(path).ok().map(|metadata| metadata.len())
+}
+
+fn swap_report_unlogged(a: &Path, b: &Path, options: &SwapOptions) -> Result<SwapReport, SwapError> {
+	swap_report_in(&StdFs, a, b, options)
+}
+
+/// Swaps `a` and `b` like `swap_report_unlogged`, moving them with `fs` if they are swapped
+/// nonatomically.
+fn swap_report_in<F>(fs: &F, a: &Path, b: &Path, options: &SwapOptions) -> Result<SwapReport, SwapError> where F: FileSystem + Clone + Send + 'static {
+	if let Some(limit) = options.timeout {
+		let (fs, a, b) = (fs.clone(), a.to_path_buf(), b.to_path_buf());
+		let options = SwapOptions { timeout: None, ..options.clone() };
+		return timeout::run(limit, move || swap_report_in(&fs, &a, &b, &options));
+	}
+
+	let _locks = if options.serialize { Some(lock::DirLocks::acquire_checked(a, b)?) } else { None };
+
+	if is_same_path(a, b) {
+		return Ok(SwapReport { before: Snapshot::capture(a, b), ..SwapReport::atomic() });
+	}
+
+	if options.create_missing {
+		if let Some(missing) = validate::missing_side(a, b)? {
+			return swap_created(missing, a, b, options);
+		}
+	}
+
+	if options.allow_missing {
+		let moved = match validate::missing_side(a, b)? {
+			Some(Side::A) => Some((b, a)),
+			Some(Side::B) => Some((a, b)),
+			None => None,
+		};
+		if let Some((from, to)) = moved {
+			let before = Snapshot::capture(a, b);
+			return move_over(from, to, a, b, options).map(|report| SwapReport { before, ..report });
+		}
+	}
+
+	let entries = validate::check(a, b)?;
+	let before = Snapshot::from_entries(&entries);
+	if entries.same_file {
+		return Ok(SwapReport { before, ..SwapReport::atomic() });
+	}
+	swap_checked(fs, a, b, &entries, options).map(|report| SwapReport { before, ..report })
+}
+
+/// Swaps `a` and `b` once they were validated.
+fn swap_checked<F>(fs: &F, a: &Path, b: &Path, entries: &validate::Entries, options: &SwapOptions) -> Result<SwapReport, SwapError> where F: FileSystem {
+	if options.overwrite_from_read_only {
+		match overwrite::read_only_side(a, b)? {
+			Some(Side::A) => return overwrite_from(Side::A, a, b, options),
+			Some(Side::B) => return overwrite_from(Side::B, b, a, options),
+			None => (),
+		}
+	}
+
+	if options.sync_data {
+		sync_tree(a).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+		sync_tree(b).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	} else if options.durable {
+		sync_content(a).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+		sync_content(b).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	}
+
+	let preserved = preserve::Preserved::capture(a, b, options).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	let protected = immutable::Protected::clear(a, b, entries, options)?;
+	let report = match readonly::cleared(a, b, || swap_strategy_with(fs, a, b, entries, options)) {
+		Ok(report) => report,
+		Err(err @ SwapError::Corrupted { .. }) => return Err(err),
+		Err(err) if options.clear_immutable => {
+			protected.restore(a, b);
+			return Err(err);
+		},
+		Err(err) => return Err(immutable::explain(err, a, b, entries)),
+	};
+	// permissions cannot be changed while the flags are set
+	let restored = preserved.restore(a, b);
+	protected.restore_swapped(a, b).map_err(SwapError::Swapped)?;
+	restored.map_err(SwapError::Swapped)?;
+
+	if options.durable {
+		sync_parents(a, b).map_err(SwapError::Swapped)?;
+	}
+
+	Ok(report)
+}
+
+/// Overwrites `to` with a copy of read-only path `from`, located on side `source`.
+fn overwrite_from(source: Side, from: &Path, to: &Path, options: &SwapOptions) -> Result<SwapReport, SwapError> {
+	let stats = overwrite::overwrite(&StdFs, from, to, options.read_only_backup.as_deref(), &mut copy::CopyControl::new(options))?;
+
+	if options.sync_data {
+		sync_tree(to).map_err(SwapError::Swapped)?;
+	} else if options.durable {
+		sync_content(to).map_err(SwapError::Swapped)?;
+	}
+	if options.durable {
+		sync_parents(to, to).map_err(SwapError::Swapped)?;
+	}
+
+	Ok(SwapReport {
+		bytes_copied: stats.copied_bytes,
+		sparse_files: stats.sparse_files,
+		..SwapReport::new(SwapKind::Overwritten { source })
+	})
+}
+
+/// Creates the `missing` path of `a` and `b` empty, with the type of the other path, and swaps
+/// them. If the swap fails without modifying the paths, the created path is removed.
+fn swap_created(missing: Side, a: &Path, b: &Path, options: &SwapOptions) -> Result<SwapReport, SwapError> {
+	let (created, existing) = match missing {
+		Side::A => (a, b),
+		Side::B => (b, a),
+	};
+	let before = Snapshot::capture(a, b);
+
+	let is_dir = fs::symlink_metadata(existing).map_err(|err| SwapError::from_unmodified(err, a, b))?.is_dir();
+	let create = if is_dir {
+		fs::create_dir(created)
+	} else {
+		fs::OpenOptions::new().write(true).create_new(true).open(created).map(|_| ())
+	};
+	create.map_err(|err| SwapError::from_unmodified(err, a, b))?;
+
+	let result = validate::check(a, b).and_then(|entries| swap_checked(&StdFs, a, b, &entries, options));
+	match result {
+		Ok(report) => Ok(SwapReport { before, ..report }),
+		Err(err @ SwapError::Corrupted { .. }) => Err(err),
+		Err(err) => {
+			// the created path is still empty, unless another process wrote to it meanwhile
+			let removed = if is_dir {
+				fs::remove_dir(created)
+			} else if fs::symlink_metadata(created).map(|metadata| metadata.len() == 0).unwrap_or(false) {
+				fs::remove_file(created)
+			} else {
+				Ok(())
+			};
+			if let Err(remove_error) = removed {
+				swap_log!(warn, "Removing created path failed"; path = ?created, error = %remove_error);
+			}
+			Err(err)
+		},
+	}
+}
+
+/// Moves `from` to missing path `to`, `a` and `b` being the swapped paths.
+fn move_over(from: &Path, to: &Path, a: &Path, b: &Path, options: &SwapOptions) -> Result<SwapReport, SwapError> {
+	if options.sync_data {
+		sync_tree(from).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	} else if options.durable {
+		sync_content(from).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	}
+
+	// `to` is never replaced, even if it's created meanwhile
+	let report = match platform::rename_noreplace(from, to) {
+		Ok(_) => SwapReport::atomic(),
+		Err(ref err) if platform::is_cross_device(err) && options.require_atomic => return Err(SwapError::CrossDevice),
+		Err(ref err) if platform::is_cross_device(err) => {
+			let mut control = copy::CopyControl::new(options);
+			let stats = copy::move_copied(from, to, &mut control).map_err(|err| control.unmodified_error(err, a, b))?;
+			SwapReport { fallback: Some(FallbackReason::cross_device(err)), ..SwapReport::copied(&stats) }
+		},
+		Err(ref err) if platform::is_atomic_unsupported(err) && options.require_atomic => return Err(SwapError::AtomicUnsupported),
+		Err(ref err) if platform::is_atomic_unsupported(err) => {
+			filesystem::rename_exclusive(from, to).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+			SwapReport { fallback: Some(FallbackReason::atomic_unsupported(err)), ..SwapReport::new(SwapKind::Nonatomic) }
+		},
+		Err(err) => return Err(SwapError::from_unmodified(err, a, b)),
+	};
+
+	if options.durable {
+		sync_parents(a, b).map_err(SwapError::Swapped)?;
+	}
+
+	Ok(report)
+}
+
+fn swap_strategy_with<F>(fs: &F, a: &Path, b: &Path, entries: &validate::Entries, options: &SwapOptions) -> Result<SwapReport, SwapError> where F: FileSystem {
+	let fs = RetryingFs::new(fs, Retry::from_options(options));
+	swap_strategy_in(&fs, a, b, entries, options).map(|report| SwapReport { retries: fs.retried(), ..report })
+}
+
+fn swap_strategy_in<F>(fs: &RetryingFs<F>, a: &Path, b: &Path, entries: &validate::Entries, options: &SwapOptions) -> Result<SwapReport, SwapError> where F: FileSystem {
+	let reason = if options.atomic || options.require_atomic {
+		// a failed atomic swap leaves the paths unchanged, so it can be retried as a whole
+		match fs.run(|| platform::swap(a, b)) {
+			Ok(_) => {
+				swap_metric!("fs_swap.atomic");
+				return Ok(SwapReport::atomic());
+			},
+			Err(ref err) if options.require_atomic && platform::is_cross_device(err) => return Err(SwapError::CrossDevice),
+			Err(ref err) if options.require_atomic && platform::is_atomic_unsupported(err) => return Err(SwapError::AtomicUnsupported),
+			Err(ref err) if platform::is_cross_device(err) => FallbackReason::cross_device(err),
+			Err(ref err) if platform::is_atomic_unsupported(err) => FallbackReason::atomic_unsupported(err),
+			Err(err) => return Err(SwapError::from_unmodified(err, a, b)),
+		}
+	} else {
+		FallbackReason::Disabled
+	};
+	swap_log!(debug, "Swapping nonatomically"; a = ?a, b = ?b, reason = %reason);
+
+	let linked = match reason {
+		FallbackReason::AtomicUnsupported { .. } => linked(fs, a, b, entries, options),
+		_ => None,
+	};
+	let result = match linked {
+		Some(result) => result,
+		None => nonatomic(fs, a, b, entries, options),
+	};
+	result.map(|report| SwapReport { fallback: Some(reason), ..report })
+}
+
+/// Returns true if `a` and `b` can be swapped atomically.
+///
+/// The paths are not modified, but the filesystem is probed by swapping two scratch files created
+/// next to `a`. Paths on different filesystems can never be swapped atomically.
+pub fn atomic_swap_supported<A, B>(a: A, b: B) -> io::Result<bool> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = canonicalize_parent(a.as_ref())?;
+	let b = canonicalize_parent(b.as_ref())?;
+
+	if !platform::same_device(&a, &b)? {
+		return Ok(false);
+	}
+
+	let dir = a.parent().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))?;
+	platform::atomic_supported(dir)
+}
+
+/// Flushes the content of `path` to disk.
+///
+/// Symlinks are skipped, since only the links are swapped, and so are special files like FIFOs,
+/// which have no content and may block when opened.
+fn sync_content(path: &Path) -> io::Result<()> {
+	let file_type = fs::symlink_metadata(path)?.file_type();
+	if !file_type.is_file() && !file_type.is_dir() {
+		return Ok(());
+	}
+	platform::sync(path)
+}
+
+/// Flushes `path` to disk like `sync_content`, and every file and directory within it.
+fn sync_tree(path: &Path) -> io::Result<()> {
+	if fs::symlink_metadata(path)?.is_dir() {
+		for entry in fs::read_dir(path)? {
+			sync_tree(&entry?.path())?;
+		}
+	}
+	sync_content(path)
+}
+
+/// Flushes the directory entries of `a` and `b` to disk.
+fn sync_parents(a: &Path, b: &Path) -> io::Result<()> {
+	fn parent(path: &Path) -> &Path {
+		match path.parent() {
+			Some(parent) if parent != Path::new("") => parent,
+			_ => Path::new("."),
+		}
+	}
+
+	platform::sync(parent(a))?;
+	if parent(a) != parent(b) {
+		platform::sync(parent(b))?;
+	}
+	Ok(())
+}
+
+/// Returns `path` with its parent directory canonicalized.
+///
+/// The last component is not resolved, so a symlink is not replaced by its target.
+pub(crate) fn canonicalize_parent(path: &Path) -> io::Result<PathBuf> {
+	match (path.parent(), path.file_name()) {
+		(Some(parent), Some(name)) if parent == Path::new("") => Ok(fs::canonicalize(".")?.join(name)),
+		(Some(parent), Some(name)) => Ok(fs::canonicalize(parent)?.join(name)),
+		_ => fs::canonicalize(path),
+	}
+}
+
+/// Returns true if `a` and `b` are different spellings of the same path.
+///
+/// Paths ending with different names are never the same, so their parents are not canonicalized.
+fn is_same_path(a: &Path, b: &Path) -> bool {
+	match (a.file_name(), b.file_name()) {
+		(Some(name_a), Some(name_b)) if name_a != name_b => return false,
+		_ => (),
+	}
+
+	match (canonicalize_parent(a), canonicalize_parent(b)) {
+		(Ok(a), Ok(b)) => a == b,
+		_ => false,
+	}
+}
+
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Room for the name of a temp path, `.fs_swap.` followed by two decimal `u32` and `usize`.
+const TMP_NAME_CAPACITY: usize = 48;
+
+/// Returns a path in `dir` that is not used by any file or directory.
+///
+/// The path is written in a single buffer, reused if a name is already taken.
+fn tmp_path_in_dir(dir: &Path) -> io::Result<PathBuf> {
+	let mut tmp = PathBuf::with_capacity(dir.as_os_str().len() + TMP_NAME_CAPACITY);
+	tmp.push(dir);
+	loop {
+		tmp.push(".fs_swap.");
+		write!(tmp.as_mut_os_string(), "{}.{}", process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed))
+			.expect("writing to an OsString never fails; qed");
+		match fs::symlink_metadata(&tmp) {
+			Ok(_) => {
+				tmp.pop();
+			},
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(tmp),
+			Err(err) => return Err(err),
+		}
+	}
+}
+
+/// Number of names generated by `SwapOptions::temp_name_fn` before giving up.
+const TEMP_NAME_ATTEMPTS: usize = 16;
+
+/// Returns a path in `dir` named by `temp_name`, that is not used by any file or directory, nor
+/// another spelling of one of the `excluded` paths.
+fn named_tmp_path_in_dir(dir: &Path, temp_name: &TempName, excluded: &[&Path]) -> io::Result<PathBuf> {
+	for _ in 0..TEMP_NAME_ATTEMPTS {
+		let name = temp_name.generate();
+		let mut components = Path::new(&name).components();
+		match (components.next(), components.next()) {
+			(Some(Component::Normal(component)), None) if component == name => (),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Temp name {:?} is not a single path component", name))),
+		}
+
+		let tmp = dir.join(name);
+		if excluded.iter().any(|path| is_same_path(&tmp, path)) {
+			continue;
+		}
+		match fs::symlink_metadata(&tmp) {
+			Ok(_) => continue,
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(tmp),
+			Err(err) => return Err(err),
+		}
+	}
+	Err(io::Error::new(io::ErrorKind::AlreadyExists, "Temp name generator returned no unused name"))
 }
 
 /// Nonatomic swap.
-pub fn swap_nonatomic<A, B>(a: A, b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
-	const TMP_SWAP_FILE: &'static str = "tmp.fs_swap";
+///
+/// `a` is renamed to a temp path, `b` to `a` and the temp path to `b`, so no path is ever renamed
+/// over an existing one, and directories are swapped the same way whether they are empty or not.
+/// The temp path is never replaced: if something, like a symlink, was created there after it was
+/// found unused, the swap fails without modifying the paths. On a filesystem which cannot rename
+/// without replacing, files are moved there with a hardlink, but a directory can still replace an
+/// empty directory created at the temp path right before the rename.
+/// If `a` and `b` are on different filesystems, or in different bind mounts of one, they are
+/// swapped by copying them.
+pub fn swap_nonatomic<A, B>(a: A, b: B) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = &*validate::resolve(a.as_ref())?;
+	let b = &*validate::resolve(b.as_ref())?;
+	swap_span!("swap_nonatomic", a, b);
 
-	let a = a.as_ref();
-	let b = b.as_ref();
+	if is_same_path(a, b) {
+		return Ok(());
+	}
+
+	let entries = validate::check(a, b)?;
+	if entries.same_file {
+		return Ok(());
+	}
 
-	let tmp = a.parent()
-		.or_else(|| b.parent())
-		.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))?
-		.join(TMP_SWAP_FILE);
+	readonly::cleared(a, b, || nonatomic(&StdFs, a, b, &entries, &SwapOptions::new()))
+		.map(|_| ())
+		.map_err(|err| immutable::explain(err, a, b, &entries))
+}
 
-	// cleanup
-	match fs::metadata(&tmp) {
-		Ok(ref meta) if meta.is_dir() => fs::remove_dir_all(&tmp)?,
-		Ok(_) => fs::remove_file(&tmp)?,
-		Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
-		Err(err) => return Err(err),
+/// Returns the temp path used to swap `a` and `b` nonatomically, in the directory chosen by
+/// `options.temp`.
+///
+/// The path is never another spelling of `a` or `b`, which could otherwise be removed if one of
+/// them disappeared after the swap was validated.
+fn nonatomic_tmp_path(a: &Path, b: &Path, options: &SwapOptions) -> io::Result<PathBuf> {
+	fn beside<'a>(first: &'a Path, second: &'a Path) -> io::Result<Cow<'a, Path>> {
+		first.parent()
+			.or_else(|| second.parent())
+			.map(Cow::Borrowed)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))
 	}
 
-	// rename a to tmp
-	// if it fails, the directories are unchanged
-	fs::rename(a, &tmp)?;
-
-	match fs::rename(b, a) {
-		Ok(_) => (),
-		Err(_) => {
-			// let's try to recover the previous state
-			// if it fails, there is nothing we can do
-			return fs::rename(&tmp, a);
+	let on_same_device = |dir: &Path| -> io::Result<bool> {
+		Ok(platform::same_device(dir, a)? && platform::same_device(dir, b)?)
+	};
+
+	let dir = match options.temp {
+		TempStrategy::BesideA => beside(a, b)?,
+		TempStrategy::BesideB => beside(b, a)?,
+		TempStrategy::System => {
+			let dir = env::temp_dir();
+			if on_same_device(&dir)? { Cow::Owned(dir) } else { beside(a, b)? }
 		},
+		TempStrategy::Dir(ref dir) => {
+			if !on_same_device(dir)? {
+				return Err(io::Error::new(io::ErrorKind::InvalidInput, "Temp directory is not on the same filesystem as the swapped paths"));
+			}
+			Cow::Borrowed(dir.as_path())
+		},
+	};
+
+	if let Some(ref temp_name) = options.temp_name {
+		return named_tmp_path_in_dir(&dir, temp_name, &[a, b]);
+	}
+	// the counter in the default names never repeats them
+	loop {
+		let tmp = tmp_path_in_dir(&dir)?;
+		if !is_same_path(&tmp, a) && !is_same_path(&tmp, b) {
+			return Ok(tmp);
+		}
+	}
+}
+
+/// Returns true if `a` and `b` are files on the same filesystem on unix, which `linked` swaps.
+fn can_link(a: &Path, b: &Path, entries: &validate::Entries) -> Result<bool, SwapError> {
+	if !cfg!(unix) || !entries.a.is_file() || !entries.b.is_file() {
+		return Ok(false);
+	}
+	platform::same_device_metadata(a, &entries.a, b, &entries.b).map_err(|err| SwapError::from_unmodified(err, a, b))
+}
+
+/// Swaps files `a` and `b`, located on the same filesystem, with a temp hardlink on unix, so
+/// that `a` is never missing: `a` is linked to a temp path, then `b` is renamed over `a` and the
+/// temp path to `b`.
+///
+/// The swap is still not atomic: between both renames, `b` is missing and `a` already has the
+/// content of `b`. Unlike `swap_nonatomic`, only one of the paths is missing at any time. If the
+/// last rename fails, `a` is renamed back to `b` and the temp path back over `a`.
+///
+/// Returns `None` without modifying anything if the paths are not files on the same filesystem,
+/// or if the link could not be created, for instance because the filesystem does not support
+/// hardlinks.
+fn linked<F>(fs: &F, a: &Path, b: &Path, entries: &validate::Entries, options: &SwapOptions) -> Option<Result<SwapReport, SwapError>> where F: FileSystem {
+	match can_link(a, b, entries) {
+		Ok(true) => (),
+		Ok(false) => return None,
+		Err(err) => return Some(Err(err)),
+	}
+
+	let tmp = match nonatomic_tmp_path(a, b, options) {
+		Ok(tmp) => tmp,
+		Err(err) => return Some(Err(err.into())),
+	};
+
+	// link a to tmp
+	// if it fails, the paths are unchanged and they are swapped by the other strategies
+	if let Err(err) = fs.hard_link(a, &tmp) {
+		swap_log!(debug, "Linking a failed, falling back to renames"; a = ?a, tmp = ?tmp, error = %err);
+		return None;
+	}
+
+	// rename b over a
+	// if it fails, only tmp was created, so removing it restores the previous state
+	if let Err(err) = fs.rename(b, a) {
+		let _ = fs.remove_file(&tmp);
+		return Some(Err(SwapError::from_unmodified(err, a, b)));
 	}
 
 	// rename tmp to b
-	match fs::rename(&tmp, b) {
-		Ok(_) => Ok(()),
-		Err(_) => {
-			// let's try to recover to previous state
-			// if it fails, there is nothing we can do
-			fs::rename(a, b)?;
-			fs::rename(&tmp, a)
+	// if it fails, b is missing, so a is moved back to b first and then tmp back over a
+	if let Err(err) = fs.rename(&tmp, b) {
+		swap_log!(warn, "Renaming tmp to b failed, restoring a and b"; a = ?a, b = ?b, tmp = ?tmp, error = %err);
+		swap_metric!("fs_swap.recovery");
+		// the failed revert, and where the content of the paths was left
+		let rollback = match fs.rename(a, b) {
+			Ok(_) => fs.rename(&tmp, a).map_err(|err| (err, (tmp.as_path(), a), vec![(a, tmp.as_path())])),
+			Err(err) => Err((err, (a, b), vec![(a, tmp.as_path()), (b, a)])),
+		};
+		return Some(match rollback {
+			Ok(_) => Err(SwapError::RolledBack(err)),
+			Err((rollback_error, failed_revert, moved)) => {
+				swap_log!(error, "Restoring a and b failed, content of a is in tmp, content of b may be in a"; a = ?a, b = ?b, tmp = ?tmp, error = %rollback_error);
+				let state = CorruptedState {
+					moved: moved.into_iter().map(|(original, current)| (original.to_path_buf(), current.to_path_buf())).collect(),
+					temp_path: Some(tmp.clone()),
+					failed_rename: Some((tmp.clone(), b.to_path_buf())),
+					failed_revert: Some((failed_revert.0.to_path_buf(), failed_revert.1.to_path_buf())),
+				};
+				Err(SwapError::Corrupted { error: err, rollback_error, state: Box::new(state) })
+			},
+		});
+	}
+
+	swap_metric!("fs_swap.linked");
+	Some(Ok(SwapReport {
+		temp_path: Some(tmp),
+		..SwapReport::new(SwapKind::Linked)
+	}))
+}
+
+/// Swaps `a` and `b` without an atomic primitive, `entries` being their metadata read by
+/// `validate::check`.
+fn nonatomic<F>(fs: &F, a: &Path, b: &Path, entries: &validate::Entries, options: &SwapOptions) -> Result<SwapReport, SwapError> where F: FileSystem {
+	let copied = || copy::swap_copied(fs, a, b, &mut copy::CopyControl::new(options)).map(|stats| {
+		swap_metric!("fs_swap.fallback_copy");
+		SwapReport::copied(&stats)
+	});
+
+	if !platform::same_device_metadata(a, &entries.a, b, &entries.b).map_err(|err| SwapError::from_unmodified(err, a, b))? {
+		return copied();
+	}
+
+	match renamed(fs, a, b, options) {
+		// bind mounts of a filesystem share its device, but paths cannot be renamed across them
+		Err(SwapError::Io(ref err)) | Err(SwapError::RolledBack(ref err)) if platform::is_cross_device(err) => {
+			swap_log!(debug, "Paths are on different mounts, swapping them by copying"; a = ?a, b = ?b, error = %err);
+			copied()
 		},
+		result => result,
+	}
+}
+
+/// Swaps `a` and `b`, located on the same device, by renaming them through a temp path.
+fn renamed<F>(fs: &F, a: &Path, b: &Path, options: &SwapOptions) -> Result<SwapReport, SwapError> where F: FileSystem {
+	let tmp = nonatomic_tmp_path(a, b, options)?;
+
+	let mut renames = Renames::new(fs);
+
+	// rename a to tmp, unless something was created at tmp since it was found unused, which may
+	// be a symlink planted by another user of the directory
+	// if it fails, the directories are unchanged
+	renames.rename_noreplace(a, &tmp).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+
+	// rename b to a
+	// if it fails, only a was moved, so moving tmp back to a restores the previous state
+	if let Err(err) = renames.rename(b, a) {
+		swap_log!(warn, "Renaming b to a failed, restoring a"; a = ?a, b = ?b, tmp = ?tmp, error = %err);
+		return match renames.rollback() {
+			Ok(_) => Err(SwapError::from_rolled_back(err, a, b)),
+			Err(rollback_error) => {
+				swap_log!(error, "Restoring a failed, content of a is in tmp"; a = ?a, b = ?b, tmp = ?tmp, error = %rollback_error);
+				Err(renames.corrupted(err, rollback_error, Some(&tmp)))
+			},
+		};
+	}
+
+	// rename tmp to b
+	// if it fails, b is missing, so a is moved back to b first and then tmp back to a
+	if let Err(err) = renames.rename(&tmp, b) {
+		swap_log!(warn, "Renaming tmp to b failed, restoring a and b"; a = ?a, b = ?b, tmp = ?tmp, error = %err);
+		return match renames.rollback() {
+			Ok(_) => Err(SwapError::RolledBack(err)),
+			Err(rollback_error) => {
+				swap_log!(error, "Restoring a and b failed, content of a is in tmp, content of b may be in a"; a = ?a, b = ?b, tmp = ?tmp, error = %rollback_error);
+				Err(renames.corrupted(err, rollback_error, Some(&tmp)))
+			},
+		};
 	}
+
+	renames.commit();
+	swap_metric!("fs_swap.nonatomic");
+	Ok(SwapReport {
+		temp_path: Some(tmp),
+		..SwapReport::new(SwapKind::Nonatomic)
+	})
 }
 
 #[cfg(test)]
 mod tests {
 	extern crate tempdir;
+	use std::cell::RefCell;
 	use std::fs;
 	use std::path::Path;
-	use std::io::{Write, Read};
+	use std::io::{self, Write, Read};
+	#[cfg(any(target_os = "linux", windows))]
+	use std::process;
+	use std::sync::{mpsc, Arc, Mutex};
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+	use std::time::Duration;
 	use self::tempdir::TempDir;
-	use super::{swap, swap_nonatomic};
+	use filetime::{self, FileTime};
+	use contents;
+	use copy::{self, CopyControl};
+	use inplace;
+	use overwrite;
+	use validate;
+	use clock::Clock;
+	use filesystem::{FailingFs, FileSystem, StdFs};
+	use retry::{Retry, RetryingFs};
+	use rollback::Renames;
+	use timeout;
+	use platform;
+	use super::{capabilities, env, exchange, nonatomic, plan, swap_strategy_with, swap, swap_dir_contents, swap_dir_contents_dedup, swap_nonatomic, swap_verbose, atomic_swap_supported, swap_auto, swap_with, swap_durable, swap3, rotate, promote, swap_many, swap_many_with, swap_guarded, swap_then, swap_if_different, move_or_swap, replace, replace_with, swap_with_backup, swap_contents, PendingSwap, MoveOrSwap, FallbackReason, SwapKind, SwapReport, SwapPlan, SwapOptions, SwapError, Side, TempStrategy, PairOutcome};
+
+	/// Counts the metadata reads and renames before delegating them to `StdFs`.
+	#[derive(Default)]
+	struct CountingFs {
+		metadata: RefCell<usize>,
+		renames: RefCell<usize>,
+	}
+
+	impl FileSystem for CountingFs {
+		fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+			*self.renames.borrow_mut() += 1;
+			StdFs.rename(from, to)
+		}
+
+		fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+			*self.renames.borrow_mut() += 1;
+			StdFs.rename_noreplace(from, to)
+		}
+
+		fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+			*self.metadata.borrow_mut() += 1;
+			StdFs.metadata(path)
+		}
+
+		fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+			StdFs.hard_link(from, to)
+		}
+
+		fn remove_file(&self, path: &Path) -> io::Result<()> {
+			StdFs.remove_file(path)
+		}
+
+		fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+			StdFs.remove_dir_all(path)
+		}
+	}
+
+	/// Bind mount of a directory on Linux, unmounted when dropped.
+	#[cfg(target_os = "linux")]
+	struct BindMount<'a>(&'a Path);
+
+	#[cfg(target_os = "linux")]
+	impl<'a> BindMount<'a> {
+		/// Mounts `source` at `target`, or returns `None` if bind mounts are not permitted, since
+		/// they require `CAP_SYS_ADMIN`.
+		fn new(source: &Path, target: &'a Path) -> Option<Self> {
+			let status = process::Command::new("mount").arg("--bind").arg(source).arg(target)
+				.stderr(process::Stdio::null())
+				.status();
+			match status {
+				Ok(ref status) if status.success() => Some(BindMount(target)),
+				_ => None,
+			}
+		}
+	}
+
+	#[cfg(target_os = "linux")]
+	impl<'a> Drop for BindMount<'a> {
+		fn drop(&mut self) {
+			let _ = process::Command::new("umount").arg(self.0).status();
+		}
+	}
+
+	/// Clock which never waits, recording the waits. A value which was not sent yet is never
+	/// received.
+	#[derive(Default)]
+	struct MockClock {
+		waits: RefCell<Vec<Duration>>,
+	}
+
+	impl Clock for MockClock {
+		fn sleep(&self, duration: Duration) {
+			self.waits.borrow_mut().push(duration);
+		}
+
+		fn recv_timeout<T>(&self, receiver: &mpsc::Receiver<T>, timeout: Duration) -> Result<T, mpsc::RecvTimeoutError> {
+			match receiver.try_recv() {
+				Ok(value) => Ok(value),
+				Err(mpsc::TryRecvError::Empty) => {
+					self.sleep(timeout);
+					Err(mpsc::RecvTimeoutError::Timeout)
+				},
+				Err(mpsc::TryRecvError::Disconnected) => Err(mpsc::RecvTimeoutError::Disconnected),
+			}
+		}
+	}
+
+	fn nonatomic_in<F: FileSystem>(fs: &F, a: &Path, b: &Path) -> Result<SwapReport, SwapError> {
+		nonatomic(fs, a, b, &validate::check(a, b).unwrap(), &SwapOptions::new())
+	}
 
 	fn write_to_file<P: AsRef<Path>>(file: P, text: &str) {
 		let mut file = fs::OpenOptions::new()
 			.create(true)
 			.write(true)
+			.truncate(true)
 			.open(file)
 			.unwrap();
 		file.write_all(text.as_ref()).unwrap();
@@ -102,8 +1025,6 @@ mod tests {
 		assert_eq!("foo", read_b);
 	}
 
-	// atomic swaps of dirs are not supported on travis machines
-	#[cfg(not(target_os = "macos"))]
 	#[test]
 	fn test_swap_dirs() {
 		let dir_a = TempDir::new("a").unwrap();
@@ -133,6 +1054,2601 @@ mod tests {
 		assert_eq!("foo", read_b);
 	}
 
+	#[test]
+	fn test_swap_auto_files() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		assert_eq!(SwapKind::Atomic, swap_auto(&path_a, &path_b).unwrap());
+		let read_a = read_from_file(&path_a);
+		let read_b = read_from_file(&path_b);
+		assert_eq!("bar", read_a);
+		assert_eq!("foo", read_b);
+	}
+
+	#[test]
+	fn test_swap_auto_missing_file() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		match swap_auto(&path_a, &path_b) {
+			Err(SwapError::NotFound { which: Side::B }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		match swap(&path_b, &path_a) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_nonatomic_missing_file() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_b, "bar");
+		match swap_nonatomic(&path_a, &path_b) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		write_to_file(&path_a, "foo");
+		fs::remove_file(&path_b).unwrap();
+		match swap_nonatomic(&path_a, &path_b) {
+			Err(SwapError::NotFound { which: Side::B }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+	}
+
+	#[test]
+	fn test_swap_nonatomic_keeps_unrelated_files() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("tmp.fs_swap");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		write_to_file(&path_c, "baz");
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!("baz", read_from_file(&path_c));
+		assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_with_temp_dir() {
+		let dir = TempDir::new("").unwrap();
+		let tmp_dir = TempDir::new_in(dir.path(), "tmp").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let options = SwapOptions::new().temp_dir(tmp_dir.path()).atomic(false);
+		assert_eq!(SwapKind::Nonatomic, swap_with(&path_a, &path_b, &options).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(0, fs::read_dir(tmp_dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_with_temp_name_fn() {
+		use std::ffi::OsString;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_tmp = dir.path().join("swap.tmp");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let options = SwapOptions::new().temp_name_fn(|| OsString::from("swap.tmp")).atomic(false);
+		assert_eq!(SwapKind::Nonatomic, swap_with(&path_a, &path_b, &options).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		write_to_file(&path_tmp, "baz");
+		match swap_with(&path_a, &path_b, &options) {
+			Err(SwapError::Io(ref err)) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		let options = SwapOptions::new().temp_name_fn(|| OsString::from("tmp/swap")).atomic(false);
+		match swap_with(&path_a, &path_b, &options) {
+			Err(SwapError::Io(ref err)) if err.kind() == io::ErrorKind::InvalidInput => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		// a missing path is still never picked as the temp path
+		let options = SwapOptions::new().temp_name_fn(|| OsString::from("file_c")).atomic(false);
+		match super::nonatomic_tmp_path(&dir.path().join("file_c"), &path_b, &options) {
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!("baz", read_from_file(&path_tmp));
+	}
+
+	#[test]
+	fn test_swap_with_temp_strategy() {
+		let dir_a = TempDir::new("a").unwrap();
+		let dir_b = TempDir::new("b").unwrap();
+		let path_a = dir_a.path().join("file_a");
+		let path_b = dir_b.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		let temp_dir = |strategy: TempStrategy| {
+			match plan::plan_with(&path_a, &path_b, &SwapOptions::new().atomic(false).temp_strategy(strategy)).unwrap() {
+				SwapPlan::Nonatomic { temp_path } => temp_path.parent().unwrap().to_path_buf(),
+				plan => panic!("unexpected plan: {:?}", plan),
+			}
+		};
+		assert_eq!(dir_a.path(), temp_dir(TempStrategy::default()));
+		assert_eq!(dir_b.path(), temp_dir(TempStrategy::BesideB));
+		assert_eq!(env::temp_dir(), temp_dir(TempStrategy::System));
+		assert_eq!(dir_b.path(), temp_dir(TempStrategy::Dir(dir_b.path().to_path_buf())));
+
+		let options = SwapOptions::new().atomic(false).temp_strategy(TempStrategy::BesideB);
+		assert_eq!(SwapKind::Nonatomic, swap_with(&path_a, &path_b, &options).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(1, fs::read_dir(dir_b.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_create_missing() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_b, "new");
+		let options = SwapOptions::new().create_missing(true);
+		swap_with(&path_a, &path_b, &options).unwrap();
+		assert_eq!("new", read_from_file(&path_a));
+		assert_eq!("", read_from_file(&path_b));
+
+		let dir_a = dir.path().join("dir_a");
+		let dir_b = dir.path().join("dir_b");
+		fs::create_dir(&dir_a).unwrap();
+		write_to_file(dir_a.join("file"), "foo");
+		swap_with(&dir_a, &dir_b, &options).unwrap();
+		assert_eq!("foo", read_from_file(dir_b.join("file")));
+		assert_eq!(0, fs::read_dir(&dir_a).unwrap().count());
+
+		match swap_with(dir.path().join("missing_a"), dir.path().join("missing_b"), &options) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		let options = options.atomic(false).temp_dir(dir.path().join("missing"));
+		assert!(swap_with(dir.path().join("missing_a"), &path_a, &options).is_err());
+		assert_eq!("new", read_from_file(&path_a));
+		assert_eq!(4, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_relative_and_absolute() {
+		use std::path::{Component, PathBuf};
+
+		let current_dir = env::current_dir().unwrap();
+		let dir = TempDir::new("relative").unwrap();
+		// a relative path climbs from the current directory to the root, then down to `dir`, which
+		// cannot be reached if it's on another drive
+		if current_dir.components().next() != dir.path().components().next() {
+			return;
+		}
+		let depth = current_dir.components().filter(|component| matches!(component, Component::Normal(_))).count();
+		let mut path_a: PathBuf = (0..depth).map(|_| "..").collect();
+		path_a.extend(dir.path().components().filter(|component| matches!(component, Component::Normal(_))));
+		path_a.push("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		swap(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		swap_nonatomic(&path_b, &path_a).unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		let options = SwapOptions::new().atomic(false);
+		match plan::plan_with(&path_a, &path_b, &options).unwrap() {
+			SwapPlan::Nonatomic { temp_path } => assert!(temp_path.is_absolute()),
+			plan => panic!("unexpected plan: {:?}", plan),
+		}
+		assert_eq!(SwapKind::Nonatomic, swap_with(&path_a, &path_b, &options).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_durable_dirs() {
+		let dir_a = TempDir::new("a").unwrap();
+		let dir_b = TempDir::new("b").unwrap();
+		let path_a = dir_a.path().join("file");
+		let path_b = dir_b.path().join("file");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		swap_durable(&dir_a, &dir_b).unwrap();
+		let read_a = read_from_file(&path_a);
+		let read_b = read_from_file(&path_b);
+		assert_eq!("bar", read_a);
+		assert_eq!("foo", read_b);
+	}
+
+	#[test]
+	fn test_swap3_files() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("file_c");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		write_to_file(&path_c, "baz");
+		swap3(&path_a, &path_b, &path_c).unwrap();
+		assert_eq!("baz", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!("bar", read_from_file(&path_c));
+		assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap3_rollback() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("file_c");
+		write_to_file(&path_b, "bar");
+		write_to_file(&path_c, "baz");
+		match swap3(&path_a, &path_b, &path_c) {
+			Err(SwapError::RolledBack(_)) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert!(!path_a.exists());
+		assert_eq!("bar", read_from_file(&path_b));
+		assert_eq!("baz", read_from_file(&path_c));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_promote() {
+		let dir = TempDir::new("").unwrap();
+		let next = dir.path().join("app.next");
+		let current = dir.path().join("app.current");
+		let old = dir.path().join("app.old");
+		write_to_file(&current, "v1");
+
+		// first deploy, there is no old release yet
+		write_to_file(&next, "v2");
+		promote(&next, &current, &old).unwrap();
+		assert_eq!("v2", read_from_file(&current));
+		assert_eq!("v1", read_from_file(&old));
+		assert!(!next.exists());
+
+		write_to_file(&next, "v3");
+		promote(&next, &current, &old).unwrap();
+		assert_eq!("v3", read_from_file(&current));
+		assert_eq!("v2", read_from_file(&old));
+		assert_eq!("v1", read_from_file(&next));
+
+		// the old release cannot be replaced, current is restored
+		fs::remove_file(&old).unwrap();
+		fs::create_dir(&old).unwrap();
+		write_to_file(&next, "v4");
+		match promote(&next, &current, &old) {
+			Err(SwapError::RolledBack(_)) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("v3", read_from_file(&current));
+		assert_eq!("v4", read_from_file(&next));
+
+		match promote(&next, &current, &current) {
+			Err(SwapError::Io(ref err)) if err.kind() == io::ErrorKind::InvalidInput => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	// a missing `old` on another filesystem cannot be renamed to
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_promote_cross_device() {
+		let dir = TempDir::new("").unwrap();
+		let other = match TempDir::new_in("/dev/shm", "") {
+			Ok(other) => other,
+			Err(_) => return,
+		};
+		if platform::same_device(dir.path(), other.path()).unwrap() {
+			return;
+		}
+		let next = dir.path().join("app.next");
+		let current = dir.path().join("app.current");
+		let old = other.path().join("app.old");
+		write_to_file(&next, "v2");
+		write_to_file(&current, "v1");
+
+		match promote(&next, &current, &old) {
+			Err(SwapError::RolledBack(_)) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("v1", read_from_file(&current));
+		assert_eq!("v2", read_from_file(&next));
+		assert!(!old.exists());
+	}
+
+	#[test]
+	fn test_rotate_files() {
+		let dir = TempDir::new("").unwrap();
+		let contents = ["foo", "bar", "baz", "qux", "quux"];
+		let paths: Vec<_> = (0..contents.len()).map(|i| dir.path().join(format!("file_{}", i))).collect();
+		for (path, text) in paths.iter().zip(contents.iter()) {
+			write_to_file(path, text);
+		}
+		rotate(&paths).unwrap();
+		for i in 0..paths.len() {
+			assert_eq!(contents[(i + contents.len() - 1) % contents.len()], read_from_file(&paths[i]));
+		}
+		assert_eq!(contents.len(), fs::read_dir(dir.path()).unwrap().count());
+		rotate(&paths[..1]).unwrap();
+		assert_eq!("quux", read_from_file(&paths[0]));
+	}
+
+	#[test]
+	fn test_swap_many_stops_at_failure() {
+		let dir = TempDir::new("").unwrap();
+		let paths: Vec<_> = (0..6).map(|i| dir.path().join(format!("file_{}", i))).collect();
+		for (i, path) in paths.iter().enumerate() {
+			if i != 3 {
+				write_to_file(path, &i.to_string());
+			}
+		}
+		let pairs = [(&paths[0], &paths[1]), (&paths[2], &paths[3]), (&paths[4], &paths[5])];
+		match swap_many(&pairs) {
+			Err((1, SwapError::NotFound { which: Side::B })) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("1", read_from_file(&paths[0]));
+		assert_eq!("0", read_from_file(&paths[1]));
+		assert_eq!("2", read_from_file(&paths[2]));
+		assert_eq!("4", read_from_file(&paths[4]));
+		assert_eq!("5", read_from_file(&paths[5]));
+	}
+
+	#[test]
+	fn test_swap_many_with_outcomes() {
+		let dir = TempDir::new("").unwrap();
+		let paths: Vec<_> = (0..6).map(|i| dir.path().join(format!("file_{}", i))).collect();
+		for (i, path) in paths.iter().enumerate() {
+			if i != 3 {
+				write_to_file(path, &i.to_string());
+			}
+		}
+		let pairs = [(&paths[0], &paths[1]), (&paths[2], &paths[3]), (&paths[4], &paths[5])];
+
+		let outcome = swap_many_with(&pairs, &SwapOptions::new());
+		assert!(!outcome.is_success());
+		match outcome.pairs() {
+			[PairOutcome::Swapped(_), PairOutcome::Failed(SwapError::NotFound { which: Side::B }), PairOutcome::Skipped] => (),
+			pairs => panic!("unexpected outcomes: {:?}", pairs),
+		}
+		assert_eq!("4", read_from_file(&paths[4]));
+
+		let outcome = swap_many_with(&pairs, &SwapOptions::new().continue_on_error(true));
+		assert_eq!(vec![1], outcome.failures().map(|(i, _)| i).collect::<Vec<_>>());
+		match outcome.pairs() {
+			[PairOutcome::Swapped(_), PairOutcome::Failed(_), PairOutcome::Swapped(_)] => (),
+			pairs => panic!("unexpected outcomes: {:?}", pairs),
+		}
+		assert_eq!("0", read_from_file(&paths[0]));
+		assert_eq!("5", read_from_file(&paths[4]));
+
+		let outcome = swap_many_with(&[(&paths[0], &paths[1]), (&paths[4], &paths[0])], &SwapOptions::new().continue_on_error(true));
+		match outcome.pairs() {
+			[PairOutcome::Skipped, PairOutcome::Failed(SwapError::Io(_))] => (),
+			pairs => panic!("unexpected outcomes: {:?}", pairs),
+		}
+		assert!(outcome.into_result().is_err());
+		assert_eq!("0", read_from_file(&paths[0]));
+	}
+
+	#[test]
+	fn test_swap_same_path() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join(".").join("file_a");
+		write_to_file(&path_a, "foo");
+		swap(&path_a, &path_b).unwrap();
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!(SwapKind::Atomic, swap_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).unwrap());
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_with_preserve_permissions() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		fs::set_permissions(&path_a, fs::Permissions::from_mode(0o600)).unwrap();
+		fs::set_permissions(&path_b, fs::Permissions::from_mode(0o644)).unwrap();
+		let options = SwapOptions::new().atomic(false).preserve_permissions(true);
+		swap_with(&path_a, &path_b, &options).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(0o600, fs::metadata(&path_a).unwrap().permissions().mode() & 0o777);
+		assert_eq!(0o644, fs::metadata(&path_b).unwrap().permissions().mode() & 0o777);
+	}
+
+	#[test]
+	fn test_swap_with_preserve_times() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let time_a = FileTime::from_unix_time(1_000_000_000, 0);
+		let time_b = FileTime::from_unix_time(1_500_000_000, 0);
+		filetime::set_file_times(&path_a, time_a, time_a).unwrap();
+		filetime::set_file_times(&path_b, time_b, time_b).unwrap();
+		let options = SwapOptions::new().atomic(false).preserve_times(true);
+		swap_with(&path_a, &path_b, &options).unwrap();
+		assert_eq!(time_b, FileTime::from_last_modification_time(&fs::metadata(&path_a).unwrap()));
+		assert_eq!(time_a, FileTime::from_last_modification_time(&fs::metadata(&path_b).unwrap()));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_symlinks() {
+		use std::os::unix::fs::symlink;
+
+		let dir = TempDir::new("").unwrap();
+		let target = dir.path().join("target");
+		let path_a = dir.path().join("link_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("link_c");
+		write_to_file(&target, "foo");
+		write_to_file(&path_b, "bar");
+		symlink(&target, &path_a).unwrap();
+		symlink("missing", &path_c).unwrap();
+
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!(target, fs::read_link(&path_b).unwrap());
+		assert_eq!("foo", read_from_file(&target));
+
+		swap_durable(&path_b, &path_c).unwrap();
+		assert_eq!(Path::new("missing"), fs::read_link(&path_b).unwrap());
+		assert_eq!(target, fs::read_link(&path_c).unwrap());
+		assert_eq!("foo", read_from_file(&target));
+	}
+
+	#[test]
+	fn test_swap_copied_dirs() {
+		let dir_a = TempDir::new("a").unwrap();
+		let dir_b = TempDir::new("b").unwrap();
+		let path_a = dir_a.path().join("dir");
+		let path_b = dir_b.path().join("file");
+		fs::create_dir(&path_a).unwrap();
+		fs::create_dir(path_a.join("nested")).unwrap();
+		write_to_file(path_a.join("nested").join("file"), "foo");
+		write_to_file(&path_b, "bar");
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(path_b.join("nested").join("file")));
+		assert_eq!(1, fs::read_dir(dir_a.path()).unwrap().count());
+		assert_eq!(1, fs::read_dir(dir_b.path()).unwrap().count());
+
+		// directories without files are not reported as cloned
+		fs::remove_file(&path_a).unwrap();
+		fs::remove_file(path_b.join("nested").join("file")).unwrap();
+		fs::create_dir(&path_a).unwrap();
+		let stats = copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		assert_eq!((0, 0), (stats.reflinked_files, stats.copied_files));
+		assert_eq!(SwapKind::Copied { reflink: false }, SwapReport::copied(&stats).kind);
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_cross_device_files() {
+		let dir_a = TempDir::new("a").unwrap();
+		let dir_b = match TempDir::new_in("/dev/shm", "b") {
+			Ok(dir) => dir,
+			Err(_) => return,
+		};
+		// no other filesystem available
+		if platform::same_device(dir_a.path(), dir_b.path()).unwrap() {
+			return;
+		}
+		let path_a = dir_a.path().join("file");
+		let path_b = dir_b.path().join("file");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		assert_eq!(SwapKind::Copied { reflink: false }, swap_auto(&path_a, &path_b).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(1, fs::read_dir(dir_b.path()).unwrap().count());
+		assert_eq!(6, swap_verbose(&path_a, &path_b).unwrap().bytes_copied);
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn test_swap_async_files() {
+		use tokio::runtime::Builder;
+		use super::{swap_async, swap_nonatomic_async};
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let runtime = Builder::new_current_thread().build().unwrap();
+		runtime.block_on(swap_async(&path_a, &path_b)).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		runtime.block_on(swap_nonatomic_async(&path_a, &path_b)).unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+	}
+
+	#[test]
+	fn test_swap_guarded_files() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		{
+			let _guard = swap_guarded(&path_a, &path_b).unwrap();
+			assert_eq!("bar", read_from_file(&path_a));
+			assert_eq!("foo", read_from_file(&path_b));
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		swap_guarded(&path_a, &path_b).unwrap().commit();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+	}
+
+	#[test]
+	fn test_swap_file_with_dir() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file");
+		let path_b = dir.path().join("dir");
+		write_to_file(&path_a, "foo");
+		fs::create_dir(&path_b).unwrap();
+		for &(a, b) in &[(&path_a, &path_b), (&path_b, &path_a)] {
+			match swap(a, b) {
+				Err(SwapError::TypeMismatch) => (),
+				result => panic!("unexpected result: {:?}", result),
+			}
+			match swap_nonatomic(a, b) {
+				Err(SwapError::TypeMismatch) => (),
+				result => panic!("unexpected result: {:?}", result),
+			}
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert!(path_b.is_dir());
+	}
+
+	#[test]
+	fn test_atomic_swap_supported() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("a");
+		let path_b = dir.path().join("b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		atomic_swap_supported(&path_a, &path_b).unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_nonatomic_rollback() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		for plan in &[&[true][..], &[false, true], &[false, false, true]] {
+			assert!(nonatomic_in(&FailingFs::new(plan), &path_a, &path_b).is_err());
+			assert_eq!("foo", read_from_file(&path_a));
+			assert_eq!("bar", read_from_file(&path_b));
+			assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+		}
+		for plan in &[&[false, true][..], &[false, false, true]] {
+			match nonatomic_in(&FailingFs::new(plan), &path_a, &path_b) {
+				Err(ref err @ SwapError::RolledBack(_)) => assert!(err.is_unmodified()),
+				result => panic!("unexpected result: {:?}", result),
+			}
+		}
+	}
+
+	#[test]
+	fn test_swap_nonatomic_reads_metadata_once() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("dir_b");
+		write_to_file(&path_a, "foo");
+		fs::create_dir(&path_b).unwrap();
+		let counting = CountingFs::default();
+		assert!(matches!(validate::check_in(&counting, &path_a, &path_b), Err(SwapError::TypeMismatch)));
+		fs::remove_dir(&path_b).unwrap();
+		write_to_file(&path_b, "bar");
+
+		let counting = CountingFs::default();
+		let entries = validate::check_in(&counting, &path_a, &path_b).unwrap();
+		nonatomic(&counting, &path_a, &path_b, &entries, &SwapOptions::new()).unwrap();
+		assert_eq!(2, *counting.metadata.borrow());
+		assert_eq!(3, *counting.renames.borrow());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_nonatomic_planted_tmp_symlink() {
+		/// Filesystem where another user creates a symlink at the temp path just before it's used.
+		struct PlantingFs<'a>(&'a Path);
+
+		impl<'a> FileSystem for PlantingFs<'a> {
+			fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+				StdFs.rename(from, to)
+			}
+
+			fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+				::std::os::unix::fs::symlink(self.0, to)?;
+				StdFs.rename_noreplace(from, to)
+			}
+
+			fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+				StdFs.metadata(path)
+			}
+
+			fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+				StdFs.hard_link(from, to)
+			}
+
+			fn remove_file(&self, path: &Path) -> io::Result<()> {
+				StdFs.remove_file(path)
+			}
+
+			fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+				StdFs.remove_dir_all(path)
+			}
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let outside = TempDir::new("").unwrap();
+		let path_a = dir.path().join("dir_a");
+		let path_b = dir.path().join("dir_b");
+		let target = outside.path().join("file");
+		fs::create_dir(&path_a).unwrap();
+		write_to_file(path_a.join("file"), "foo");
+		fs::create_dir(&path_b).unwrap();
+		write_to_file(&target, "baz");
+
+		let options = SwapOptions::new().temp_name_fn(|| ::std::ffi::OsString::from("swap.tmp"));
+		let entries = validate::check(&path_a, &path_b).unwrap();
+		match nonatomic(&PlantingFs(&target), &path_a, &path_b, &entries, &options) {
+			Err(SwapError::Io(ref err)) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(path_a.join("file")));
+		assert_eq!(0, fs::read_dir(&path_b).unwrap().count());
+		assert_eq!(target, fs::read_link(dir.path().join("swap.tmp")).unwrap());
+		assert_eq!("baz", read_from_file(&target));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_linked() {
+		use super::linked;
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let entries = validate::check(&path_a, &path_b).unwrap();
+		let report = linked(&StdFs, &path_a, &path_b, &entries, &SwapOptions::new()).unwrap().unwrap();
+		assert_eq!(SwapKind::Linked, report.kind);
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		let entries = validate::check(&path_a, &path_b).unwrap();
+		for plan in &[&[true][..], &[false, true]] {
+			assert!(linked(&FailingFs::new(plan), &path_a, &path_b, &entries, &SwapOptions::new()).unwrap().is_err());
+			assert_eq!("bar", read_from_file(&path_a));
+			assert_eq!("foo", read_from_file(&path_b));
+			assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+		}
+
+		fs::remove_file(&path_b).unwrap();
+		fs::create_dir(&path_b).unwrap();
+		fs::remove_file(&path_a).unwrap();
+		fs::create_dir(&path_a).unwrap();
+		let entries = validate::check(&path_a, &path_b).unwrap();
+		assert!(linked(&StdFs, &path_a, &path_b, &entries, &SwapOptions::new()).is_none());
+	}
+
+	#[test]
+	fn test_swap_nonatomic_failed_rollback() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let state = match nonatomic_in(&FailingFs::new(&[false, false, true, false, true]), &path_a, &path_b) {
+			Err(SwapError::Corrupted { state, .. }) => state,
+			result => panic!("unexpected result: {:?}", result),
+		};
+		assert!(!path_a.exists());
+		assert_eq!("bar", read_from_file(&path_b));
+		let tmp = fs::read_dir(dir.path()).unwrap()
+			.map(|entry| entry.unwrap().path())
+			.find(|path| path != &path_b)
+			.unwrap();
+		assert_eq!("foo", read_from_file(&tmp));
+		assert_eq!(vec![(path_a.clone(), tmp.clone())], state.moved);
+		assert_eq!(Some(tmp.clone()), state.temp_path);
+		assert_eq!(Some((tmp.clone(), path_b.clone())), state.failed_rename);
+		assert_eq!(Some((tmp.clone(), path_a.clone())), state.failed_revert);
+
+		// b is left in a if it could not be moved back
+		fs::rename(&tmp, &path_a).unwrap();
+		let state = match nonatomic_in(&FailingFs::new(&[false, false, true, true]), &path_a, &path_b) {
+			Err(SwapError::Corrupted { state, .. }) => state,
+			result => panic!("unexpected result: {:?}", result),
+		};
+		let tmp = state.temp_path.clone().unwrap();
+		assert_eq!("foo", read_from_file(&tmp));
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!(vec![(path_a.clone(), tmp.clone()), (path_b.clone(), path_a.clone())], state.moved);
+		assert_eq!(Some((path_a.clone(), path_b.clone())), state.failed_revert);
+	}
+
+	#[test]
+	fn test_swap_copied_rollback() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		match copy::swap_copied(&FailingFs::new(&[false, false, true]), &path_a, &path_b, &mut CopyControl::default()) {
+			Err(SwapError::RolledBack(_)) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_if_different() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("file_c");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "foo");
+		write_to_file(&path_c, "bar");
+		assert!(!swap_if_different(&path_a, &path_b).unwrap());
+		assert!(swap_if_different(&path_a, &path_c).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_c));
+		fs::create_dir(dir.path().join("dir")).unwrap();
+		assert!(swap_if_different(&path_a, dir.path().join("dir")).is_err());
+
+		// large files of the same size are hashed chunk by chunk
+		let mut data = vec![1; 200 * 1024];
+		fs::write(&path_a, &data).unwrap();
+		fs::write(&path_b, &data).unwrap();
+		assert!(!swap_if_different(&path_a, &path_b).unwrap());
+		*data.last_mut().unwrap() = 2;
+		fs::write(&path_b, &data).unwrap();
+		assert!(swap_if_different(&path_a, &path_b).unwrap());
+		assert_eq!(data, fs::read(&path_a).unwrap());
+	}
+
+	#[test]
+	fn test_swap_long_paths() {
+		let dir = TempDir::new("").unwrap();
+		let mut long_dir = dir.path().to_path_buf();
+		for _ in 0..30 {
+			long_dir.push("0123456789");
+		}
+		fs::create_dir_all(&long_dir).unwrap();
+		let path_a = long_dir.join("file_a");
+		let path_b = long_dir.join("file_b");
+		assert!(path_a.as_os_str().len() > 260);
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		swap(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+	}
+
+	#[test]
+	fn test_swap_with_backup() {
+		let dir = TempDir::new("").unwrap();
+		let backup_dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let backup = swap_with_backup(&path_a, &path_b, backup_dir.path()).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!("foo", read_from_file(backup.backup_of_a()));
+		assert_eq!("bar", read_from_file(backup.backup_of_b()));
+		write_to_file(&path_a, "baz");
+		backup.restore().unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		assert_eq!(0, fs::read_dir(backup_dir.path()).unwrap().count());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		swap_with_backup(&path_a, &path_b, backup_dir.path()).unwrap().discard().unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!(0, fs::read_dir(backup_dir.path()).unwrap().count());
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn test_swap_readonly_files() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let mut permissions = fs::metadata(&path_a).unwrap().permissions();
+		permissions.set_readonly(true);
+		fs::set_permissions(&path_a, permissions).unwrap();
+		swap(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert!(fs::metadata(&path_a).unwrap().permissions().readonly());
+		assert!(!fs::metadata(&path_b).unwrap().permissions().readonly());
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn test_swap_nonatomic_readonly_files() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let mut permissions = fs::metadata(&path_b).unwrap().permissions();
+		permissions.set_readonly(true);
+		fs::set_permissions(&path_b, permissions).unwrap();
+		swap_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert!(!fs::metadata(&path_a).unwrap().permissions().readonly());
+		assert!(fs::metadata(&path_b).unwrap().permissions().readonly());
+	}
+
+	#[cfg(all(feature = "xattr", target_os = "linux"))]
+	#[test]
+	fn test_swap_copied_xattrs() {
+		extern crate libc;
+		use std::ffi::CString;
+		use std::os::unix::ffi::OsStrExt;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let c_path_a = CString::new(path_a.as_os_str().as_bytes()).unwrap();
+		let c_path_b = CString::new(path_b.as_os_str().as_bytes()).unwrap();
+		let name = CString::new("user.fs_swap").unwrap();
+		let result = unsafe { libc::lsetxattr(c_path_a.as_ptr(), name.as_ptr(), b"baz".as_ptr() as *const _, 3, 0) };
+		if result != 0 {
+			// the filesystem does not support user xattrs
+			return;
+		}
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		let mut value = [0u8; 3];
+		let len = unsafe { libc::lgetxattr(c_path_b.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut _, 3) };
+		assert_eq!(3, len);
+		assert_eq!(b"baz", &value);
+		assert!(unsafe { libc::lgetxattr(c_path_a.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut _, 3) } < 0);
+	}
+
+	#[cfg(all(feature = "selinux", target_os = "linux"))]
+	#[test]
+	fn test_swap_copied_selinux_context() {
+		extern crate libc;
+		use std::ffi::CString;
+		use std::os::unix::ffi::OsStrExt;
+
+		fn context(path: &Path) -> Option<Vec<u8>> {
+			let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+			let name = CString::new("security.selinux").unwrap();
+			let mut value = vec![0u8; 256];
+			let len = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut _, value.len()) };
+			if len < 0 {
+				return None;
+			}
+			value.truncate(len as usize);
+			Some(value)
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let context_a = context(&path_a);
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		assert_eq!("foo", read_from_file(&path_b));
+		if context_a.is_some() {
+			// SELinux is enabled
+			assert_eq!(context_a, context(&path_b));
+		}
+	}
+
+	#[test]
+	fn test_swap_verbose() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "longer bar");
+		let report = swap_verbose(&path_a, &path_b).unwrap();
+		assert_eq!(0, report.bytes_copied);
+		assert_eq!(Some(3), report.before.a.map(|state| state.len));
+		assert_eq!(Some(10), report.before.b.map(|state| state.len));
+		assert_eq!(report.before.b, report.after.a);
+		assert_eq!(report.before.a, report.after.b);
+		match report.kind {
+			SwapKind::Atomic => assert_eq!((None, None), (report.temp_path, report.fallback)),
+			SwapKind::Nonatomic | SwapKind::Linked => match report.fallback {
+				Some(FallbackReason::AtomicUnsupported { .. }) => assert!(!report.temp_path.unwrap().exists()),
+				fallback => panic!("unexpected fallback: {:?}", fallback),
+			},
+			kind => panic!("unexpected kind: {:?}", kind),
+		}
+		assert_eq!("longer bar", read_from_file(&path_a));
+
+		let report = swap_verbose(&path_a, &path_a).unwrap();
+		assert_eq!(SwapReport { kind: SwapKind::Atomic, bytes_copied: 0, sparse_files: 0, retries: 0, fallback: None, temp_path: None, before: report.before, after: report.before }, report);
+
+		let entries = validate::check(&path_a, &path_b).unwrap();
+		let report = swap_strategy_with(&StdFs, &path_a, &path_b, &entries, &SwapOptions::new().atomic(false)).unwrap();
+		assert_eq!(Some(FallbackReason::Disabled), report.fallback);
+		assert_eq!("foo", read_from_file(&path_a));
+		let unsupported = FallbackReason::AtomicUnsupported { error: io::Error::from_raw_os_error(38).to_string() };
+		assert!(unsupported.to_string().starts_with("atomic swaps unsupported: "));
+	}
+
+	#[test]
+	fn test_swap_invalid_paths() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		write_to_file(&path_a, "foo");
+		for path in &[Path::new(""), Path::new("file\0b")] {
+			assert_eq!(io::ErrorKind::InvalidInput, swap(&path_a, path).unwrap_err().kind());
+			assert_eq!(io::ErrorKind::InvalidInput, swap_nonatomic(path, &path_a).unwrap_err().kind());
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_nested_dirs() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("dir_a");
+		let path_b = path_a.join("dir_b");
+		fs::create_dir_all(&path_b).unwrap();
+		write_to_file(path_b.join("file"), "foo");
+
+		assert_eq!(io::ErrorKind::InvalidInput, swap(&path_a, &path_b).unwrap_err().kind());
+		assert_eq!(io::ErrorKind::InvalidInput, swap_nonatomic(&path_b, &path_a).unwrap_err().kind());
+		// `..` is resolved
+		let spelled = path_b.join("..").join("..").join("dir_a");
+		assert_eq!(io::ErrorKind::InvalidInput, swap(&path_b, &spelled).unwrap_err().kind());
+		assert_eq!("foo", read_from_file(path_b.join("file")));
+		assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::symlink;
+
+			// the parent of b resolves inside a
+			let link = dir.path().join("link");
+			symlink(&path_a, &link).unwrap();
+			assert_eq!(io::ErrorKind::InvalidInput, swap(link.join("dir_b"), &path_a).unwrap_err().kind());
+			assert_eq!(io::ErrorKind::InvalidInput, swap(&path_a, link.join("dir_b")).unwrap_err().kind());
+			assert_eq!("foo", read_from_file(path_b.join("file")));
+		}
+	}
+
+	#[test]
+	fn test_plan() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		assert_eq!(SwapPlan::Nothing, plan(&path_a, &path_a).unwrap());
+		match plan(&path_a, &path_b).unwrap() {
+			SwapPlan::Atomic => assert_eq!(SwapKind::Atomic, swap_auto(&path_a, &path_b).unwrap()),
+			SwapPlan::Linked { temp_path } => {
+				assert_eq!(Some(dir.path()), temp_path.parent());
+				assert_eq!(SwapKind::Linked, swap_auto(&path_a, &path_b).unwrap());
+			},
+			plan => panic!("unexpected plan: {:?}", plan),
+		}
+		match plan::plan_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).unwrap() {
+			SwapPlan::Nonatomic { temp_path } => assert_eq!(Some(dir.path()), temp_path.parent()),
+			plan => panic!("unexpected plan: {:?}", plan),
+		}
+		swap_auto(&path_a, &path_b).unwrap();
+		match plan(&path_a, dir.path().join("file_c")) {
+			Err(SwapError::NotFound { which: Side::B }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		let path_c = dir.path().join("file_c");
+		let options = SwapOptions::new().allow_missing(true);
+		assert_eq!(SwapPlan::Moved { from: path_a.clone(), to: path_c.clone() }, plan::plan_with(&path_a, &path_c, &options).unwrap());
+		assert_eq!(SwapPlan::Moved { from: path_b.clone(), to: path_c.clone() }, plan::plan_with(&path_c, &path_b, &options).unwrap());
+		let options = SwapOptions::new().create_missing(true);
+		assert_eq!(SwapPlan::Created { which: Side::A }, plan::plan_with(&path_c, &path_b, &options).unwrap());
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[cfg(any(target_os = "linux", target_os = "macos"))]
+	#[test]
+	fn test_swap_fds() {
+		use super::swap_fds;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("file_c");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let file_a = fs::File::open(&path_a).unwrap();
+		let file_b = fs::File::open(&path_b).unwrap();
+		fs::rename(&path_a, &path_c).unwrap();
+		write_to_file(&path_a, "baz");
+		swap_fds(&file_a, &file_b).unwrap();
+		assert_eq!("baz", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_c));
+		assert_eq!("foo", read_from_file(&path_b));
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_hardened() {
+		use std::os::unix::fs::symlink;
+		use super::swap_hardened;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		if !atomic_swap_supported(&path_a, &path_b).unwrap() {
+			return;
+		}
+		swap_hardened(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+
+		let link = dir.path().join("link");
+		symlink(dir.path(), &link).unwrap();
+		assert!(swap_hardened(link.join("file_a"), &path_b).is_err());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+
+		match swap_hardened(&path_a, dir.path().join("missing")) {
+			Err(SwapError::NotFound { which: Side::B }) => (),
+			result => panic!("expected NotFound for b, got {:?}", result),
+		}
+
+		let linked = dir.path().join("linked");
+		fs::hard_link(&path_a, &linked).unwrap();
+		swap_hardened(&path_a, &linked).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_dir_swapper() {
+		use super::DirSwapper;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		if !atomic_swap_supported(&path_a, &path_b).unwrap() {
+			return;
+		}
+
+		let swapper = DirSwapper::open(dir.path()).unwrap();
+		swapper.swap("file_a", "file_b").unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		swapper.swap("file_b", "file_a").unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+
+		for name in &["", ".", "..", "file_a/", "sub/file_a", "/file_a"] {
+			assert!(swapper.swap(name, "file_b").is_err());
+		}
+		match swapper.swap("file_a", "file_c") {
+			Err(SwapError::NotFound { which: Side::B }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_at() {
+		use super::swap_at;
+
+		let dir = TempDir::new("").unwrap();
+		let dir_a = dir.path().join("dir_a");
+		let dir_b = dir.path().join("dir_b");
+		fs::create_dir(&dir_a).unwrap();
+		fs::create_dir(&dir_b).unwrap();
+		write_to_file(dir_a.join("file_a"), "foo");
+		write_to_file(dir_b.join("file_b"), "bar");
+		if !atomic_swap_supported(&dir_a, &dir_b).unwrap() {
+			return;
+		}
+
+		let file_a = fs::File::open(&dir_a).unwrap();
+		let file_b = fs::File::open(&dir_b).unwrap();
+		swap_at(&file_a, "file_a", &file_b, "file_b").unwrap();
+		assert_eq!("bar", read_from_file(dir_a.join("file_a")));
+		assert_eq!("foo", read_from_file(dir_b.join("file_b")));
+
+		// the opened directories are used even if their paths are replaced
+		fs::rename(&dir_a, dir.path().join("dir_c")).unwrap();
+		swap_at(&file_a, "file_a", &file_b, "file_b").unwrap();
+		assert_eq!("foo", read_from_file(dir.path().join("dir_c").join("file_a")));
+		assert_eq!("bar", read_from_file(dir_b.join("file_b")));
+
+		assert!(swap_at(&file_a, "../file_a", &file_b, "file_b").is_err());
+		match swap_at(&file_a, "file_a", &file_b, "file_c") {
+			Err(SwapError::NotFound { which: Side::B }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+
+		let other = match TempDir::new_in("/dev/shm", "") {
+			Ok(other) => other,
+			Err(_) => return,
+		};
+		if platform::same_device(dir.path(), other.path()).unwrap() {
+			return;
+		}
+		write_to_file(other.path().join("file_c"), "baz");
+		match swap_at(&file_a, "file_a", &fs::File::open(other.path()).unwrap(), "file_c") {
+			Err(err) => assert_eq!(io::ErrorKind::Unsupported, err.kind()),
+			result => panic!("unexpected result: {:?}", result),
+		}
+	}
+
+	#[test]
+	fn test_renames_reverted_on_drop() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("file_c");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		{
+			let mut renames = Renames::new(&StdFs);
+			renames.rename(&path_a, &path_c).unwrap();
+			renames.rename(&path_b, &path_a).unwrap();
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_dir_contents() {
+		let dir = TempDir::new("").unwrap();
+		let dir_a = dir.path().join("a");
+		let dir_b = dir.path().join("b");
+		fs::create_dir(&dir_a).unwrap();
+		fs::create_dir(&dir_b).unwrap();
+		write_to_file(dir_a.join("file_a"), "foo");
+		write_to_file(dir_a.join("file_c"), "baz");
+		write_to_file(dir_b.join("file_b"), "bar");
+		swap_dir_contents(&dir_a, &dir_b).unwrap();
+		assert_eq!("bar", read_from_file(dir_a.join("file_b")));
+		assert_eq!("foo", read_from_file(dir_b.join("file_a")));
+		assert_eq!("baz", read_from_file(dir_b.join("file_c")));
+		assert_eq!(1, fs::read_dir(&dir_a).unwrap().count());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		match contents::swap_dir_contents_in(&FailingFs::new(&[false, false, false, true]), &dir_a, &dir_b) {
+			Err(SwapError::RolledBack(_)) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("bar", read_from_file(dir_a.join("file_b")));
+		assert_eq!(1, fs::read_dir(&dir_a).unwrap().count());
+		assert_eq!(2, fs::read_dir(&dir_b).unwrap().count());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_dir_contents_mount_point() {
+		let dir = TempDir::new("").unwrap();
+		let source = dir.path().join("source");
+		let dir_a = dir.path().join("a");
+		let dir_b = dir.path().join("b");
+		for path in &[&source, &dir_a, &dir_b] {
+			fs::create_dir(path).unwrap();
+		}
+		write_to_file(source.join("file_a"), "foo");
+		write_to_file(dir_b.join("file_b"), "bar");
+		let _mount = match BindMount::new(&source, &dir_a) {
+			Some(mount) => mount,
+			None => return,
+		};
+
+		// the entries of the mount point are moved within it, but not to `b`, in another mount
+		assert!(matches!(swap_dir_contents(&dir_a, &dir_b), Err(SwapError::CrossDevice)));
+		assert!(matches!(swap_dir_contents_dedup(&dir_a, &dir_b), Err(SwapError::CrossDevice)));
+		for (dir, file) in &[(&dir_a, "file_a"), (&dir_b, "file_b")] {
+			let entries: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+			assert_eq!(vec![dir.join(file)], entries);
+		}
+		assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_dir_contents_dedup() {
+		let dir = TempDir::new("").unwrap();
+		let dir_a = dir.path().join("a");
+		let dir_b = dir.path().join("b");
+		fs::create_dir_all(dir_a.join("sub")).unwrap();
+		fs::create_dir_all(dir_b.join("sub")).unwrap();
+		write_to_file(dir_a.join("same"), "foo");
+		write_to_file(dir_b.join("same"), "foo");
+		write_to_file(dir_a.join("sub").join("changed"), "bar");
+		write_to_file(dir_b.join("sub").join("changed"), "baz");
+		write_to_file(dir_a.join("sub").join("same"), "qux");
+		write_to_file(dir_b.join("sub").join("same"), "qux");
+		write_to_file(dir_a.join("only_a"), "a");
+		fs::create_dir(dir_b.join("only_b")).unwrap();
+		write_to_file(dir_b.join("only_b").join("file"), "b");
+
+		let report = swap_dir_contents_dedup(&dir_a, &dir_b).unwrap();
+		assert_eq!(vec![Path::new("only_a"), Path::new("only_b"), Path::new("sub/changed")], report.exchanged);
+		assert_eq!(vec![Path::new("same"), Path::new("sub/same")], report.skipped);
+		assert_eq!("baz", read_from_file(dir_a.join("sub").join("changed")));
+		assert_eq!("bar", read_from_file(dir_b.join("sub").join("changed")));
+		assert_eq!("b", read_from_file(dir_a.join("only_b").join("file")));
+		assert_eq!("a", read_from_file(dir_b.join("only_a")));
+		assert!(!dir_a.join("only_a").exists());
+		assert!(!dir_b.join("only_b").exists());
+		assert_eq!("foo", read_from_file(dir_a.join("same")));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		let report = swap_dir_contents_dedup(&dir_a, &dir_a).unwrap();
+		assert!(report.exchanged.is_empty() && report.skipped.is_empty());
+	}
+
+	#[test]
+	fn test_retry_backoff() {
+		let clock = MockClock::default();
+		let retry = Retry::new(3, Duration::from_millis(10), |_| true);
+		let (result, retried) = retry.run_counted_with(&clock, || Err::<(), _>(io::Error::other("transient")));
+		assert!(result.is_err());
+		assert_eq!(3, retried);
+		assert_eq!(vec![Duration::from_millis(10), Duration::from_millis(20), Duration::from_millis(40)], *clock.waits.borrow());
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let clock = MockClock::default();
+		let failing = FailingFs::new(&[true, true, false, true, false]);
+		nonatomic_in(&RetryingFs::with_clock(&failing, retry, &clock), &path_a, &path_b).unwrap();
+		assert_eq!(vec![Duration::from_millis(10), Duration::from_millis(20), Duration::from_millis(10)], *clock.waits.borrow());
+		assert_eq!("bar", read_from_file(&path_a));
+	}
+
+	#[test]
+	fn test_swap_nonatomic_retries() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let retry = Retry::new(2, Duration::from_millis(1), |_| true);
+		let failing = FailingFs::new(&[true, true, false, true, true, true]);
+		assert!(nonatomic_in(&RetryingFs::new(&failing, retry), &path_a, &path_b).is_err());
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		let failing = FailingFs::new(&[true, true, false, true, false]);
+		let fs = RetryingFs::new(&failing, retry);
+		nonatomic_in(&fs, &path_a, &path_b).unwrap();
+		assert_eq!(3, fs.retried());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		let retry = Retry::new(2, Duration::from_millis(1), |_| false);
+		let failing = FailingFs::new(&[true]);
+		assert!(nonatomic_in(&RetryingFs::new(&failing, retry), &path_a, &path_b).is_err());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_capabilities() {
+		let dir = TempDir::new("").unwrap();
+		let path = dir.path().join("file");
+		write_to_file(&path, "foo");
+		let capabilities = capabilities(dir.path()).unwrap();
+		assert!(capabilities.same_device_rename);
+		assert_eq!(capabilities, super::capabilities(&path).unwrap());
+		assert_eq!(capabilities.atomic_exchange, atomic_swap_supported(&path, &path).unwrap());
+		assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_fifos() {
+		extern crate libc;
+		use std::ffi::CString;
+		use std::os::unix::ffi::OsStrExt;
+		use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+		fn mode(path: &Path) -> u32 {
+			fs::symlink_metadata(path).unwrap().permissions().mode() & 0o777
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("fifo_a");
+		let path_b = dir.path().join("fifo_b");
+		for &(path, mode) in &[(&path_a, 0o600u32), (&path_b, 0o640)] {
+			let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+			assert_eq!(0, unsafe { libc::mkfifo(c_path.as_ptr(), mode as libc::mode_t) });
+			fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+		}
+		swap(&path_a, &path_b).unwrap();
+		assert_eq!(0o640, mode(&path_a));
+		swap_durable(&path_a, &path_b).unwrap();
+		assert_eq!(0o600, mode(&path_a));
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		assert_eq!(0o640, mode(&path_a));
+		assert_eq!(0o600, mode(&path_b));
+		assert!(fs::symlink_metadata(&path_a).unwrap().file_type().is_fifo());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_then() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		swap_then(&path_a, &path_b, || {
+			assert_eq!("bar", read_from_file(&path_a));
+			Ok(())
+		}).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		match swap_then(&path_a, &path_b, || Err(io::Error::other("reload failed"))) {
+			Err(SwapError::RolledBack(ref err)) => assert_eq!("reload failed", err.to_string()),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+	}
+
+	#[test]
+	fn test_batch_duplicate_paths() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("file_c");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		write_to_file(&path_c, "baz");
+		let path_a2 = dir.path().join(".").join("file_a");
+		let err = rotate(&[&path_a, &path_b, &path_a2]).unwrap_err();
+		assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+		match swap_many(&[(&path_a, &path_b), (&path_c, &path_a2)]) {
+			Err((1, ref err)) if err.kind() == io::ErrorKind::InvalidInput => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		#[cfg(unix)]
+		{
+			let link = dir.path().join("link");
+			::std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+			assert!(swap3(&path_a, &path_b, link.join("file_b")).is_err());
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		assert_eq!("baz", read_from_file(&path_c));
+	}
+
+	#[test]
+	fn test_swap_allow_missing() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_b, "bar");
+		let options = SwapOptions::new().allow_missing(true);
+		swap_with(&path_a, &path_b, &options).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert!(!path_b.exists());
+		swap_with(&path_a, &path_b, &options).unwrap();
+		assert_eq!("bar", read_from_file(&path_b));
+		assert!(!path_a.exists());
+		match swap_with(&path_a, dir.path().join("file_c"), &options) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		match swap_with(&path_a, &path_b, &SwapOptions::new()) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+	}
+
+	#[test]
+	fn test_move_or_swap() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_b, "bar");
+		assert_eq!(MoveOrSwap::Moved { from: Side::B, kind: SwapKind::Atomic }, move_or_swap(&path_a, &path_b).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert!(!path_b.exists());
+
+		write_to_file(&path_b, "foo");
+		assert_eq!(MoveOrSwap::Swapped(SwapKind::Atomic), move_or_swap(&path_a, &path_b).unwrap());
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+
+		fs::remove_file(&path_b).unwrap();
+		fs::create_dir(&path_b).unwrap();
+		match platform::rename_noreplace(&path_a, &path_b) {
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		match move_or_swap(dir.path().join("file_c"), dir.path().join("file_d")) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+
+		// a copy is not moved over a path created during the copy
+		match copy::move_copied(&path_a, &path_b, &mut CopyControl::default()) {
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert!(path_b.is_dir());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_rename_exclusive() {
+		use filesystem::rename_exclusive;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let path_c = dir.path().join("file_c");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		// an existing file is never replaced
+		match rename_exclusive(&path_a, &path_b) {
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+
+		rename_exclusive(&path_a, &path_c).unwrap();
+		assert!(!path_a.exists());
+		assert_eq!("foo", read_from_file(&path_c));
+
+		let dir_a = dir.path().join("dir_a");
+		fs::create_dir(&dir_a).unwrap();
+		match rename_exclusive(&dir_a, &path_b) {
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		rename_exclusive(&dir_a, &path_a).unwrap();
+		assert!(path_a.is_dir());
+	}
+
+	#[test]
+	fn test_replace() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		assert_eq!(SwapKind::Atomic, replace(&path_a, &path_b).unwrap());
+		assert_eq!("foo", read_from_file(&path_b));
+		assert!(!path_a.exists());
+
+		// a file is renamed over, so it's never missing even without atomic swaps
+		write_to_file(&path_a, "bar");
+		assert_eq!(SwapKind::Atomic, replace_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).unwrap());
+		assert_eq!("bar", read_from_file(&path_b));
+		assert!(!path_a.exists());
+		match replace(&path_a, &path_b) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+
+		// a non-empty directory is replaced too
+		let dir_a = dir.path().join("dir_a");
+		let dir_b = dir.path().join("dir_b");
+		fs::create_dir(&dir_a).unwrap();
+		fs::create_dir(&dir_b).unwrap();
+		write_to_file(dir_a.join("file_a"), "foo");
+		write_to_file(dir_b.join("file_b"), "bar");
+		replace(&dir_a, &dir_b).unwrap();
+		assert_eq!("foo", read_from_file(dir_b.join("file_a")));
+		assert!(!dir_b.join("file_b").exists());
+		assert!(!dir_a.exists());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		// an empty directory is renamed over
+		#[cfg(unix)]
+		{
+			fs::create_dir(&dir_a).unwrap();
+			write_to_file(dir_a.join("file_a"), "bar");
+			fs::remove_dir_all(&dir_b).unwrap();
+			fs::create_dir(&dir_b).unwrap();
+			assert_eq!(SwapKind::Atomic, replace_with(&dir_a, &dir_b, &SwapOptions::new().atomic(false)).unwrap());
+			assert_eq!("bar", read_from_file(dir_b.join("file_a")));
+			assert!(!dir_a.exists());
+		}
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_replace_preserve_permissions() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		fs::set_permissions(&path_b, fs::Permissions::from_mode(0o600)).unwrap();
+		replace_with(&path_a, &path_b, &SwapOptions::new().preserve_permissions(true)).unwrap();
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(0o600, fs::metadata(&path_b).unwrap().permissions().mode() & 0o777);
+		assert!(!path_a.exists());
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_bind_mounts() {
+		let dir = TempDir::new("").unwrap();
+		let source = dir.path().join("source");
+		let mount_a = dir.path().join("mount_a");
+		let mount_b = dir.path().join("mount_b");
+		for path in &[&source, &mount_a, &mount_b] {
+			fs::create_dir(path).unwrap();
+		}
+		write_to_file(source.join("file_a"), "foo");
+		write_to_file(source.join("file_b"), "bar");
+		let mut mounts = Vec::new();
+		for mount in &[&mount_a, &mount_b] {
+			match BindMount::new(&source, mount) {
+				Some(bound) => mounts.push(bound),
+				None => return,
+			}
+		}
+
+		// the mounts, and the paths of a file in each of them, are the same inode
+		swap(&mount_a, &mount_b).unwrap();
+		swap_nonatomic(mount_a.join("file_a"), mount_b.join("file_a")).unwrap();
+		assert_eq!(SwapKind::Atomic, swap_auto(mount_a.join("file_a"), mount_b.join("file_a")).unwrap());
+		assert_eq!("foo", read_from_file(source.join("file_a")));
+
+		// distinct files share the device, but cannot be renamed from one mount to the other
+		assert!(matches!(swap(mount_a.join("file_a"), mount_b.join("file_b")), Err(SwapError::CrossDevice)));
+		assert_eq!(SwapKind::Copied { reflink: false }, swap_auto(mount_a.join("file_a"), mount_b.join("file_b")).unwrap());
+		assert_eq!("bar", read_from_file(source.join("file_a")));
+		assert_eq!("foo", read_from_file(source.join("file_b")));
+		swap_nonatomic(mount_a.join("file_a"), mount_b.join("file_b")).unwrap();
+		assert_eq!("foo", read_from_file(source.join("file_a")));
+		assert_eq!("bar", read_from_file(source.join("file_b")));
+		assert_eq!(2, fs::read_dir(&source).unwrap().count());
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_immutable() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		// requires CAP_LINUX_IMMUTABLE and a filesystem with inode flags
+		if platform::set_protected_flags(&path_b, 0x10).is_err() {
+			return;
+		}
+
+		for result in [swap_nonatomic(&path_a, &path_b), swap_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).map(|_| ())] {
+			match result {
+				Err(SwapError::Io(ref err)) | Err(SwapError::RolledBack(ref err)) if err.kind() == io::ErrorKind::PermissionDenied => {
+					assert!(err.to_string().contains("file_b"));
+				},
+				result => panic!("unexpected result: {:?}", result),
+			}
+		}
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+
+		swap_with(&path_a, &path_b, &SwapOptions::new().atomic(false).clear_immutable(true)).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(0x10, platform::protected_flags(&path_a).unwrap());
+		assert_eq!(0, platform::protected_flags(&path_b).unwrap());
+		platform::set_protected_flags(&path_a, 0).unwrap();
+	}
+
+	#[test]
+	fn test_pending_swap() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let pending = PendingSwap::new(&path_a, &path_b).options(SwapOptions::new().atomic(false));
+		let queued = pending.clone();
+		let kind = ::std::thread::spawn(move || queued.apply()).join().unwrap().unwrap();
+		assert_eq!(SwapKind::Nonatomic, kind);
+		assert_eq!("bar", read_from_file(pending.a()));
+		assert_eq!("foo", read_from_file(pending.b()));
+		pending.apply().unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+	}
+
+	#[test]
+	fn test_swap_sync_data_dirs() {
+		let dir = TempDir::new("").unwrap();
+		let dir_a = dir.path().join("a");
+		let dir_b = dir.path().join("b");
+		fs::create_dir_all(dir_a.join("nested")).unwrap();
+		fs::create_dir(&dir_b).unwrap();
+		write_to_file(dir_a.join("nested").join("file"), "foo");
+		write_to_file(dir_b.join("file"), "bar");
+		swap_with(&dir_a, &dir_b, &SwapOptions::new().sync_data(true)).unwrap();
+		assert_eq!("bar", read_from_file(dir_a.join("file")));
+		assert_eq!("foo", read_from_file(dir_b.join("nested").join("file")));
+	}
+
+	#[cfg(all(feature = "acl", windows))]
+	#[test]
+	fn test_swap_copied_acls() {
+		// the entries listed by `icacls`, without the path starting the first line
+		fn acl(path: &Path) -> Vec<String> {
+			let output = process::Command::new("icacls").arg(path).output().unwrap();
+			let output = String::from_utf8_lossy(&output.stdout).into_owned();
+			let path = path.display().to_string();
+			output.lines()
+				.take_while(|line| !line.trim().is_empty())
+				.map(|line| line.trim_start_matches(path.as_str()).trim().to_owned())
+				.collect()
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("dir_b");
+		write_to_file(&path_a, "foo");
+		fs::create_dir(&path_b).unwrap();
+		write_to_file(path_b.join("file"), "bar");
+
+		// grants reading to the users group, `S-1-5-32-545`
+		let granted = process::Command::new("icacls").arg(&path_a).arg("/grant").arg("*S-1-5-32-545:(R)")
+			.stdout(process::Stdio::null())
+			.status();
+		match granted {
+			Ok(status) if status.success() => (),
+			_ => return,
+		}
+		let acl_a = acl(&path_a);
+		assert!(acl_a.iter().any(|entry| entry.ends_with(":(R)")), "unexpected ACL: {:?}", acl_a);
+
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		assert_eq!("bar", read_from_file(path_a.join("file")));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(acl_a, acl(&path_b));
+	}
+
+	#[cfg(all(feature = "xattr", target_os = "linux"))]
+	#[test]
+	fn test_swap_copied_posix_acls() {
+		// POSIX ACLs are stored in the `system.posix_acl_access` extended attribute
+		fn acl(path: &Path) -> String {
+			let output = process::Command::new("getfacl").arg("--omit-header").arg(path).output().unwrap();
+			String::from_utf8(output.stdout).unwrap()
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		// `setfacl` may be missing, or the filesystem may not support ACLs
+		let set = process::Command::new("setfacl").arg("-m").arg("u:nobody:r").arg(&path_a)
+			.stderr(process::Stdio::null())
+			.status();
+		match set {
+			Ok(status) if status.success() => (),
+			_ => return,
+		}
+		let acl_a = acl(&path_a);
+		assert!(acl_a.contains("user:nobody:r--"), "unexpected ACL: {}", acl_a);
+
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(acl_a, acl(&path_b));
+		assert!(!acl(&path_a).contains("user:nobody"));
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_with_require_atomic() {
+		let dir_a = TempDir::new("a").unwrap();
+		let dir_b = match TempDir::new_in("/dev/shm", "b") {
+			Ok(dir) => dir,
+			Err(_) => return,
+		};
+		// no other filesystem available
+		if platform::same_device(dir_a.path(), dir_b.path()).unwrap() {
+			return;
+		}
+		let path_a = dir_a.path().join("file");
+		let path_b = dir_b.path().join("file");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		let options = SwapOptions::new().require_atomic(true);
+		assert!(matches!(swap_with(&path_a, &path_b, &options), Err(SwapError::CrossDevice)));
+		assert!(matches!(plan::plan_with(&path_a, &path_b, &options), Err(SwapError::CrossDevice)));
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+	}
+
+	#[test]
+	fn test_swap_nonatomic_files_named_like_tmp() {
+		use std::ffi::OsString;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		// the first names generated are the swapped paths themselves
+		let names = ["file_a", "file_b", "swap.tmp"];
+		let next = AtomicUsize::new(0);
+		let options = SwapOptions::new().temp_name_fn(move || OsString::from(names[next.fetch_add(1, Ordering::Relaxed)])).atomic(false);
+		assert_eq!(SwapKind::Nonatomic, swap_with(&path_a, &path_b, &options).unwrap());
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_overwrite_from_read_only() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("dir_b");
+		let backup = dir.path().join("backup");
+		write_to_file(&path_a, "foo");
+		fs::create_dir(&path_b).unwrap();
+		write_to_file(path_b.join("file"), "bar");
+		assert_eq!(None, overwrite::read_only_side(&path_a, &path_b).unwrap());
+		assert!(swap_with(&path_a, &path_b, &SwapOptions::new().overwrite_from_read_only(true)).is_err());
+
+		overwrite::overwrite(&StdFs, &path_a, &path_b, Some(&backup), &mut CopyControl::default()).unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!("bar", read_from_file(backup.join("file")));
+		assert!(overwrite::overwrite(&StdFs, &path_a, &path_b, Some(&backup), &mut CopyControl::default()).is_err());
+
+		overwrite::overwrite(&StdFs, &backup, &path_b, None, &mut CopyControl::default()).unwrap();
+		assert_eq!("bar", read_from_file(path_b.join("file")));
+		assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_contents() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "longer bar");
+		let file_a = fs::File::open(&path_a).unwrap();
+		swap_contents(&path_a, &path_b).unwrap();
+		assert_eq!("longer bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		// the open file still refers to `a`
+		let mut content = String::new();
+		(&file_a).read_to_string(&mut content).unwrap();
+		assert_eq!("longer bar", content);
+
+		fs::create_dir(dir.path().join("dir")).unwrap();
+		assert!(swap_contents(&path_a, dir.path().join("dir")).is_err());
+		assert!(matches!(swap_contents(&path_a, dir.path().join("file_c")), Err(SwapError::NotFound { which: Side::B })));
+		assert_eq!("longer bar", read_from_file(&path_a));
+	}
+
+	#[test]
+	fn test_swap_contents_rollback() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "longer bar");
+
+		// the rewrites are the backup of a, a and then b, which fails after truncating b
+		let mut rewrites = 0;
+		let result = inplace::swap_contents_in(&path_a, &path_b, |to, from| {
+			rewrites += 1;
+			if rewrites == 3 {
+				to.set_len(0)?;
+				return Err(io::Error::other("injected rewrite failure"));
+			}
+			inplace::rewrite(to, from)
+		});
+		match result {
+			Err(SwapError::RolledBack(_)) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!(b"foo".to_vec(), fs::read(&path_a).unwrap());
+		assert_eq!(b"longer bar".to_vec(), fs::read(&path_b).unwrap());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		// restoring b from a fails too, so a holds the only copy of the data of b
+		let mut rewrites = 0;
+		let result = inplace::swap_contents_in(&path_a, &path_b, |to, from| {
+			rewrites += 1;
+			if rewrites >= 3 {
+				to.set_len(0)?;
+				return Err(io::Error::other("injected rewrite failure"));
+			}
+			inplace::rewrite(to, from)
+		});
+		let state = match result {
+			Err(SwapError::Corrupted { state, .. }) => state,
+			result => panic!("unexpected result: {:?}", result),
+		};
+		let tmp = state.temp_path.clone().unwrap();
+		assert_eq!(vec![(path_a.clone(), tmp.clone()), (path_b.clone(), path_a.clone())], state.moved);
+		assert_eq!(b"foo".to_vec(), fs::read(&tmp).unwrap());
+		assert_eq!(b"longer bar".to_vec(), fs::read(&path_a).unwrap());
+		assert_eq!(b"".to_vec(), fs::read(&path_b).unwrap());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_non_utf8_paths() {
+		use std::ffi::OsStr;
+		use std::os::unix::ffi::OsStrExt;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join(OsStr::from_bytes(b"dir_\xff"));
+		let path_b = dir.path().join(OsStr::from_bytes(b"dir_\xfe"));
+		fs::create_dir(&path_a).unwrap();
+		fs::create_dir(&path_b).unwrap();
+		write_to_file(path_a.join(OsStr::from_bytes(b"file_\xfc")), "foo");
+		write_to_file(path_b.join(OsStr::from_bytes(b"file_\xfd")), "bar");
+
+		swap(&path_a, &path_b).unwrap();
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		swap_dir_contents(&path_a, &path_b).unwrap();
+		assert_eq!("foo", read_from_file(path_a.join(OsStr::from_bytes(b"file_\xfc"))));
+		assert_eq!("bar", read_from_file(path_b.join(OsStr::from_bytes(b"file_\xfd"))));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_copied_cancelled() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("dir_a");
+		let path_b = dir.path().join("dir_b");
+		fs::create_dir(&path_a).unwrap();
+		fs::create_dir(&path_b).unwrap();
+		write_to_file(path_a.join("file"), "foo");
+		write_to_file(path_b.join("file"), "bar");
+
+		let cancel = Arc::new(AtomicBool::new(true));
+		let options = SwapOptions::new().cancel_flag(cancel.clone());
+		match copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::new(&options)) {
+			Err(SwapError::Cancelled) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(path_a.join("file")));
+		assert_eq!("bar", read_from_file(path_b.join("file")));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		cancel.store(false, Ordering::Relaxed);
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::new(&options)).unwrap();
+		assert_eq!("bar", read_from_file(path_a.join("file")));
+		assert_eq!("foo", read_from_file(path_b.join("file")));
+	}
+
+	#[test]
+	fn test_swap_copied_progress() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		fs::write(&path_a, vec![1; 3 * 1024 * 1024 + 1]).unwrap();
+		write_to_file(&path_b, "bar");
+
+		let reports = Arc::new(Mutex::new(Vec::new()));
+		let recorded = reports.clone();
+		let options = SwapOptions::new().progress(move |done, total| recorded.lock().unwrap().push((done, total)));
+		swap_with(&path_a, &path_b, &options).unwrap();
+		assert!(reports.lock().unwrap().is_empty());
+
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::new(&options)).unwrap();
+		let reports = reports.lock().unwrap();
+		let total = 3 * 1024 * 1024 + 4;
+		assert!(reports.len() >= 2);
+		assert!(reports.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+		assert!(reports.iter().all(|&(_, report_total)| report_total == total));
+		assert_eq!(Some(&(total, total)), reports.last());
+		assert_eq!("bar", read_from_file(&path_b));
+	}
+
+	#[test]
+	fn test_swap_empty_dir_with_populated_dir() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("dir_a");
+		let path_b = dir.path().join("dir_b");
+		fs::create_dir(&path_a).unwrap();
+		fs::create_dir_all(path_b.join("sub")).unwrap();
+		write_to_file(path_b.join("sub").join("file"), "foo");
+
+		let check = |empty: &Path, populated: &Path| {
+			assert_eq!(0, fs::read_dir(empty).unwrap().count());
+			assert_eq!("foo", read_from_file(populated.join("sub").join("file")));
+			assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+		};
+		swap(&path_a, &path_b).unwrap();
+		check(&path_b, &path_a);
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		check(&path_a, &path_b);
+		swap_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).unwrap();
+		check(&path_b, &path_a);
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		check(&path_a, &path_b);
+		swap_dir_contents(&path_a, &path_b).unwrap();
+		check(&path_b, &path_a);
+	}
+
+	#[test]
+	fn test_copy_to_existing_tmp() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("dir_a");
+		let tmp = dir.path().join("tmp");
+		fs::create_dir(&path_a).unwrap();
+		fs::create_dir(&tmp).unwrap();
+		write_to_file(tmp.join("file"), "foo");
+
+		// the temp path was not created by the copy, so it is not removed
+		match copy::copy_to_tmp(&StdFs, &path_a, &tmp, &mut copy::CopyStats::default(), &mut CopyControl::default()) {
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(tmp.join("file")));
+
+		// nor is a file, which is not overwritten either
+		let path_a = dir.path().join("file_a");
+		let tmp = dir.path().join("tmp_file");
+		write_to_file(&path_a, "bar");
+		write_to_file(&tmp, "foo");
+		match copy::copy_to_tmp(&StdFs, &path_a, &tmp, &mut copy::CopyStats::default(), &mut CopyControl::default()) {
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!("foo", read_from_file(&tmp));
+	}
+
+	#[test]
+	fn test_swap_copied_buffer_size() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		fs::write(&path_a, vec![1; 64 * 1024]).unwrap();
+		write_to_file(&path_b, "bar");
+
+		let reports = Arc::new(AtomicUsize::new(0));
+		let recorded = reports.clone();
+		// raised to 4 KiB
+		let options = SwapOptions::new().copy_buffer_size(1).progress(move |_, _| { recorded.fetch_add(1, Ordering::Relaxed); });
+
+		// files may be cloned rather than copied chunk by chunk, so the chunks are counted on a
+		// copied stream
+		let mut copied = Vec::new();
+		let len = copy::copy_stream(&mut io::Cursor::new(vec![1; 64 * 1024]), &mut copied, &mut CopyControl::new(&options)).unwrap();
+		assert_eq!(64 * 1024, len);
+		assert_eq!(16, reports.load(Ordering::Relaxed));
+		assert_eq!(vec![1; 64 * 1024], copied);
+
+		copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::new(&options)).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!(vec![1; 64 * 1024], fs::read(&path_b).unwrap());
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_copied_sparse() {
+		use std::io::{Seek, SeekFrom, Write};
+		use std::os::unix::fs::MetadataExt;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		let len = 64 * 1024 * 1024;
+		let mut file = fs::File::create(&path_a).unwrap();
+		file.seek(SeekFrom::Start(len / 2)).unwrap();
+		file.write_all(b"foo").unwrap();
+		file.set_len(len).unwrap();
+		write_to_file(&path_b, "bar");
+		// the filesystem may not create holes
+		if fs::metadata(&path_a).unwrap().blocks() * 512 >= len {
+			return;
+		}
+
+		let stats = copy::swap_copied(&StdFs, &path_a, &path_b, &mut CopyControl::default()).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		let metadata = fs::metadata(&path_b).unwrap();
+		assert_eq!(len, metadata.len());
+		if stats.reflinked_files == 1 {
+			// cloned
+			return;
+		}
+		assert_eq!(1, stats.sparse_files);
+		assert!(stats.copied_bytes < len);
+		assert!(metadata.blocks() * 512 < len);
+		let content = fs::read(&path_b).unwrap();
+		assert_eq!(b"foo", &content[len as usize / 2..len as usize / 2 + 3]);
+		assert!(content[..len as usize / 2].iter().all(|&byte| byte == 0));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_nonatomic_sticky_dir() {
+		extern crate libc;
+		use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+
+		let dir = TempDir::new("").unwrap();
+		fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o1777)).unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		// only a privileged user can give a file to another user
+		if fs::metadata(&path_a).unwrap().uid() != 0 {
+			return;
+		}
+		fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+		chown(dir.path(), Some(65534), None).unwrap();
+		fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o1777)).unwrap();
+		chown(&path_b, Some(65534), None).unwrap();
+		// root bypasses the sticky bit, so the rename fails like it would for another user
+		match nonatomic_in(&FailingFs::with_os_error(&[true], libc::EPERM), &path_a, &path_b) {
+			Err(err @ SwapError::Io(_)) => {
+				assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+				assert!(err.to_string().contains("sticky bit"), "{}", err);
+			},
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_swap_hardlinks() {
+		use std::os::unix::fs::MetadataExt;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		fs::hard_link(&path_a, &path_b).unwrap();
+		let inode = fs::metadata(&path_a).unwrap().ino();
+
+		swap(&path_a, &path_b).unwrap();
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!(SwapKind::Atomic, swap_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).unwrap());
+		for path in &[&path_a, &path_b] {
+			assert_eq!("foo", read_from_file(path));
+			assert_eq!(inode, fs::metadata(path).unwrap().ino());
+		}
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_exchange() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		match exchange(&path_a, &path_b) {
+			Ok(_) => {
+				assert_eq!("bar", read_from_file(&path_a));
+				assert_eq!("foo", read_from_file(&path_b));
+			},
+			Err(ref err) if err.kind() == io::ErrorKind::Unsupported => {
+				assert_eq!("foo", read_from_file(&path_a));
+				assert_eq!("bar", read_from_file(&path_b));
+			},
+			Err(err) => panic!("unexpected error: {}", err),
+		}
+		assert!(exchange(&path_a, dir.path().join("file_c")).is_err());
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[test]
+	fn test_swap_trailing_separators() {
+		use std::path::MAIN_SEPARATOR_STR;
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("dir_a");
+		let path_b = dir.path().join("dir_b");
+		fs::create_dir(&path_a).unwrap();
+		fs::create_dir(&path_b).unwrap();
+		write_to_file(path_a.join("file_a"), "foo");
+		write_to_file(path_b.join("file_b"), "bar");
+		let trailing = |path: &Path| {
+			let mut path = path.as_os_str().to_owned();
+			path.push(MAIN_SEPARATOR_STR);
+			path.push(MAIN_SEPARATOR_STR);
+			path
+		};
+
+		swap(trailing(&path_a), trailing(&path_b)).unwrap();
+		assert_eq!("bar", read_from_file(path_a.join("file_b")));
+		swap_nonatomic(trailing(&path_a), &path_b).unwrap();
+		assert_eq!("foo", read_from_file(path_a.join("file_a")));
+		swap_with(&path_a, trailing(&path_b), &SwapOptions::new().atomic(false)).unwrap();
+		assert_eq!("bar", read_from_file(path_a.join("file_b")));
+		assert_eq!("foo", read_from_file(path_b.join("file_a")));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+		for path in &[path_a.join("."), path_a.join(".."), Path::new(&trailing(&path_a.join(".."))).to_path_buf()] {
+			match swap(path, &path_b) {
+				Err(SwapError::Io(ref err)) if err.kind() == io::ErrorKind::InvalidInput => (),
+				result => panic!("unexpected result: {:?}", result),
+			}
+		}
+		assert_eq!("bar", read_from_file(path_a.join("file_b")));
+		assert_eq!("foo", read_from_file(path_b.join("file_a")));
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn test_swap_junctions() {
+		let dir = TempDir::new("").unwrap();
+		let target_a = dir.path().join("target_a");
+		let target_b = dir.path().join("target_b");
+		fs::create_dir(&target_a).unwrap();
+		fs::create_dir(&target_b).unwrap();
+		write_to_file(target_a.join("file_a"), "foo");
+		write_to_file(target_b.join("file_b"), "bar");
+		let path_a = dir.path().join("junction_a");
+		let path_b = dir.path().join("junction_b");
+		for (junction, target) in &[(&path_a, &target_a), (&path_b, &target_b)] {
+			let status = process::Command::new("cmd")
+				.arg("/C").arg("mklink").arg("/J").arg(junction).arg(target)
+				.stdout(process::Stdio::null())
+				.status()
+				.unwrap();
+			assert!(status.success());
+		}
+
+		swap(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(path_a.join("file_b")));
+		assert_eq!("foo", read_from_file(path_b.join("file_a")));
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!("foo", read_from_file(path_a.join("file_a")));
+		assert_eq!("bar", read_from_file(path_b.join("file_b")));
+		for (target, name) in &[(&target_a, "file_a"), (&target_b, "file_b")] {
+			let entries: Vec<_> = fs::read_dir(target).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+			assert_eq!(vec![::std::ffi::OsString::from(name)], entries);
+		}
+		assert_eq!(4, fs::read_dir(dir.path()).unwrap().count());
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn test_swap_device_paths() {
+		let dir = TempDir::new("").unwrap();
+		let path = dir.path().join("file");
+		write_to_file(&path, "foo");
+
+		for device in &[r"\\.\PhysicalDrive0", r"\\.\pipe\fs_swap", r"\\?\GLOBALROOT\Device\Null", "NUL", "com1.txt"] {
+			let device = dir.path().join(device);
+			for (a, b) in &[(&device, &path), (&path, &device)] {
+				match swap_nonatomic(a, b) {
+					Err(SwapError::Io(ref err)) => assert_eq!(io::ErrorKind::Unsupported, err.kind()),
+					result => panic!("unexpected result for {:?}: {:?}", device, result),
+				}
+			}
+		}
+		assert_eq!("foo", read_from_file(&path));
+
+		// a drive reached through the device namespace holds files
+		let other = dir.path().join("other");
+		write_to_file(&other, "bar");
+		let device_path = ::std::path::PathBuf::from(format!(r"\\.\{}", path.display()));
+		swap_nonatomic(&device_path, &other).unwrap();
+		assert_eq!("bar", read_from_file(&path));
+		assert_eq!("foo", read_from_file(&other));
+	}
+
+	#[cfg(feature = "testing")]
+	#[test]
+	fn test_testing_helpers() {
+		use testing::{assert_contents, assert_swapped, swap_roundtrip};
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("dir_a");
+		let path_b = dir.path().join("file_b");
+		fs::create_dir(&path_a).unwrap();
+		write_to_file(path_a.join("file"), "foo");
+		write_to_file(&path_b, "bar");
+		swap_roundtrip(path_a.join("file"), &path_b);
+		assert_contents(&path_b, "bar");
+
+		let path_c = dir.path().join("dir_c");
+		fs::create_dir(&path_c).unwrap();
+		swap_roundtrip(&path_a, &path_c);
+		swap(path_a.join("file"), &path_b).unwrap();
+		assert_swapped(path_a.join("file"), &path_b, "bar", "foo");
+	}
+
+	#[cfg(feature = "metrics")]
+	#[test]
+	fn test_swap_metrics() {
+		use std::collections::HashMap;
+		use std::sync::atomic::AtomicU64;
+		use metrics::{self, Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+
+		#[derive(Default)]
+		struct Counters(Mutex<HashMap<String, Arc<Count>>>);
+
+		#[derive(Default)]
+		struct Count(AtomicU64);
+
+		impl CounterFn for Count {
+			fn increment(&self, value: u64) {
+				self.0.fetch_add(value, Ordering::Relaxed);
+			}
+
+			fn absolute(&self, value: u64) {
+				self.0.store(value, Ordering::Relaxed);
+			}
+		}
+
+		impl Recorder for Counters {
+			fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+			fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+			fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+			fn register_counter(&self, key: &Key, _: &Metadata) -> Counter {
+				let count = self.0.lock().unwrap().entry(key.name().to_owned()).or_default().clone();
+				Counter::from_arc(count)
+			}
+
+			fn register_gauge(&self, _: &Key, _: &Metadata) -> Gauge {
+				Gauge::noop()
+			}
+
+			fn register_histogram(&self, _: &Key, _: &Metadata) -> Histogram {
+				Histogram::noop()
+			}
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		let counters = Counters::default();
+		metrics::with_local_recorder(&counters, || {
+			swap_with(&path_a, &path_b, &SwapOptions::new().atomic(false)).unwrap();
+			nonatomic_in(&FailingFs::new(&[false, true]), &path_a, &path_b).unwrap_err();
+		});
+		let count = |name: &str| counters.0.lock().unwrap().get(name).map(|count| count.0.load(Ordering::Relaxed));
+		assert_eq!(Some(1), count("fs_swap.nonatomic"));
+		assert_eq!(Some(1), count("fs_swap.recovery"));
+		assert_eq!(None, count("fs_swap.atomic"));
+		assert_eq!("bar", read_from_file(&path_a));
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_swap_case_variants_case_sensitive() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("Foo.txt");
+		let path_b = dir.path().join("foo.txt");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		swap(&path_a, &path_b).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		swap_nonatomic(&path_a, &path_b).unwrap();
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+	}
+
+	#[cfg(any(target_os = "macos", windows))]
+	#[test]
+	fn test_swap_case_variants_case_insensitive() {
+		let dir = TempDir::new("").unwrap();
+		let file_a = dir.path().join("Foo.txt");
+		let file_b = dir.path().join("foo.txt");
+		let dir_a = dir.path().join("Dir");
+		let dir_b = dir.path().join("dir");
+		write_to_file(&file_a, "foo");
+		fs::create_dir(&dir_a).unwrap();
+		write_to_file(dir_a.join("file"), "bar");
+		if !file_b.exists() {
+			// the volume is case-sensitive
+			return;
+		}
+
+		for &(a, b) in &[(&file_a, &file_b), (&dir_a, &dir_b)] {
+			swap(a, b).unwrap();
+			swap_nonatomic(a, b).unwrap();
+			assert_eq!(SwapKind::Atomic, swap_with(a, b, &SwapOptions::new().atomic(false)).unwrap());
+		}
+		assert_eq!("foo", read_from_file(&file_a));
+		assert_eq!("bar", read_from_file(dir_a.join("file")));
+		let mut names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+		names.sort();
+		assert_eq!(vec!["Dir", "Foo.txt"], names);
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn test_remove_leftover_open_file() {
+		use std::os::windows::fs::OpenOptionsExt;
+		use std::thread;
+
+		let dir = TempDir::new("").unwrap();
+		let leftover = dir.path().join("leftover");
+		fs::create_dir(&leftover).unwrap();
+		write_to_file(leftover.join("file"), "foo");
+
+		// another process scanning the file, without sharing it
+		let file = fs::OpenOptions::new().read(true).share_mode(0).open(leftover.join("file")).unwrap();
+		let scan = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(20));
+			drop(file);
+		});
+
+		copy::remove_leftover(&StdFs, &leftover).unwrap();
+		scan.join().unwrap();
+		assert!(!leftover.exists());
+	}
+
+	#[test]
+	fn test_swap_serialized() {
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		let options = SwapOptions::new().atomic(false).serialize(true);
+		let threads: Vec<_> = (0..4).map(|_| {
+			let (a, b, options) = (path_a.clone(), path_b.clone(), options.clone());
+			::std::thread::spawn(move || {
+				for _ in 0..50 {
+					swap_with(&a, &b, &options).unwrap();
+				}
+			})
+		}).collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+
+		assert_eq!("foo", read_from_file(&path_a));
+		assert_eq!("bar", read_from_file(&path_b));
+		// only the lock file is left
+		assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+		assert!(dir.path().join(".fs_swap.lock").exists());
+
+		// no lock file can be created in a missing parent, which is reported as a missing path
+		let missing = dir.path().join("missing").join("file");
+		match swap_with(&path_a, &missing, &options) {
+			Err(SwapError::NotFound { which: Side::B }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		match replace_with(&missing, &path_b, &options) {
+			Err(SwapError::NotFound { which: Side::A }) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+	}
+
+	#[test]
+	fn test_swap_timeout() {
+		/// Filesystem whose renames are delayed, like the ones of a network filesystem.
+		struct SlowFs(Duration);
+
+		impl FileSystem for SlowFs {
+			fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+				::std::thread::sleep(self.0);
+				StdFs.rename(from, to)
+			}
+
+			fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+				::std::thread::sleep(self.0);
+				StdFs.rename_noreplace(from, to)
+			}
+
+			fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+				StdFs.metadata(path)
+			}
+
+			fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+				StdFs.hard_link(from, to)
+			}
+
+			fn remove_file(&self, path: &Path) -> io::Result<()> {
+				StdFs.remove_file(path)
+			}
+
+			fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+				StdFs.remove_dir_all(path)
+			}
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		let (a, b) = (path_a.clone(), path_b.clone());
+		timeout::run(Duration::from_secs(10), move || nonatomic_in(&SlowFs(Duration::from_millis(1)), &a, &b)).unwrap();
+		assert_eq!("bar", read_from_file(&path_a));
+		let options = SwapOptions::new().timeout(Duration::from_secs(10));
+		assert_eq!(SwapKind::Atomic, swap_with(&path_a, &path_b, &options).unwrap());
+		assert_eq!("foo", read_from_file(&path_a));
+
+		// the swap keeps running in the background, and is waited for before the directory is
+		// removed
+		let (a, b) = (path_a.clone(), path_b.clone());
+		let running = Arc::new(());
+		let worker = running.clone();
+		match timeout::run(Duration::from_millis(10), move || {
+			let _worker = worker;
+			nonatomic_in(&SlowFs(Duration::from_millis(200)), &a, &b)
+		}) {
+			Err(SwapError::TimedOut) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		while Arc::strong_count(&running) > 1 {
+			::std::thread::sleep(Duration::from_millis(10));
+		}
+		assert_eq!("bar", read_from_file(&path_a));
+
+		// the mock clock times out without waiting
+		let clock = MockClock::default();
+		let (release, blocked) = mpsc::channel::<()>();
+		match timeout::run_with(&clock, Duration::from_secs(60), move || Ok(blocked.recv().is_err())) {
+			Err(SwapError::TimedOut) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+		assert_eq!(vec![Duration::from_secs(60)], *clock.waits.borrow());
+		drop(release);
+	}
+
+	#[test]
+	fn test_swap_with_timeout_blocked() {
+		/// Filesystem whose renames are blocked until the sender of its channel is dropped, like
+		/// the ones of an unreachable network filesystem.
+		#[derive(Clone)]
+		struct BlockingFs(Arc<Mutex<mpsc::Receiver<()>>>);
+
+		impl BlockingFs {
+			fn block(&self) {
+				let _ = self.0.lock().unwrap().recv();
+			}
+		}
+
+		impl FileSystem for BlockingFs {
+			fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+				self.block();
+				StdFs.rename(from, to)
+			}
+
+			fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+				self.block();
+				StdFs.rename_noreplace(from, to)
+			}
+
+			fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+				StdFs.metadata(path)
+			}
+
+			fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+				StdFs.hard_link(from, to)
+			}
+
+			fn remove_file(&self, path: &Path) -> io::Result<()> {
+				StdFs.remove_file(path)
+			}
+
+			fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+				StdFs.remove_dir_all(path)
+			}
+		}
+
+		let dir = TempDir::new("").unwrap();
+		let path_a = dir.path().join("file_a");
+		let path_b = dir.path().join("file_b");
+		write_to_file(&path_a, "foo");
+		write_to_file(&path_b, "bar");
+
+		let (release, blocked) = mpsc::channel();
+		let fs = BlockingFs(Arc::new(Mutex::new(blocked)));
+		let options = SwapOptions::new().atomic(false).timeout(Duration::from_millis(10));
+		match super::swap_report_in(&fs, &path_a, &path_b, &options) {
+			Err(SwapError::TimedOut) => (),
+			result => panic!("unexpected result: {:?}", result),
+		}
+
+		// the worker drops its copy of the filesystem once the released swap completes, before
+		// the directory is removed
+		drop(release);
+		while Arc::strong_count(&fs.0) > 1 {
+			::std::thread::sleep(Duration::from_millis(1));
+		}
+		assert_eq!("bar", read_from_file(&path_a));
+		assert_eq!("foo", read_from_file(&path_b));
+		assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+	}
+
 	// atomic swaps of dirs are not supported on travis machines
 	#[test]
 	fn test_swap_nonatomic_dirs() {
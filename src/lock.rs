@@ -0,0 +1,54 @@
+//! Serializing concurrent swaps with lock files, see `SwapOptions::serialize`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use error::SwapError;
+use {platform, validate};
+
+/// Name of the lock file created in the parent directories of the swapped paths.
+const LOCK_NAME: &str = ".fs_swap.lock";
+
+/// Exclusive locks on the lock files of one or two directories, released when dropped.
+pub(crate) struct DirLocks {
+	_files: Vec<File>,
+}
+
+impl DirLocks {
+	/// Locks the parent directories of `a` and `b`, waiting until no other swap holds them.
+	///
+	/// The directories are canonicalized and locked in order, so that a directory is locked once
+	/// even if it's spelled differently, and two swaps locking the same directories cannot
+	/// deadlock.
+	pub(crate) fn acquire(a: &Path, b: &Path) -> io::Result<Self> {
+		let mut dirs = vec![parent_dir(a)?, parent_dir(b)?];
+		dirs.sort();
+		dirs.dedup();
+
+		let mut files = Vec::with_capacity(dirs.len());
+		for dir in dirs {
+			let path = dir.join(LOCK_NAME);
+			let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+			platform::lock_exclusive(&file)?;
+			swap_log!(trace, "Locked directory"; path = ?path);
+			files.push(file);
+		}
+		Ok(DirLocks { _files: files })
+	}
+
+	/// Validates `a` and `b`, then locks their parent directories like `acquire`. A missing parent
+	/// is reported as `SwapError::NotFound` for its path, rather than as a lock file which could
+	/// not be created.
+	pub(crate) fn acquire_checked(a: &Path, b: &Path) -> Result<Self, SwapError> {
+		validate::check_path(a)?;
+		validate::check_path(b)?;
+		DirLocks::acquire(a, b).map_err(|err| SwapError::from_unmodified(err, a, b))
+	}
+}
+
+fn parent_dir(path: &Path) -> io::Result<PathBuf> {
+	match path.parent() {
+		Some(parent) if parent != Path::new("") => fs::canonicalize(parent),
+		_ => fs::canonicalize("."),
+	}
+}
@@ -0,0 +1,71 @@
+//! Logging macros.
+//!
+//! With the `tracing` feature, messages are emitted through `tracing`, otherwise with the
+//! `logging` feature they are emitted through `log`. If both features are disabled, the macros
+//! below expand to nothing.
+//!
+//! Successful swaps are logged at `debug`, and their individual steps at `trace`. `warn` is
+//! reserved for failures which were recovered from, like a reverted rename or a leftover temp
+//! path, and `error` for failures which left the paths in an inconsistent state.
+
+/// Emits message `$msg` at `$level` (`error`, `warn`, `debug` or `trace`) with key-value fields.
+///
+/// Fields are written `key = ?value` to format the value with `Debug` and `key = %value` to format
+/// it with `Display`. With `tracing`, they are recorded as event fields, with `log` they are
+/// appended to the message as `key=value`.
+#[cfg(feature = "tracing")]
+macro_rules! swap_log {
+	($level:ident, $msg:expr $(; $($key:ident = $sigil:tt $value:expr),+)*) => {
+		::tracing::$level!($($($key = $sigil $value,)+)* $msg)
+	};
+}
+
+#[cfg(all(feature = "logging", not(feature = "tracing")))]
+macro_rules! swap_log {
+	($level:ident, $msg:expr $(; $($key:ident = $sigil:tt $value:expr),+)*) => {
+		::log::$level!(concat!($msg $($(, " ", stringify!($key), "={}")+)*) $($(, log_field!($sigil $value))+)*)
+	};
+}
+
+#[cfg(all(feature = "logging", not(feature = "tracing")))]
+macro_rules! log_field {
+	(? $value:expr) => { format_args!("{:?}", $value) };
+	(% $value:expr) => { format_args!("{}", $value) };
+}
+
+#[cfg(not(any(feature = "logging", feature = "tracing")))]
+macro_rules! swap_log {
+	($level:ident, $msg:expr $(; $($key:ident = $sigil:tt $value:expr),+)*) => {{
+		$($(let _ = &$value;)+)*
+	}};
+}
+
+/// Enters a span covering the swap of `a` and `b` until the end of the current block.
+#[cfg(feature = "tracing")]
+macro_rules! swap_span {
+	($name:expr, $a:expr, $b:expr) => {
+		let _span = ::tracing::debug_span!($name, a = ?$a, b = ?$b).entered();
+	};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! swap_span {
+	($name:expr, $a:expr, $b:expr) => {};
+}
+
+/// Increments counter `$name` of the `metrics` crate, with the `metrics` feature.
+///
+/// Counters are incremented once per swap, with the strategy which swapped the paths:
+/// `fs_swap.atomic`, `fs_swap.linked`, `fs_swap.nonatomic` or `fs_swap.fallback_copy`, and once
+/// per rollback of a failed swap: `fs_swap.recovery`.
+#[cfg(feature = "metrics")]
+macro_rules! swap_metric {
+	($name:expr) => {
+		::metrics::counter!($name).increment(1)
+	};
+}
+
+#[cfg(not(feature = "metrics"))]
+macro_rules! swap_metric {
+	($name:expr) => {};
+}
@@ -0,0 +1,74 @@
+//! Moving a path over a missing one, or swapping it if both exist.
+
+use std::io;
+use std::path::Path;
+use copy::{self, CopyControl};
+use error::{SwapError, Side};
+use filesystem::{FileSystem, StdFs};
+use {platform, swap_auto, validate, SwapKind, SwapReport};
+
+/// Number of times the paths are inspected again if one of them is created or removed between
+/// the check and the move or the swap.
+const ATTEMPTS: usize = 3;
+
+/// What was done by `move_or_swap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOrSwap {
+	/// Both paths existed and were swapped with the given strategy.
+	Swapped(SwapKind),
+	/// Only one path existed and was moved to the other one, which now has its content.
+	Moved {
+		/// The path which existed and is now missing.
+		from: Side,
+		/// `SwapKind::Atomic` if it was renamed, or `SwapKind::Copied` if the paths are on
+		/// different filesystems.
+		kind: SwapKind,
+	},
+}
+
+/// Renames `from` to `to` unless `to` exists, or moves it by copying it if they are on different
+/// filesystems.
+fn move_noreplace(from: &Path, to: &Path) -> io::Result<SwapKind> {
+	match StdFs.rename_noreplace(from, to) {
+		Ok(_) => Ok(SwapKind::Atomic),
+		Err(ref err) if platform::is_cross_device(err) => {
+			copy::move_copied(from, to, &mut CopyControl::default()).map(|stats| SwapReport::copied(&stats).kind)
+		},
+		Err(err) => Err(err),
+	}
+}
+
+/// Swaps `a` and `b` with `swap_auto` if both exist, or moves the existing one to the other one
+/// if only one does. Fails with `SwapError::NotFound` if none exists.
+///
+/// The move never replaces a path created after it was found missing: the paths are then
+/// inspected again, and swapped. On the same filesystem, the move is a single rename, which is
+/// atomic on Linux, macOS and Windows. Across filesystems, the path is copied next to the
+/// other one and renamed in place. Paths are resolved like with `swap`.
+pub fn move_or_swap<A, B>(a: A, b: B) -> Result<MoveOrSwap, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = &*validate::resolve(a.as_ref())?;
+	let b = &*validate::resolve(b.as_ref())?;
+	swap_span!("move_or_swap", a, b);
+
+	for _ in 0..ATTEMPTS {
+		let (from, to, side) = match validate::missing_side(a, b)? {
+			None => match swap_auto(a, b) {
+				Ok(kind) => return Ok(MoveOrSwap::Swapped(kind)),
+				// removed after it was found
+				Err(SwapError::NotFound { .. }) => continue,
+				Err(err) => return Err(err),
+			},
+			Some(Side::A) => (b, a, Side::B),
+			Some(Side::B) => (a, b, Side::A),
+		};
+
+		match move_noreplace(from, to) {
+			Ok(kind) => return Ok(MoveOrSwap::Moved { from: side, kind }),
+			// created or removed after it was found
+			Err(ref err) if matches!(err.kind(), io::ErrorKind::AlreadyExists | io::ErrorKind::NotFound) => continue,
+			Err(err) => return Err(SwapError::from_unmodified(err, a, b)),
+		}
+	}
+
+	Err(io::Error::new(io::ErrorKind::Interrupted, "Paths kept being created or removed during the move").into())
+}
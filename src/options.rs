@@ -0,0 +1,345 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+/// Default size of the buffer of chunked copies, see `SwapOptions::copy_buffer_size`. It was the
+/// fastest in the `copy buffer size` benchmark: smaller buffers take more syscalls, and larger
+/// ones do not fit in the CPU caches anymore.
+pub(crate) const DEFAULT_COPY_BUFFER_SIZE: usize = 128 * 1024;
+const MIN_COPY_BUFFER_SIZE: usize = 4 * 1024;
+
+/// Callback receiving the progress of copies, shared by the clones of `SwapOptions`.
+#[derive(Clone)]
+pub(crate) struct Progress(Arc<Mutex<dyn FnMut(u64, u64) + Send>>);
+
+impl Progress {
+	pub(crate) fn report(&self, done: u64, total: u64) {
+		if let Ok(mut progress) = self.0.lock() {
+			(*progress)(done, total);
+		}
+	}
+}
+
+impl fmt::Debug for Progress {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("Progress")
+	}
+}
+
+/// Generator of the temp names of nonatomic swaps, shared by the clones of `SwapOptions`.
+#[derive(Clone)]
+pub(crate) struct TempName(Arc<dyn Fn() -> OsString + Send + Sync>);
+
+impl TempName {
+	pub(crate) fn generate(&self) -> OsString {
+		(*self.0)()
+	}
+}
+
+impl fmt::Debug for TempName {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("TempName")
+	}
+}
+
+/// Location of the temp path of a nonatomic swap.
+///
+/// The temp path must be on the same filesystem as the swapped paths, since they are renamed to
+/// it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TempStrategy {
+	/// In the parent directory of `a`, or of `b` if `a` has none. This is the default.
+	#[default]
+	BesideA,
+	/// In the parent directory of `b`, or of `a` if `b` has none.
+	BesideB,
+	/// In the system temp directory, or beside `a` if it is on another filesystem.
+	System,
+	/// In the given directory. If it is on another filesystem, the swap fails without modifying
+	/// the paths.
+	Dir(PathBuf),
+}
+
+/// Options used to configure `swap_with`.
+#[derive(Debug, Clone)]
+pub struct SwapOptions {
+	pub(crate) atomic: bool,
+	pub(crate) temp: TempStrategy,
+	pub(crate) temp_name: Option<TempName>,
+	pub(crate) durable: bool,
+	pub(crate) preserve_permissions: bool,
+	pub(crate) preserve_times: bool,
+	pub(crate) retries: u32,
+	pub(crate) retry_backoff: Duration,
+	pub(crate) allow_missing: bool,
+	pub(crate) create_missing: bool,
+	pub(crate) sync_data: bool,
+	pub(crate) require_atomic: bool,
+	pub(crate) overwrite_from_read_only: bool,
+	pub(crate) read_only_backup: Option<PathBuf>,
+	pub(crate) cancel: Option<Arc<AtomicBool>>,
+	pub(crate) progress: Option<Progress>,
+	pub(crate) continue_on_error: bool,
+	pub(crate) timeout: Option<Duration>,
+	pub(crate) serialize: bool,
+	pub(crate) clear_immutable: bool,
+	pub(crate) copy_buffer_size: usize,
+}
+
+impl Default for SwapOptions {
+	fn default() -> Self {
+		SwapOptions {
+			atomic: true,
+			temp: TempStrategy::default(),
+			temp_name: None,
+			durable: false,
+			preserve_permissions: false,
+			preserve_times: false,
+			retries: 0,
+			retry_backoff: Duration::from_millis(10),
+			allow_missing: false,
+			create_missing: false,
+			sync_data: false,
+			require_atomic: false,
+			overwrite_from_read_only: false,
+			read_only_backup: None,
+			cancel: None,
+			progress: None,
+			continue_on_error: false,
+			timeout: None,
+			serialize: false,
+			clear_immutable: false,
+			copy_buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+		}
+	}
+}
+
+impl SwapOptions {
+	/// Creates default options.
+	pub fn new() -> Self {
+		SwapOptions::default()
+	}
+
+	/// If set to `true` (default), an atomic swap is attempted first and the paths are swapped
+	/// nonatomically only if they are on different filesystems. If set to `false`, the paths are
+	/// always swapped nonatomically.
+	pub fn atomic(mut self, atomic: bool) -> Self {
+		self.atomic = atomic;
+		self
+	}
+
+	/// Sets the directory in which the temporary file of a nonatomic swap is created, like
+	/// `TempStrategy::Dir`.
+	///
+	/// By default, it is created in the parent directory of `a`. The directory must be on the
+	/// same filesystem as both swapped paths, otherwise the swap fails without modifying them.
+	pub fn temp_dir<P>(mut self, dir: P) -> Self where P: AsRef<Path> {
+		self.temp = TempStrategy::Dir(dir.as_ref().to_path_buf());
+		self
+	}
+
+	/// Sets where the temporary file of a nonatomic swap is created. Defaults to
+	/// `TempStrategy::BesideA`.
+	pub fn temp_strategy(mut self, temp: TempStrategy) -> Self {
+		self.temp = temp;
+		self
+	}
+
+	/// Sets a function generating the name of the temporary file of a nonatomic swap, for
+	/// instance to follow a naming policy or to get a known name in tests.
+	///
+	/// The name must be a single path component. If it's already used in the temp directory, the
+	/// function is called again, and the swap fails without modifying the paths if it returns no
+	/// unused name after a few attempts. By default, a hidden name made of the process id and a
+	/// counter is used.
+	pub fn temp_name_fn<F>(mut self, temp_name: F) -> Self where F: Fn() -> OsString + Send + Sync + 'static {
+		self.temp_name = Some(TempName(Arc::new(temp_name)));
+		self
+	}
+
+	/// If set to `true`, both paths are flushed to disk before the swap and their parent
+	/// directories are flushed after it, so that the swap survives a crash. Defaults to `false`.
+	pub fn durable(mut self, durable: bool) -> Self {
+		self.durable = durable;
+		self
+	}
+
+	/// If set to `true`, each path keeps its permissions after the swap instead of taking the
+	/// permissions of the other path's content. On unix, the owner and group are also kept when
+	/// the process is allowed to change them. Defaults to `false`.
+	pub fn preserve_permissions(mut self, preserve: bool) -> Self {
+		self.preserve_permissions = preserve;
+		self
+	}
+
+	/// If set to `true`, the access and modification times of each content are restored after
+	/// the swap, so they are not altered by the strategy used to swap it. Defaults to `false`.
+	pub fn preserve_times(mut self, preserve: bool) -> Self {
+		self.preserve_times = preserve;
+		self
+	}
+
+	/// Retries each rename and removal up to `retries` times if it fails with a transient error,
+	/// waiting `backoff` before the first retry and twice as long before each next one. Defaults
+	/// to no retries.
+	///
+	/// Only Windows sharing violations and access denied errors, usually caused by antivirus or
+	/// indexing services briefly opening the files, are considered transient.
+	pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+		self.retries = retries;
+		self.retry_backoff = backoff;
+		self
+	}
+
+	/// If set to `true`, a missing path is treated as empty: the other path is moved to it and
+	/// ends up missing itself. Swapping two missing paths still fails. Defaults to `false`, so
+	/// that a mistyped path is reported with `SwapError::NotFound`.
+	///
+	/// The move is a single rename, which fails if the missing path is created meanwhile, or a
+	/// copy if the paths are on different filesystems. If the filesystem cannot rename without
+	/// replacing, the missing path is checked right before, as described in `swap_nonatomic`, and
+	/// `SwapKind::Nonatomic` is returned.
+	pub fn allow_missing(mut self, allow_missing: bool) -> Self {
+		self.allow_missing = allow_missing;
+		self
+	}
+
+	/// If set to `true`, a missing path is created empty before the swap: an empty directory if
+	/// the other path is a directory, or an empty file otherwise. After the swap, it has the content
+	/// of the other path, which is left empty but still exists. Swapping two missing paths still
+	/// fails. Defaults to `false`.
+	///
+	/// Unlike `allow_missing`, both paths exist after the swap, and it takes precedence over it.
+	/// If the swap fails without modifying the paths, the created path is removed.
+	pub fn create_missing(mut self, create_missing: bool) -> Self {
+		self.create_missing = create_missing;
+		self
+	}
+
+	/// If set to `true`, every file of both paths, including the files within directories, is
+	/// flushed to disk before the swap, so a crash never leaves a path with partially written
+	/// content. Defaults to `false`.
+	///
+	/// Each file is opened and flushed with `sync_all`, which may take a long time for large
+	/// directories or slow disks. Use `durable` to also flush the parent directories.
+	pub fn sync_data(mut self, sync_data: bool) -> Self {
+		self.sync_data = sync_data;
+		self
+	}
+
+	/// If set to `true`, the swap never falls back to a nonatomic strategy: it fails with
+	/// `SwapError::AtomicUnsupported` if the kernel or the filesystem cannot swap the paths
+	/// atomically, and with `SwapError::CrossDevice` if they are on different filesystems. Nothing
+	/// is modified in both cases. Defaults to `false`.
+	///
+	/// This takes precedence over `atomic(false)`. With `allow_missing`, a missing path is still
+	/// replaced with a single rename, which is atomic, but never with a copy.
+	pub fn require_atomic(mut self, require_atomic: bool) -> Self {
+		self.require_atomic = require_atomic;
+		self
+	}
+
+	/// If set to `true` and exactly one of the paths is on a read-only filesystem, the other path
+	/// is overwritten with a copy of its content instead of being swapped with it. Defaults to
+	/// `false`, so that the swap fails.
+	///
+	/// This is a one-way operation, not a swap: the read-only path is left untouched and the
+	/// original content of the writable path is removed, unless `read_only_backup` is set. The
+	/// swap returns `SwapKind::Overwritten` naming the read-only side.
+	pub fn overwrite_from_read_only(mut self, overwrite: bool) -> Self {
+		self.overwrite_from_read_only = overwrite;
+		self
+	}
+
+	/// Sets the path to which the original content of the writable path is moved when it is
+	/// overwritten with `overwrite_from_read_only`.
+	///
+	/// The path must not exist and must be on the same filesystem as the writable path. By default,
+	/// the original content is removed.
+	pub fn read_only_backup<P>(mut self, path: P) -> Self where P: AsRef<Path> {
+		self.read_only_backup = Some(path.as_ref().to_path_buf());
+		self
+	}
+
+	/// Sets a flag which cancels the swap of paths on different filesystems when it is set to
+	/// `true`, for instance on shutdown.
+	///
+	/// The flag is checked before copying each path and between the chunks of large files. Once
+	/// it is set, the partial copies are removed and the swap fails with `SwapError::Cancelled`,
+	/// leaving both paths unmodified. The flag is not checked anymore once the copies are being
+	/// renamed in place.
+	pub fn cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+		self.cancel = Some(cancel);
+		self
+	}
+
+	/// Sets a callback receiving the progress of the swap of paths on different filesystems, as
+	/// the number of bytes copied so far and the total number of bytes to copy.
+	///
+	/// It is called after each chunk of the copied files, the bytes done never decrease. It is
+	/// not called when the paths are swapped by renaming them.
+	pub fn progress<F>(mut self, progress: F) -> Self where F: FnMut(u64, u64) + Send + 'static {
+		self.progress = Some(Progress(Arc::new(Mutex::new(progress))));
+		self
+	}
+
+	/// If set to `true`, `swap_many_with` keeps swapping the remaining pairs after a pair could
+	/// not be swapped. Defaults to `false`, which skips them.
+	pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+		self.continue_on_error = continue_on_error;
+		self
+	}
+
+	/// Sets the maximum time to wait for the swap, for instance on a network filesystem whose
+	/// server may become unreachable. The swap runs on a new thread, and it fails with
+	/// `SwapError::TimedOut` if it takes longer. Defaults to no timeout.
+	///
+	/// Filesystem calls cannot be interrupted, so a timed out swap keeps running in the
+	/// background and may still complete, fail or be rolled back later: only the caller is
+	/// unblocked. Use `cancel_flag` to also stop the copy of paths on different filesystems.
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
+
+	/// If set to `true`, the swap holds an exclusive lock on a `.fs_swap.lock` file in the parent
+	/// directory of each path, so that concurrent swaps in the same directories run one after the
+	/// other instead of interfering with each other. Defaults to `false`.
+	///
+	/// The lock is advisory: it only protects against other swaps using this option, not against
+	/// other processes modifying the paths. The lock files are created if needed, which requires
+	/// write access to the directories, and are left in place. WASI has no file locks, so swaps
+	/// are not serialized there.
+	pub fn serialize(mut self, serialize: bool) -> Self {
+		self.serialize = serialize;
+		self
+	}
+
+	/// If set to `true`, the immutable and append-only flags of the swapped paths, set with
+	/// `chattr` on Linux, are cleared before the swap and set again afterwards, following the
+	/// content. This requires `CAP_LINUX_IMMUTABLE`. Defaults to `false`, so that swapping such a
+	/// path fails with a `PermissionDenied` error naming it, after any modification was rolled
+	/// back. The flags are then only read once the swap failed.
+	///
+	/// Only the swapped paths are checked, not the files within directories, which are not
+	/// renamed.
+	pub fn clear_immutable(mut self, clear_immutable: bool) -> Self {
+		self.clear_immutable = clear_immutable;
+		self
+	}
+
+	/// Sets the size of the buffer used to copy files chunk by chunk when paths on different
+	/// filesystems are swapped, which is also the interval between two checks of `cancel_flag`
+	/// and two calls of `progress`. Defaults to 128 KiB, and sizes below 4 KiB are raised to it.
+	///
+	/// Files are copied chunk by chunk if `cancel_flag` or `progress` is set, or if they are
+	/// sparse. Otherwise, they are copied with `fs::copy`, which lets the kernel copy them where
+	/// the platform supports it.
+	pub fn copy_buffer_size(mut self, size: usize) -> Self {
+		self.copy_buffer_size = size.max(MIN_COPY_BUFFER_SIZE);
+		self
+	}
+}
@@ -0,0 +1,64 @@
+//! Overwriting a writable path with the content of a path on a read-only filesystem.
+
+use std::io;
+use std::path::Path;
+use copy::{copy_to_tmp, remove_leftover, remove_path, CopyControl, CopyStats};
+use error::{SwapError, Side};
+use filesystem::FileSystem;
+use platform;
+use rollback::rename_all;
+use tmp_path_in_dir;
+
+/// Returns the side of the path located on a read-only filesystem, or `None` if both or none
+/// of them are.
+pub(crate) fn read_only_side(a: &Path, b: &Path) -> Result<Option<Side>, SwapError> {
+	let a_read_only = platform::is_read_only(a).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	let b_read_only = platform::is_read_only(b).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	match (a_read_only, b_read_only) {
+		(true, false) => Ok(Some(Side::A)),
+		(false, true) => Ok(Some(Side::B)),
+		_ => Ok(None),
+	}
+}
+
+/// Replaces `to` with a copy of `from`, `from` being left untouched.
+///
+/// The copy is made in a temp path next to `to`. Then `to` is renamed to `backup`, or to another
+/// temp path removed afterwards if there is none, and the copy is renamed in its place. If a copy
+/// or a rename fails, `to` is restored. If the cancellation flag of `control` is set during the
+/// copy, `to` is not modified and `SwapError::Cancelled` is returned.
+pub(crate) fn overwrite<F>(fs: &F, from: &Path, to: &Path, backup: Option<&Path>, control: &mut CopyControl) -> Result<CopyStats, SwapError> where F: FileSystem {
+	let dir = to.parent().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))?;
+	let copy = tmp_path_in_dir(dir)?;
+	let aside = match backup {
+		Some(backup) if fs.metadata(backup).is_ok() => {
+			return Err(SwapError::Io(io::Error::new(io::ErrorKind::AlreadyExists, "Backup path already exists")));
+		},
+		Some(backup) => backup.to_path_buf(),
+		None => tmp_path_in_dir(dir)?,
+	};
+
+	control.add_total(from).map_err(|err| SwapError::from_unmodified(err, from, to))?;
+	let mut stats = CopyStats::default();
+	copy_to_tmp(fs, from, &copy, &mut stats, control).map_err(|err| control.unmodified_error(err, from, to))?;
+
+	match rename_all(fs, &[(to, &aside), (&copy, to)]) {
+		Ok(_) => (),
+		Err(err @ SwapError::Corrupted { .. }) => return Err(err),
+		Err(err) => {
+			let _ = remove_path(fs, &copy);
+			return Err(match err {
+				SwapError::Io(err) => SwapError::from_unmodified(err, from, to),
+				err => err,
+			});
+		},
+	}
+
+	if backup.is_none() {
+		if let Err(err) = remove_leftover(fs, &aside) {
+			swap_log!(warn, "Removing overwritten path failed"; path = ?aside, error = %err);
+		}
+	}
+
+	Ok(stats)
+}
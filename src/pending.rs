@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+use error::SwapError;
+use options::SwapOptions;
+use {swap_with, SwapKind};
+
+/// Swap of two paths to be applied later, possibly on another thread.
+#[derive(Debug, Clone)]
+pub struct PendingSwap {
+	a: PathBuf,
+	b: PathBuf,
+	options: SwapOptions,
+}
+
+impl PendingSwap {
+	/// Creates a pending swap of paths `a` and `b` with default options.
+	pub fn new<A, B>(a: A, b: B) -> Self where A: AsRef<Path>, B: AsRef<Path> {
+		PendingSwap {
+			a: a.as_ref().to_path_buf(),
+			b: b.as_ref().to_path_buf(),
+			options: SwapOptions::new(),
+		}
+	}
+
+	/// Sets the options used to apply the swap.
+	pub fn options(mut self, options: SwapOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	/// First swapped path.
+	pub fn a(&self) -> &Path {
+		&self.a
+	}
+
+	/// Second swapped path.
+	pub fn b(&self) -> &Path {
+		&self.b
+	}
+
+	/// Swaps the paths with `swap_with`. The same swap can be applied multiple times.
+	pub fn apply(&self) -> Result<SwapKind, SwapError> {
+		swap_with(&self.a, &self.b, &self.options)
+	}
+}
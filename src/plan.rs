@@ -0,0 +1,115 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use error::{Side, SwapError};
+use options::SwapOptions;
+use {atomic_swap_supported, can_link, is_same_path, nonatomic_tmp_path, platform, validate};
+
+/// Operations that `swap_with` would perform to swap two paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwapPlan {
+	/// Paths are the same, nothing would be done.
+	Nothing,
+	/// Paths would be swapped atomically.
+	Atomic,
+	/// Files would be swapped with a hardlink at `temp_path` and two renames, so that `a` is never
+	/// missing. If the link cannot be created, the swap falls back to three renames.
+	Linked {
+		/// Temp path which would be used. It's unused now, but another temp path may be picked by
+		/// the actual swap.
+		temp_path: PathBuf,
+	},
+	/// Paths would be swapped with three renames through `temp_path`.
+	Nonatomic {
+		/// Temp path which would be used. It's unused now, but another temp path may be picked by
+		/// the actual swap.
+		temp_path: PathBuf,
+	},
+	/// Paths are on different filesystems and would be swapped by copying them.
+	Copied,
+	/// Path `to` does not exist, and `from` would be moved to it with
+	/// `SwapOptions::allow_missing`.
+	Moved {
+		/// Existing path which would be moved.
+		from: PathBuf,
+		/// Missing path it would be moved to.
+		to: PathBuf,
+	},
+	/// Path `which` does not exist, and would be created empty with the type of the other path
+	/// before swapping them with `SwapOptions::create_missing`.
+	Created {
+		/// Path which does not exist.
+		which: Side,
+	},
+}
+
+/// Returns how `swap_auto` would swap paths `a` and `b`, without modifying them.
+pub fn plan<A, B>(a: A, b: B) -> Result<SwapPlan, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	plan_with(a, b, &SwapOptions::new())
+}
+
+/// Returns how `swap_with` would swap paths `a` and `b` using given `options`, without modifying
+/// them.
+///
+/// The same checks as the swap are performed, so validation errors and the errors of
+/// `SwapOptions::require_atomic` are returned as well. Support for atomic swaps is probed with
+/// `atomic_swap_supported`. A missing path is planned to be moved over or created, like the swap
+/// does with `SwapOptions::allow_missing` or `SwapOptions::create_missing`.
+pub fn plan_with<A, B>(a: A, b: B, options: &SwapOptions) -> Result<SwapPlan, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = &*validate::resolve(a.as_ref())?;
+	let b = &*validate::resolve(b.as_ref())?;
+
+	if is_same_path(a, b) {
+		return Ok(SwapPlan::Nothing);
+	}
+
+	if options.create_missing {
+		if let Some(which) = validate::missing_side(a, b)? {
+			return Ok(SwapPlan::Created { which });
+		}
+	}
+
+	if options.allow_missing {
+		let moved = match validate::missing_side(a, b)? {
+			Some(Side::A) => Some((b, a)),
+			Some(Side::B) => Some((a, b)),
+			None => None,
+		};
+		if let Some((from, to)) = moved {
+			return plan_moved(from, to, options);
+		}
+	}
+
+	let entries = validate::check(a, b)?;
+
+	if !platform::same_device(a, b).map_err(|err| SwapError::from_unmodified(err, a, b))? {
+		if options.require_atomic {
+			return Err(SwapError::CrossDevice);
+		}
+		return Ok(SwapPlan::Copied);
+	}
+
+	if (options.atomic || options.require_atomic) && atomic_swap_supported(a, b)? {
+		return Ok(SwapPlan::Atomic);
+	}
+
+	if options.require_atomic {
+		return Err(SwapError::AtomicUnsupported);
+	}
+
+	// files are linked only if an atomic swap was attempted
+	let temp_path = nonatomic_tmp_path(a, b, options)?;
+	if options.atomic && can_link(a, b, &entries)? {
+		return Ok(SwapPlan::Linked { temp_path });
+	}
+	Ok(SwapPlan::Nonatomic { temp_path })
+}
+
+/// Returns how `from` would be moved to missing path `to`. It's copied if `to` is on another
+/// filesystem, which `SwapOptions::require_atomic` does not allow.
+fn plan_moved(from: &Path, to: &Path, options: &SwapOptions) -> Result<SwapPlan, SwapError> {
+	let parent = to.parent().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))?;
+	if options.require_atomic && !platform::same_device(from, parent).map_err(|err| SwapError::from_unmodified(err, from, to))? {
+		return Err(SwapError::CrossDevice);
+	}
+	Ok(SwapPlan::Moved { from: from.to_path_buf(), to: to.to_path_buf() })
+}
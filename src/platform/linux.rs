@@ -1,8 +1,13 @@
 extern crate libc;
 
+use std::ffi::{CString, OsStr};
+use std::ops::Range;
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
-use std::{io, ffi};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::{io, fs};
+use super::unix::ParentDir;
 
 unsafe fn renameat2(
 	olddirfd: libc::c_int, oldpath: *const libc::c_char, 
@@ -11,14 +16,194 @@ unsafe fn renameat2(
 	libc::syscall(libc::SYS_renameat2, olddirfd, oldpath, newdirfd, newpath, flags) as libc::c_int
 }
 
+fn swap_at(a: &ParentDir, b: &ParentDir) -> io::Result<()> {
+	unsafe {
+		match renameat2(a.fd(), a.name(), b.fd(), b.name(), libc::RENAME_EXCHANGE as libc::c_int) {
+			0 => Ok(()),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Swaps `a` and `b` with `renameat2`, relative to their opened parent directories.
 pub fn swap<A, B>(a: A, b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
-	let a_path = ffi::CString::new(a.as_ref().as_os_str().as_bytes())?;
-	let b_path = ffi::CString::new(b.as_ref().as_os_str().as_bytes())?;
+	swap_at(&ParentDir::open(a.as_ref(), false)?, &ParentDir::open(b.as_ref(), false)?)
+}
+
+/// Exchanges `a` and `b` with `swap`, which has no fallback.
+pub fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+	swap(a, b)
+}
+
+/// Renames `from` to `to` with `renameat2`, failing with `AlreadyExists` if `to` exists.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+	let from = CString::new(from.as_os_str().as_bytes())?;
+	let to = CString::new(to.as_os_str().as_bytes())?;
+
+	unsafe {
+		match renameat2(libc::AT_FDCWD, from.as_ptr(), libc::AT_FDCWD, to.as_ptr(), libc::RENAME_NOREPLACE as libc::c_int) {
+			0 => Ok(()),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Swaps the entries of opened parent directories `a` and `b` with `renameat2`.
+pub fn swap_parents(a: &ParentDir, b: &ParentDir) -> io::Result<()> {
+	swap_at(a, b)
+}
+
+/// Swaps entries `a` and `b` of open directory `dir` with `renameat2`.
+pub fn swap_in_dir(dir: &fs::File, a: &OsStr, b: &OsStr) -> io::Result<()> {
+	swap_in_dirs(dir, a, dir, b)
+}
+
+/// Swaps entry `a` of open directory `dir_a` and entry `b` of open directory `dir_b` with
+/// `renameat2`.
+pub fn swap_in_dirs(dir_a: &fs::File, a: &OsStr, dir_b: &fs::File, b: &OsStr) -> io::Result<()> {
+	let a = CString::new(a.as_bytes())?;
+	let b = CString::new(b.as_bytes())?;
 
 	unsafe {
-		match renameat2(libc::AT_FDCWD, a_path.as_ptr(), libc::AT_FDCWD, b_path.as_ptr(), libc::RENAME_EXCHANGE) {
+		match renameat2(dir_a.as_raw_fd(), a.as_ptr(), dir_b.as_raw_fd(), b.as_ptr(), libc::RENAME_EXCHANGE as libc::c_int) {
 			0 => Ok(()),
-			_ => Err(io::Error::new(io::ErrorKind::Other, format!("renameat2 failed with code: {}", *libc::__errno_location()))),
+			_ => Err(io::Error::last_os_error()),
 		}
 	}
 }
+
+/// Returns true if `err` was caused by an attempt to swap paths on different filesystems.
+pub fn is_cross_device(err: &io::Error) -> bool {
+	err.raw_os_error() == Some(libc::EXDEV)
+}
+
+/// Returns true if `err` indicates that the kernel or the filesystem does not support
+/// `renameat2` with `RENAME_EXCHANGE` (available since 3.15).
+pub fn is_atomic_unsupported(err: &io::Error) -> bool {
+	matches!(err.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EINVAL))
+}
+
+/// Errors are never considered transient.
+pub fn is_transient(_err: &io::Error) -> bool {
+	false
+}
+
+/// Creates `to` as a copy-on-write clone of file `from` with `ioctl(FICLONE)`.
+///
+/// Fails if the filesystem does not support it or if the files are on different filesystems.
+pub fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+	let src = fs::File::open(from)?;
+	let dst = fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+
+	unsafe {
+		if libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) == 0 {
+			return Ok(());
+		}
+	}
+
+	let err = io::Error::last_os_error();
+	let _ = fs::remove_file(to);
+	Err(err)
+}
+
+/// Returns the ranges of `file` holding data, found with `lseek(SEEK_DATA)` and
+/// `lseek(SEEK_HOLE)`, or `None` if the file has no holes or the filesystem can't report them.
+///
+/// The position of `file` is moved.
+pub fn data_ranges(file: &fs::File) -> io::Result<Option<Vec<Range<u64>>>> {
+	let metadata = file.metadata()?;
+	// files whose allocated blocks cover their whole length have no holes
+	if metadata.blocks() * 512 >= metadata.len() {
+		return Ok(None);
+	}
+
+	let mut ranges = Vec::new();
+	let mut offset = 0;
+	while offset < metadata.len() {
+		let start = unsafe { libc::lseek64(file.as_raw_fd(), offset as libc::off64_t, libc::SEEK_DATA) };
+		if start == -1 {
+			let err = io::Error::last_os_error();
+			return match err.raw_os_error() {
+				// no data after `offset`
+				Some(libc::ENXIO) => Ok(Some(ranges)),
+				Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => Ok(None),
+				_ => Err(err),
+			};
+		}
+		let end = unsafe { libc::lseek64(file.as_raw_fd(), start, libc::SEEK_HOLE) };
+		if end == -1 {
+			return Err(io::Error::last_os_error());
+		}
+		ranges.push(start as u64..end as u64);
+		offset = end as u64;
+	}
+	Ok(Some(ranges))
+}
+
+/// Files are sparse as soon as they have holes, nothing needs to be set.
+pub fn set_sparse(_file: &fs::File) -> io::Result<()> {
+	Ok(())
+}
+
+/// Inode flags set with `chattr +i` and `chattr +a`, which prevent renaming or removing the
+/// file or directory.
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+const FS_APPEND_FL: libc::c_int = 0x20;
+
+/// Opens file or directory `path` to read or change its inode flags. Symlinks have none.
+fn open_for_flags(path: &Path) -> io::Result<fs::File> {
+	fs::OpenOptions::new()
+		.read(true)
+		.custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+		.open(path)
+}
+
+fn get_flags(file: &fs::File) -> io::Result<libc::c_int> {
+	let mut flags: libc::c_int = 0;
+	unsafe {
+		match libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) {
+			0 => Ok(flags),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Returns the immutable and append-only flags of file or directory `path`, read with
+/// `FS_IOC_GETFLAGS`. Filesystems without inode flags have none.
+pub fn protected_flags(path: &Path) -> io::Result<u32> {
+	let file = match open_for_flags(path) {
+		Ok(file) => file,
+		// a path which cannot be opened, like a file with mode 000, can still be renamed, and
+		// only privileged users may set the flags anyway
+		Err(ref err) if matches!(err.raw_os_error(), Some(libc::EACCES) | Some(libc::EPERM)) => return Ok(0),
+		Err(err) => return Err(err),
+	};
+	match get_flags(&file) {
+		Ok(flags) => Ok((flags & (FS_IMMUTABLE_FL | FS_APPEND_FL)) as u32),
+		Err(ref err) if matches!(err.raw_os_error(), Some(libc::ENOTTY) | Some(libc::EOPNOTSUPP) | Some(libc::EINVAL)) => Ok(0),
+		Err(err) => Err(err),
+	}
+}
+
+/// Sets the immutable and append-only flags of file or directory `path` to `protected`, keeping
+/// its other flags, with `FS_IOC_SETFLAGS`. Requires `CAP_LINUX_IMMUTABLE`.
+pub fn set_protected_flags(path: &Path, protected: u32) -> io::Result<()> {
+	let file = open_for_flags(path)?;
+	let mask = FS_IMMUTABLE_FL | FS_APPEND_FL;
+	let flags = (get_flags(&file)? & !mask) | (protected as libc::c_int & mask);
+	unsafe {
+		match libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) {
+			0 => Ok(()),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Returns the path currently linked to the open `file`, read from `/proc/self/fd`.
+pub fn fd_path(file: &fs::File) -> io::Result<PathBuf> {
+	let path = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+	if path.as_os_str().as_bytes().ends_with(b" (deleted)") {
+		return Err(io::Error::new(io::ErrorKind::NotFound, "File was deleted"));
+	}
+	Ok(path)
+}
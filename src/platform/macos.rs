@@ -1,27 +1,38 @@
 extern crate libc;
 extern crate libloading;
 
+use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
-use std::{io, ffi};
+use std::os::unix::io::AsRawFd;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::{io, ffi, fs};
 use self::libloading::os::unix::{Library, Symbol};
+use super::unix::ParentDir;
 
 lazy_static! {
 	/// `renamex_np` is available only on macos >= 10.12
-	static ref RENAMEX_NP: Option<Symbol<unsafe extern fn (oldpath: *const libc::c_char, newpath: *const libc::c_char, flags: libc::c_uint) -> libc::c_int>> = unsafe {
+	static ref RENAMEX_NP: Option<Symbol<unsafe extern "C" fn (oldpath: *const libc::c_char, newpath: *const libc::c_char, flags: libc::c_uint) -> libc::c_int>> = unsafe {
 		let lib = Library::this();
 		lib.get(b"renamex_np").ok()
 	};
+
+	/// `renameatx_np` is available only on macos >= 10.12
+	static ref RENAMEATX_NP: Option<Symbol<unsafe extern "C" fn (fromfd: libc::c_int, from: *const libc::c_char, tofd: libc::c_int, to: *const libc::c_char, flags: libc::c_uint) -> libc::c_int>> = unsafe {
+		let lib = Library::this();
+		lib.get(b"renameatx_np").ok()
+	};
 }
 
+const RENAME_SWAP: libc::c_uint = 2;
+const RENAME_EXCL: libc::c_uint = 4;
+
 
-extern "stdcall" {
+extern "C" {
 	fn exchangedata(oldpath: *const libc::c_char, newpath: *const libc::c_char, flags: libc::c_uint) -> libc::c_int;
 }
 
 pub fn swap<A, B>(a: A, b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
-	const RENAME_SWAP: libc::c_uint = 2;
-
 	let a_path = ffi::CString::new(a.as_ref().as_os_str().as_bytes())?;
 	let b_path = ffi::CString::new(b.as_ref().as_os_str().as_bytes())?;
 
@@ -37,15 +48,142 @@ pub fn swap<A, B>(a: A, b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<P
 			// if it's not APFS, let's fallback to `exchangedata`
 			let err = *libc::__error();
 			if err != libc::ENOTSUP {
-				return Err(io::Error::new(io::ErrorKind::Other, format!("renamex_np failed with code: {}", err)));
+				return Err(io::Error::from_raw_os_error(err));
 			}
 		}
 
-		// `exchangedata` does not support swapping directories
+		// `exchangedata` does not support swapping directories and is not available on APFS,
+		// `ENOTSUP` or `EINVAL` make the caller fallback to the nonatomic swap
 		if exchangedata(a_path.as_ptr(), b_path.as_ptr(), 0) == 0 {
 			return Ok(())
 		}
 
-		Err(io::Error::new(io::ErrorKind::Other, format!("exchangedata failed with code: {}", *libc::__error())))
+		Err(io::Error::last_os_error())
+	}
+}
+
+/// Exchanges `a` and `b` with `renamex_np` only. Unlike `swap`, there is no fallback to
+/// `exchangedata`, which exchanges the data of the files rather than their entries.
+pub fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+	let a = ffi::CString::new(a.as_os_str().as_bytes())?;
+	let b = ffi::CString::new(b.as_os_str().as_bytes())?;
+
+	unsafe {
+		match *RENAMEX_NP {
+			Some(ref renamex_np) => match renamex_np(a.as_ptr(), b.as_ptr(), RENAME_SWAP) {
+				0 => Ok(()),
+				_ => Err(io::Error::last_os_error()),
+			},
+			None => Err(io::Error::from_raw_os_error(libc::ENOTSUP)),
+		}
+	}
+}
+
+/// Renames `from` to `to` with `renamex_np`, failing with `AlreadyExists` if `to` exists.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+	let from = ffi::CString::new(from.as_os_str().as_bytes())?;
+	let to = ffi::CString::new(to.as_os_str().as_bytes())?;
+
+	unsafe {
+		match *RENAMEX_NP {
+			Some(ref renamex_np) => match renamex_np(from.as_ptr(), to.as_ptr(), RENAME_EXCL) {
+				0 => Ok(()),
+				_ => Err(io::Error::last_os_error()),
+			},
+			None => Err(io::Error::from_raw_os_error(libc::ENOTSUP)),
+		}
+	}
+}
+
+/// Swaps the entries of opened parent directories `a` and `b` with `renameatx_np`.
+///
+/// There is no fallback to `exchangedata`, which works only with paths.
+pub fn swap_parents(a: &ParentDir, b: &ParentDir) -> io::Result<()> {
+	unsafe {
+		match *RENAMEATX_NP {
+			Some(ref renameatx_np) => match renameatx_np(a.fd(), a.name(), b.fd(), b.name(), RENAME_SWAP) {
+				0 => Ok(()),
+				_ => Err(io::Error::last_os_error()),
+			},
+			None => Err(io::Error::from_raw_os_error(libc::ENOTSUP)),
+		}
+	}
+}
+
+/// Swaps entries `a` and `b` of open directory `dir` with `renameatx_np`.
+pub fn swap_in_dir(dir: &fs::File, a: &OsStr, b: &OsStr) -> io::Result<()> {
+	swap_in_dirs(dir, a, dir, b)
+}
+
+/// Swaps entry `a` of open directory `dir_a` and entry `b` of open directory `dir_b` with
+/// `renameatx_np`.
+pub fn swap_in_dirs(dir_a: &fs::File, a: &OsStr, dir_b: &fs::File, b: &OsStr) -> io::Result<()> {
+	let a = ffi::CString::new(a.as_bytes())?;
+	let b = ffi::CString::new(b.as_bytes())?;
+
+	unsafe {
+		match *RENAMEATX_NP {
+			Some(ref renameatx_np) => match renameatx_np(dir_a.as_raw_fd(), a.as_ptr(), dir_b.as_raw_fd(), b.as_ptr(), RENAME_SWAP) {
+				0 => Ok(()),
+				_ => Err(io::Error::last_os_error()),
+			},
+			None => Err(io::Error::from_raw_os_error(libc::ENOTSUP)),
+		}
+	}
+}
+
+/// Returns true if `err` was caused by an attempt to swap paths on different filesystems.
+pub fn is_cross_device(err: &io::Error) -> bool {
+	err.raw_os_error() == Some(libc::EXDEV)
+}
+
+/// Returns true if `err` indicates that neither `renamex_np` nor `exchangedata` can swap the paths.
+pub fn is_atomic_unsupported(err: &io::Error) -> bool {
+	matches!(err.raw_os_error(), Some(libc::ENOTSUP) | Some(libc::EINVAL))
+}
+
+/// Errors are never considered transient.
+pub fn is_transient(_err: &io::Error) -> bool {
+	false
+}
+
+/// Creates `to` as a copy-on-write clone of file `from` with `clonefile`.
+///
+/// Fails if the filesystem is not APFS or if the files are on different filesystems.
+pub fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+	const CLONE_NOFOLLOW: u32 = 1;
+
+	let from = ffi::CString::new(from.as_os_str().as_bytes())?;
+	let to = ffi::CString::new(to.as_os_str().as_bytes())?;
+
+	unsafe {
+		match libc::clonefile(from.as_ptr(), to.as_ptr(), CLONE_NOFOLLOW) {
+			0 => Ok(()),
+			_ => Err(io::Error::last_os_error()),
+		}
 	}
 }
+
+/// Returns the path currently linked to the open `file`, read with `fcntl(F_GETPATH)`.
+pub fn fd_path(file: &fs::File) -> io::Result<PathBuf> {
+	const MAXPATHLEN: usize = 1024;
+
+	let mut buf = vec![0u8; MAXPATHLEN];
+	unsafe {
+		if libc::fcntl(file.as_raw_fd(), libc::F_GETPATH, buf.as_mut_ptr()) == -1 {
+			return Err(io::Error::last_os_error());
+		}
+	}
+
+	let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+	Ok(PathBuf::from(OsStr::from_bytes(&buf[..len])))
+}
+
+/// Holes of sparse files are not detected, they are copied as data.
+pub fn data_ranges(_file: &fs::File) -> io::Result<Option<Vec<Range<u64>>>> {
+	Ok(None)
+}
+
+pub fn set_sparse(_file: &fs::File) -> io::Result<()> {
+	Ok(())
+}
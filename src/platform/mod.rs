@@ -1,17 +1,51 @@
+#[cfg(unix)]
+mod unix;
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
 #[cfg(windows)]
 mod windows;
-#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+#[cfg(target_os = "wasi")]
+mod wasi;
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows, target_os = "wasi")))]
 mod unsupported;
+#[cfg(any(all(feature = "xattr", any(target_os = "linux", target_os = "macos")), all(feature = "selinux", target_os = "linux")))]
+mod xattr;
 
 #[cfg(target_os = "linux")]
-pub use self::linux::swap;
+pub use self::linux::{swap, exchange, rename_noreplace, is_cross_device, is_atomic_unsupported, is_transient, reflink, data_ranges, set_sparse};
 #[cfg(target_os = "macos")]
-pub use self::macos::swap;
+pub use self::macos::{swap, exchange, rename_noreplace, is_cross_device, is_atomic_unsupported, is_transient, reflink, data_ranges, set_sparse};
 #[cfg(windows)]
-pub use self::windows::swap;
-#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
-pub use self::unsupported::swap;
+pub use self::windows::{swap, exchange, rename_noreplace, is_cross_device, is_atomic_unsupported, is_transient, reflink, data_ranges, set_sparse};
+#[cfg(target_os = "wasi")]
+pub use self::wasi::{swap, exchange, rename_noreplace, is_cross_device, is_atomic_unsupported, is_transient, reflink, data_ranges, set_sparse};
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows, target_os = "wasi")))]
+pub use self::unsupported::{swap, exchange, rename_noreplace, is_cross_device, is_atomic_unsupported, is_transient, reflink, data_ranges, set_sparse};
+
+#[cfg(unix)]
+pub use self::unix::{same_device, same_device_metadata, same_file, atomic_supported, copy_special, explain_sticky, is_read_only, sync, set_owner, lock_exclusive};
+#[cfg(windows)]
+pub use self::windows::{same_device, same_device_metadata, same_file, atomic_supported, is_read_only, sync, set_owner, lock_exclusive};
+#[cfg(target_os = "wasi")]
+pub use self::wasi::{same_device, same_device_metadata, same_file, atomic_supported, is_read_only, sync, set_owner, lock_exclusive};
+#[cfg(not(any(unix, windows, target_os = "wasi")))]
+pub use self::unsupported::{same_device, same_device_metadata, same_file, atomic_supported, is_read_only, sync, set_owner, lock_exclusive};
+
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+pub use self::xattr::copy_xattrs;
+#[cfg(all(feature = "selinux", target_os = "linux"))]
+pub use self::xattr::copy_selinux_context;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::{fd_path, swap_in_dir, swap_in_dirs, swap_parents, protected_flags, set_protected_flags};
+#[cfg(target_os = "macos")]
+pub use self::macos::{fd_path, swap_in_dir, swap_in_dirs, swap_parents};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub use self::unix::{open_dir, EntryStat, ParentDir};
+
+#[cfg(windows)]
+pub use self::windows::{is_dir_link, check_device, clear_readonly, set_readonly};
+#[cfg(all(feature = "acl", windows))]
+pub use self::windows::copy_security;
@@ -0,0 +1,209 @@
+extern crate libc;
+
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::{ffi, fs, io};
+
+/// Parent directory of a path, opened once, and the last component of the path.
+///
+/// Operations relative to the directory descriptor don't resolve the parent path again.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub struct ParentDir {
+	dir: fs::File,
+	name: ffi::CString,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl ParentDir {
+	/// Opens the parent directory of `path`. If `nofollow` is true, fails if the last component
+	/// of the parent is a symlink.
+	pub fn open(path: &Path, nofollow: bool) -> io::Result<Self> {
+		let name = path.file_name()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+		let parent = match path.parent() {
+			Some(parent) if parent != Path::new("") => parent,
+			_ => Path::new("."),
+		};
+
+		Ok(ParentDir {
+			dir: open_dir(parent, nofollow)?,
+			name: ffi::CString::new(name.as_bytes())?,
+		})
+	}
+
+	pub fn fd(&self) -> libc::c_int {
+		self.dir.as_raw_fd()
+	}
+
+	pub fn name(&self) -> *const libc::c_char {
+		self.name.as_ptr()
+	}
+
+	/// Reads the metadata of the entry with `fstatat`, without following a symlink.
+	pub fn stat(&self) -> io::Result<EntryStat> {
+		let mut stat: libc::stat = unsafe { ::std::mem::zeroed() };
+		match unsafe { libc::fstatat(self.fd(), self.name(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) } {
+			0 => Ok(EntryStat {
+				dev: stat.st_dev as u64,
+				ino: stat.st_ino as u64,
+				is_dir: stat.st_mode & libc::S_IFMT == libc::S_IFDIR,
+			}),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Identity and type of an entry, read relative to its opened parent directory.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub struct EntryStat {
+	pub dev: u64,
+	pub ino: u64,
+	pub is_dir: bool,
+}
+
+/// Opens directory `path`, to be used as the base of relative syscalls. If `nofollow` is true,
+/// fails if the last component of `path` is a symlink.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn open_dir(path: &Path, nofollow: bool) -> io::Result<fs::File> {
+	let flags = if nofollow { libc::O_DIRECTORY | libc::O_NOFOLLOW } else { libc::O_DIRECTORY };
+	fs::OpenOptions::new()
+		.read(true)
+		.custom_flags(flags)
+		.open(path)
+}
+
+/// Returns true if `a` and `b` are located on the same device.
+pub fn same_device(a: &Path, b: &Path) -> io::Result<bool> {
+	Ok(fs::symlink_metadata(a)?.dev() == fs::symlink_metadata(b)?.dev())
+}
+
+/// Returns true if `a` and `b`, whose metadata was already read, are located on the same device.
+pub fn same_device_metadata(_a: &Path, meta_a: &fs::Metadata, _b: &Path, meta_b: &fs::Metadata) -> io::Result<bool> {
+	Ok(meta_a.dev() == meta_b.dev())
+}
+
+/// Returns true if `a` and `b`, whose metadata was already read, are the same inode, for instance
+/// two hardlinks to the same file.
+pub fn same_file(_a: &Path, meta_a: &fs::Metadata, _b: &Path, meta_b: &fs::Metadata) -> io::Result<bool> {
+	Ok(meta_a.dev() == meta_b.dev() && meta_a.ino() == meta_b.ino())
+}
+
+/// Returns true if the filesystem of `dir` supports atomic swaps.
+///
+/// Two scratch files are created in `dir`, swapped and removed.
+pub fn atomic_supported(dir: &Path) -> io::Result<bool> {
+	let a = ::tmp_path_in_dir(dir)?;
+	fs::OpenOptions::new().write(true).create_new(true).open(&a)?;
+	let result = ::tmp_path_in_dir(dir)
+		.and_then(|b| {
+			let result = fs::OpenOptions::new().write(true).create_new(true).open(&b)
+				.and_then(|_| match super::swap(&a, &b) {
+					Ok(_) => Ok(true),
+					Err(ref err) if super::is_atomic_unsupported(err) => Ok(false),
+					Err(err) => Err(err),
+				});
+			let _ = fs::remove_file(&b);
+			result
+		});
+	let _ = fs::remove_file(&a);
+	result
+}
+
+/// Creates `to` as a special file of the same type and device as `from`, with `mknod`.
+pub fn copy_special(from: &Path, to: &Path) -> io::Result<()> {
+	let metadata = fs::symlink_metadata(from)?;
+	let to = ffi::CString::new(to.as_os_str().as_bytes())?;
+
+	unsafe {
+		match libc::mknod(to.as_ptr(), metadata.mode() as libc::mode_t, metadata.rdev() as libc::dev_t) {
+			0 => Ok(()),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Returns true if `path` is located on a filesystem mounted read-only.
+pub fn is_read_only(path: &Path) -> io::Result<bool> {
+	let path = ffi::CString::new(path.as_os_str().as_bytes())?;
+
+	unsafe {
+		let mut stat: libc::statvfs = ::std::mem::zeroed();
+		match libc::statvfs(path.as_ptr(), &mut stat) {
+			0 => Ok(stat.f_flag & libc::ST_RDONLY != 0),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Returns a more precise error than `err` if it's an `EPERM` caused by `a` or `b` being owned by
+/// another user in a directory with the sticky bit set, like `/tmp`. Only the owner of such an
+/// entry, or of the directory, can rename or remove it.
+pub fn explain_sticky(err: io::Error, a: &Path, b: &Path) -> io::Error {
+	if err.raw_os_error() != Some(libc::EPERM) {
+		return err;
+	}
+
+	let euid = unsafe { libc::geteuid() };
+	match [a, b].iter().find(|path| is_sticky_restricted(path, euid)) {
+		Some(path) => {
+			let message = format!("{:?} is owned by another user in a directory with the sticky bit set, only its owner can move it", path);
+			io::Error::new(io::ErrorKind::PermissionDenied, message)
+		},
+		None => err,
+	}
+}
+
+/// `S_ISVTX`, whose type differs between platforms.
+const STICKY_BIT: u32 = 0o1000;
+
+fn is_sticky_restricted(path: &Path, euid: libc::uid_t) -> bool {
+	let parent = match path.parent() {
+		Some(parent) if parent != Path::new("") => parent,
+		_ => Path::new("."),
+	};
+	match (fs::symlink_metadata(path), fs::metadata(parent)) {
+		(Ok(entry), Ok(dir)) => dir.mode() & STICKY_BIT != 0 && entry.uid() != euid && dir.uid() != euid,
+		_ => false,
+	}
+}
+
+/// Flushes the content and metadata of file or directory at `path` to disk.
+pub fn sync(path: &Path) -> io::Result<()> {
+	fs::File::open(path)?.sync_all()
+}
+
+/// Changes the owner of `path` to the owner of `metadata`, if they differ.
+pub fn set_owner(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+	let current = fs::symlink_metadata(path)?;
+	if current.uid() == metadata.uid() && current.gid() == metadata.gid() {
+		return Ok(());
+	}
+
+	let path = ffi::CString::new(path.as_os_str().as_bytes())?;
+	unsafe {
+		match libc::lchown(path.as_ptr(), metadata.uid(), metadata.gid()) {
+			0 => Ok(()),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+}
+
+/// Locks `file` exclusively with `flock`, waiting until no other process holds a lock on it.
+/// The lock is released when the file is closed.
+pub fn lock_exclusive(file: &fs::File) -> io::Result<()> {
+	loop {
+		match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } {
+			0 => return Ok(()),
+			_ => {
+				let err = io::Error::last_os_error();
+				if err.kind() != io::ErrorKind::Interrupted {
+					return Err(err);
+				}
+			},
+		}
+	}
+}
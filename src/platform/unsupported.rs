@@ -1,6 +1,103 @@
-use std::io;
+//! Portable fallback for platforms without a specialized implementation, like the BSDs.
+//!
+//! Only `std::fs` is needed: swaps are always nonatomic, and paths are renamed one by one.
+
+#[cfg(unix)]
+extern crate libc;
+
+use std::{fs, io};
+use std::ops::Range;
 use std::path::Path;
 
+/// There is no atomic exchange, so the returned error makes the caller fallback to the nonatomic
+/// swap, which only needs `fs::rename`.
 pub fn swap<A, B>(_a: A, _b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
-	Err(io::Error::new(io::ErrorKind::Other, "PathSwap not supported by the current platform"))
+	Err(io::Error::new(io::ErrorKind::Unsupported, "Atomic swaps are not supported by the current platform"))
+}
+
+pub fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::Unsupported, "Atomic exchanges are not supported by the current platform"))
+}
+
+/// Renames `from` to `to` after checking that `to` does not exist, which is not atomic.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+	if fs::symlink_metadata(to).is_ok() {
+		return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Target path already exists"));
+	}
+	fs::rename(from, to)
+}
+
+/// Returns true if `err` was caused by an attempt to rename paths across filesystems.
+#[cfg(unix)]
+pub fn is_cross_device(err: &io::Error) -> bool {
+	err.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(not(unix))]
+pub fn is_cross_device(_err: &io::Error) -> bool {
+	false
+}
+
+#[cfg(not(unix))]
+pub fn same_device(_a: &Path, _b: &Path) -> io::Result<bool> {
+	Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn same_device_metadata(_a: &Path, _meta_a: &fs::Metadata, _b: &Path, _meta_b: &fs::Metadata) -> io::Result<bool> {
+	Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn same_file(_a: &Path, _meta_a: &fs::Metadata, _b: &Path, _meta_b: &fs::Metadata) -> io::Result<bool> {
+	Ok(false)
+}
+
+/// Returns true if `err` is the error returned by `swap`.
+pub fn is_atomic_unsupported(err: &io::Error) -> bool {
+	err.kind() == io::ErrorKind::Unsupported
+}
+
+/// Errors are never considered transient.
+pub fn is_transient(_err: &io::Error) -> bool {
+	false
+}
+
+#[cfg(not(unix))]
+pub fn atomic_supported(_dir: &Path) -> io::Result<bool> {
+	Ok(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_read_only(_path: &Path) -> io::Result<bool> {
+	Ok(false)
+}
+
+#[cfg(not(unix))]
+pub fn sync(_path: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+/// File locks are not supported, so concurrent swaps are not serialized.
+#[cfg(not(unix))]
+pub fn lock_exclusive(_file: &fs::File) -> io::Result<()> {
+	Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_owner(_path: &Path, _metadata: &fs::Metadata) -> io::Result<()> {
+	Ok(())
+}
+
+pub fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+	Err(io::Error::other("Reflinks are not supported by the current platform"))
+}
+
+/// Holes of sparse files are not detected, they are copied as data.
+pub fn data_ranges(_file: &fs::File) -> io::Result<Option<Vec<Range<u64>>>> {
+	Ok(None)
+}
+
+pub fn set_sparse(_file: &fs::File) -> io::Result<()> {
+	Ok(())
 }
@@ -0,0 +1,91 @@
+use std::{fs, io};
+use std::ops::Range;
+use std::path::Path;
+
+/// `EXDEV` in WASI preview 1.
+const ERRNO_XDEV: i32 = 75;
+
+/// WASI has no atomic exchange, so the returned error makes the caller fallback to the nonatomic
+/// swap, which only needs `path_rename`.
+pub fn swap<A, B>(_a: A, _b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
+	Err(io::Error::new(io::ErrorKind::Unsupported, "Atomic swaps are not supported by WASI"))
+}
+
+pub fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::Unsupported, "Atomic exchanges are not supported by WASI"))
+}
+
+/// `path_rename` always replaces the target, so the returned error makes the caller check that
+/// it does not exist before renaming.
+pub fn rename_noreplace(_from: &Path, _to: &Path) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::Unsupported, "Renames without replacement are not supported by WASI"))
+}
+
+/// Returns true if `err` was caused by an attempt to rename paths across preopened directories
+/// on different filesystems.
+pub fn is_cross_device(err: &io::Error) -> bool {
+	err.raw_os_error() == Some(ERRNO_XDEV)
+}
+
+/// The device of a path is not exposed by the stable WASI API, so paths are assumed to be on
+/// the same filesystem. A nonatomic swap across filesystems fails on its first cross-device
+/// rename, which is reverted.
+pub fn same_device(_a: &Path, _b: &Path) -> io::Result<bool> {
+	Ok(true)
+}
+
+pub fn same_device_metadata(a: &Path, _meta_a: &fs::Metadata, b: &Path, _meta_b: &fs::Metadata) -> io::Result<bool> {
+	same_device(a, b)
+}
+
+/// Inodes are not exposed by the stable WASI API, so paths are never considered the same file.
+pub fn same_file(_a: &Path, _meta_a: &fs::Metadata, _b: &Path, _meta_b: &fs::Metadata) -> io::Result<bool> {
+	Ok(false)
+}
+
+/// Returns true if `err` is the error returned by `swap`.
+pub fn is_atomic_unsupported(err: &io::Error) -> bool {
+	err.kind() == io::ErrorKind::Unsupported
+}
+
+/// Errors are never considered transient.
+pub fn is_transient(_err: &io::Error) -> bool {
+	false
+}
+
+pub fn atomic_supported(_dir: &Path) -> io::Result<bool> {
+	Ok(false)
+}
+
+/// Read-only mounts are not exposed by WASI.
+pub fn is_read_only(_path: &Path) -> io::Result<bool> {
+	Ok(false)
+}
+
+/// Flushes the content and metadata of file or directory at `path` to disk with `fd_sync`.
+pub fn sync(path: &Path) -> io::Result<()> {
+	fs::File::open(path)?.sync_all()
+}
+
+/// Ownership is not exposed by WASI.
+pub fn set_owner(_path: &Path, _metadata: &fs::Metadata) -> io::Result<()> {
+	Ok(())
+}
+
+pub fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+	Err(io::Error::other("Reflinks are not supported by WASI"))
+}
+
+/// Holes of sparse files are not detected, they are copied as data.
+pub fn data_ranges(_file: &fs::File) -> io::Result<Option<Vec<Range<u64>>>> {
+	Ok(None)
+}
+
+pub fn set_sparse(_file: &fs::File) -> io::Result<()> {
+	Ok(())
+}
+
+/// WASI has no file locks, so concurrent swaps are not serialized.
+pub fn lock_exclusive(_file: &fs::File) -> io::Result<()> {
+	Ok(())
+}
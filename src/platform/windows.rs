@@ -1,36 +1,28 @@
 extern crate winapi;
 
 use std::ffi::{OsStr, OsString};
-use std::os::windows::ffi::{OsStringExt, OsStrExt};
-use std::path::Path;
-use std::{io, fs, ptr};
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::fs::{MetadataExt, OpenOptionsExt};
+use std::os::windows::io::AsRawHandle;
+use std::ops::Range;
+use std::path::{Path, Component, Prefix};
+use std::{io, fs, mem, ptr};
+use error::{CorruptedState, SwapError};
 
-use self::winapi::shared::minwindef::{MAX_PATH, FALSE};
+use self::winapi::shared::minwindef::{DWORD, FALSE, LPVOID, MAX_PATH};
 use self::winapi::shared::ntdef::HANDLE;
+use self::winapi::shared::winerror::{
+	ERROR_ACCESS_DENIED, ERROR_LOCK_VIOLATION, ERROR_MORE_DATA, ERROR_NOT_SAME_DEVICE, ERROR_NOT_SUPPORTED, ERROR_RM_NOT_ACTIVE,
+	ERROR_SHARING_VIOLATION, ERROR_TRANSACTIONS_UNSUPPORTED_REMOTE,
+};
 use self::winapi::um::errhandlingapi::GetLastError;
-use self::winapi::um::fileapi::GetTempFileNameW;
-use self::winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use self::winapi::um::fileapi::{BY_HANDLE_FILE_INFORMATION, GetFileInformationByHandle, GetVolumeInformationW, GetVolumePathNameW};
+use self::winapi::um::handleapi::{INVALID_HANDLE_VALUE, CloseHandle};
+use self::winapi::um::ioapiset::DeviceIoControl;
 use self::winapi::um::ktmw32::{CreateTransaction, RollbackTransaction, CommitTransaction};
-use self::winapi::um::winbase::{MoveFileTransactedW, DeleteFileTransactedW};
-
-/// Function used to create a temporary file in given directory
-fn tmp_name_in_dir(dir: &Path) -> io::Result<OsString> {
-	let mut out = Vec::with_capacity(MAX_PATH);
-	let pre: Vec<u16> = OsStr::new("tmp").encode_wide().chain(Some(0)).collect();
-	let dir: Vec<u16> = OsStr::new(dir).encode_wide().chain(Some(0)).collect();
-
-	unsafe {
-		if GetTempFileNameW(dir.as_ptr(), pre.as_ptr(), 0, out.as_mut_ptr()) != 0 {
-			out.set_len(MAX_PATH);
-			let n = out.iter()
-				.position(|&x| x == 0)
-				.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "GetTempFileNameW returned invalid path".to_string()))?;
-			Ok(OsString::from_wide(&out[..n]))
-		} else {
-			Err(io::Error::new(io::ErrorKind::Other, format!("GetTempFileNameW failed with code: {}", GetLastError())))
-		}
-	}
-}
+use self::winapi::um::winioctl::{FSCTL_QUERY_ALLOCATED_RANGES, FSCTL_SET_SPARSE};
+use self::winapi::um::winnt::{FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_SPARSE_FILE, FILE_READ_ONLY_VOLUME};
+use self::winapi::um::winbase::{MoveFileTransactedW, MoveFileExW, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT};
 
 struct Transaction(HANDLE);
 
@@ -39,75 +31,488 @@ impl Transaction {
 		unsafe {
 			let handle = CreateTransaction(ptr::null_mut(), ptr::null_mut(), 0, 0, 0, 0, ptr::null_mut());
 			if handle == INVALID_HANDLE_VALUE {
-				return Err(io::Error::new(io::ErrorKind::Other, format!("ktmw32::CreateTransaction failed with code: {}", GetLastError())));
+				return Err(io::Error::from_raw_os_error(GetLastError() as i32));
 			}
 			Ok(Transaction(handle))
 		}
 	}
 
-	fn delete_file<A>(&self, a: A) -> io::Result<()> where A: AsRef<OsStr> {
+	fn move_file<A, B>(&self, a: A, b: B) -> io::Result<()> where A: AsRef<OsStr>, B: AsRef<OsStr> {
 		let a: Vec<u16> = a.as_ref().encode_wide().chain(Some(0)).collect();
+		let b: Vec<u16> = b.as_ref().encode_wide().chain(Some(0)).collect();
 
 		unsafe {
-			if DeleteFileTransactedW(a.as_ptr(), self.0) != FALSE {
+			if MoveFileTransactedW(a.as_ptr(), b.as_ptr(), None, ptr::null_mut(), 0, self.0) != FALSE {
 				Ok(())
 			} else {
-				let error = GetLastError();
-				self.rollback();
-				Err(io::Error::new(io::ErrorKind::Other, format!("DeleteFileTransactedW failed with code: {}", error)))
+				Err(io::Error::from_raw_os_error(GetLastError() as i32))
 			}
 		}
 	}
 
-	fn move_file<A, B>(&self, a: A, b: B) -> io::Result<()> where A: AsRef<OsStr>, B: AsRef<OsStr> {
-		let a: Vec<u16> = a.as_ref().encode_wide().chain(Some(0)).collect();
-		let b: Vec<u16> = b.as_ref().encode_wide().chain(Some(0)).collect();
-
+	fn commit(&self) -> io::Result<()> {
 		unsafe {
-			if MoveFileTransactedW(a.as_ptr(), b.as_ptr(), None, ptr::null_mut(), 0, self.0) != FALSE {
+			if CommitTransaction(self.0) != FALSE {
 				Ok(())
 			} else {
 				let error = GetLastError();
-				self.rollback();
-				Err(io::Error::new(io::ErrorKind::Other, format!("MoveFileTransactedW failed with code: {}", error)))
+				Err(io::Error::new(io::ErrorKind::Other, format!("CommitTransaction failed with code: {}", error)))
 			}
 		}
 	}
 
-	fn commit(&self) -> io::Result<()> {
+	fn rollback(&self) -> io::Result<()> {
 		unsafe {
-			if CommitTransaction(self.0) != FALSE {
+			if RollbackTransaction(self.0) != FALSE {
 				Ok(())
 			} else {
-				let error = GetLastError();
-				self.rollback();
-				Err(io::Error::new(io::ErrorKind::Other, format!("CommitTransaction failed with code: {}", error)))
+				Err(io::Error::from_raw_os_error(GetLastError() as i32))
 			}
 		}
 	}
 
-	fn rollback(&self) {
+	/// Rolls back the transaction after `error`, returning an error holding
+	/// `SwapError::Corrupted` with `state` if the rollback fails.
+	fn abort(&self, error: io::Error, state: CorruptedState) -> io::Error {
+		match self.rollback() {
+			Ok(()) => error,
+			Err(rollback_error) => corrupted(error, rollback_error, state),
+		}
+	}
+}
+
+impl Drop for Transaction {
+	fn drop(&mut self) {
 		unsafe {
-			// we simply ignore an error over here, cause there is nothing we can do to recover from this state
-			let _ = RollbackTransaction(self.0);
+			CloseHandle(self.0);
+		}
+	}
+}
+
+/// Returns true if `err` indicates that transactions are not supported for given paths.
+fn is_transaction_unsupported(err: &io::Error) -> bool {
+	match err.raw_os_error() {
+		Some(code) => {
+			let code = code as u32;
+			code == ERROR_NOT_SUPPORTED || code == ERROR_RM_NOT_ACTIVE || code == ERROR_TRANSACTIONS_UNSUPPORTED_REMOTE
+		},
+		None => false,
+	}
+}
+
+/// Renames `from` to `to` with `MoveFileExW`, which fails with `AlreadyExists` if `to` exists
+/// since `MOVEFILE_REPLACE_EXISTING` is not set.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+	let from: Vec<u16> = from.as_os_str().encode_wide().chain(Some(0)).collect();
+	let to: Vec<u16> = to.as_os_str().encode_wide().chain(Some(0)).collect();
+
+	unsafe {
+		if MoveFileExW(from.as_ptr(), to.as_ptr(), 0) != FALSE {
+			Ok(())
+		} else {
+			Err(io::Error::from_raw_os_error(GetLastError() as i32))
 		}
 	}
 }
 
+/// Swaps the paths with three moves in a transaction, which is rolled back if a step fails.
+///
+/// If the rollback fails, the returned error holds `SwapError::Corrupted` describing the moves
+/// made in the transaction.
+fn swap_transacted(a: &Path, b: &Path, tmp: &Path) -> io::Result<()> {
+	let transaction = Transaction::new()?;
+	if let Err(err) = transaction.move_file(a, tmp) {
+		return Err(transaction.abort(err, transacted_state(tmp, &[], Some((a, tmp)))));
+	}
+	if let Err(err) = transaction.move_file(b, a) {
+		return Err(transaction.abort(err, transacted_state(tmp, &[(a, tmp)], Some((b, a)))));
+	}
+	if let Err(err) = transaction.move_file(tmp, b) {
+		return Err(transaction.abort(err, transacted_state(tmp, &[(a, tmp), (b, a)], Some((tmp, b)))));
+	}
+	transaction.commit()
+		.map_err(|err| transaction.abort(err, transacted_state(tmp, &[(a, b), (b, a)], None)))
+}
+
+/// Returns an `io::Error` holding `SwapError::Corrupted`, which `SwapError::from_unmodified`
+/// converts back.
+fn corrupted(error: io::Error, rollback_error: io::Error, state: CorruptedState) -> io::Error {
+	SwapError::Corrupted { error, rollback_error, state: Box::new(state) }.into()
+}
+
+/// Describes the state left by `swap_transacted` through `tmp` when its transaction could not
+/// be rolled back, `moved` being the moves made in the transaction.
+fn transacted_state(tmp: &Path, moved: &[(&Path, &Path)], failed_rename: Option<(&Path, &Path)>) -> CorruptedState {
+	let owned = |(from, to): (&Path, &Path)| (from.to_path_buf(), to.to_path_buf());
+	CorruptedState {
+		moved: moved.iter().cloned().map(owned).collect(),
+		temp_path: Some(tmp.to_path_buf()),
+		failed_rename: failed_rename.map(owned),
+		failed_revert: None,
+	}
+}
+
 pub fn swap<A, B>(a: A, b: B) -> io::Result<()> where A: AsRef<Path>, B: AsRef<Path> {
-	let a = fs::canonicalize(a)?;
-	let b = fs::canonicalize(b)?;
+	// symlinks must not be followed, so that the links are swapped instead of their targets
+	// canonical paths have the `\\?\` prefix, so paths longer than `MAX_PATH` are supported
+	let a = ::canonicalize_parent(a.as_ref())?;
+	let b = ::canonicalize_parent(b.as_ref())?;
 
 	let parent_dir = a.parent()
 		.or_else(|| b.parent())
 		.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))?;
 
-	let tmp = tmp_name_in_dir(parent_dir)?;
+	let tmp = ::tmp_path_in_dir(parent_dir)?;
 
-	let transaction = Transaction::new()?;
-	transaction.delete_file(&tmp)?;
-	transaction.move_file(&a, &tmp)?;
-	transaction.move_file(&b, &a)?;
-	transaction.move_file(&tmp, &b)?;
-	transaction.commit()
+	// read-only files can't be replaced, the caller clears the attribute, see `readonly`
+	// without transactions, the error is reported by `is_atomic_unsupported`, so that the caller
+	// falls back to a nonatomic swap only if it's allowed
+	swap_transacted(&a, &b, &tmp)
+}
+
+/// Clears the read-only attribute of file `path`, returning true if it was set.
+///
+/// Directories and symlinks are left untouched.
+#[allow(clippy::permissions_set_readonly_false)]
+pub fn clear_readonly(path: &Path) -> io::Result<bool> {
+	let metadata = fs::symlink_metadata(path)?;
+	let mut permissions = metadata.permissions();
+	if !metadata.is_file() || !permissions.readonly() {
+		return Ok(false);
+	}
+
+	permissions.set_readonly(false);
+	fs::set_permissions(path, permissions)?;
+	Ok(true)
+}
+
+/// Sets the read-only attribute of `path`.
+pub fn set_readonly(path: &Path) -> io::Result<()> {
+	let mut permissions = fs::symlink_metadata(path)?.permissions();
+	permissions.set_readonly(true);
+	fs::set_permissions(path, permissions)
+}
+
+/// Always fails, since the transacted swap moves a path to a temp path rather than exchanging them.
+pub fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::Unsupported, "Atomic exchanges are not supported by Windows"))
+}
+
+/// Returns true if `err` was caused by an attempt to swap paths on different volumes.
+pub fn is_cross_device(err: &io::Error) -> bool {
+	err.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE as i32)
+}
+
+/// Returns true if `a` and `b` are located on the same volume.
+///
+/// Only the parents are canonicalized, so a symlink or a junction is located on the volume of
+/// its parent rather than of its target. Volumes mounted in a directory of another volume are not
+/// detected.
+pub fn same_device(a: &Path, b: &Path) -> io::Result<bool> {
+	fn prefix(path: &Path) -> io::Result<Option<OsString>> {
+		Ok(::canonicalize_parent(path)?
+			.components()
+			.next()
+			.and_then(|c| match c {
+				Component::Prefix(prefix) => Some(prefix.as_os_str().to_ascii_lowercase()),
+				_ => None,
+			}))
+	}
+
+	Ok(prefix(a)? == prefix(b)?)
+}
+
+/// Returns true if `a` and `b` are located on the same volume. The metadata does not identify
+/// the volume, so it is looked up with `same_device`.
+pub fn same_device_metadata(a: &Path, _meta_a: &fs::Metadata, b: &Path, _meta_b: &fs::Metadata) -> io::Result<bool> {
+	same_device(a, b)
+}
+
+/// Returns true if `a` and `b` are the same file or directory, comparing the volume serial
+/// numbers and file indexes of their handles.
+///
+/// Files may be hardlinks to the same file. Since most volumes are case-insensitive, paths
+/// differing only by case may also name the same entry, which is the only way two directory
+/// paths can, so directories are opened only if their names are equal ignoring case.
+pub fn same_file(a: &Path, meta_a: &fs::Metadata, b: &Path, _meta_b: &fs::Metadata) -> io::Result<bool> {
+	if meta_a.is_dir() && !same_name_ignoring_case(a, b) {
+		return Ok(false);
+	}
+	Ok(file_id(a)? == file_id(b)?)
+}
+
+fn same_name_ignoring_case(a: &Path, b: &Path) -> bool {
+	match (a.file_name().and_then(|name| name.to_str()), b.file_name().and_then(|name| name.to_str())) {
+		(Some(name_a), Some(name_b)) => name_a.to_lowercase() == name_b.to_lowercase(),
+		_ => false,
+	}
+}
+
+fn file_id(path: &Path) -> io::Result<(DWORD, DWORD, DWORD)> {
+	let file = fs::OpenOptions::new()
+		.access_mode(0)
+		.custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+		.open(path)?;
+
+	unsafe {
+		let mut info: BY_HANDLE_FILE_INFORMATION = mem::zeroed();
+		if GetFileInformationByHandle(file.as_raw_handle() as HANDLE, &mut info) == FALSE {
+			return Err(io::Error::last_os_error());
+		}
+		Ok((info.dwVolumeSerialNumber, info.nFileIndexHigh, info.nFileIndexLow))
+	}
+}
+
+/// Returns true if `err` was caused by the volume not supporting transactions, which atomic
+/// swaps require.
+pub fn is_atomic_unsupported(err: &io::Error) -> bool {
+	is_transaction_unsupported(err)
+}
+
+/// Returns true if `err` may be caused by another process briefly opening the file, like an
+/// antivirus or an indexing service.
+pub fn is_transient(err: &io::Error) -> bool {
+	match err.raw_os_error() {
+		Some(code) => {
+			let code = code as u32;
+			code == ERROR_SHARING_VIOLATION || code == ERROR_ACCESS_DENIED || code == ERROR_LOCK_VIOLATION
+		},
+		None => false,
+	}
+}
+
+/// Returns true if the volume of `dir` supports transactions, which makes swaps atomic.
+///
+/// Two scratch files are created in `dir`, swapped in a transaction and removed.
+pub fn atomic_supported(dir: &Path) -> io::Result<bool> {
+	let a = ::tmp_path_in_dir(dir)?;
+	fs::OpenOptions::new().write(true).create_new(true).open(&a)?;
+	let result = ::tmp_path_in_dir(dir)
+		.and_then(|b| {
+			let result = fs::OpenOptions::new().write(true).create_new(true).open(&b)
+				.and_then(|_| ::tmp_path_in_dir(dir))
+				.and_then(|tmp| match swap_transacted(&a, &b, &tmp) {
+					Ok(_) => Ok(true),
+					Err(ref err) if is_transaction_unsupported(err) => Ok(false),
+					Err(err) => Err(err),
+				});
+			let _ = fs::remove_file(&b);
+			result
+		});
+	let _ = fs::remove_file(&a);
+	result
+}
+
+/// Returns true if `path` is located on a read-only or write-protected volume.
+pub fn is_read_only(path: &Path) -> io::Result<bool> {
+	let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+	// the root of a relative path may be longer than the path itself
+	let mut root = vec![0u16; path.len() + MAX_PATH];
+	let mut flags: DWORD = 0;
+
+	unsafe {
+		if GetVolumePathNameW(path.as_ptr(), root.as_mut_ptr(), root.len() as DWORD) == FALSE {
+			return Err(io::Error::last_os_error());
+		}
+		if GetVolumeInformationW(root.as_ptr(), ptr::null_mut(), 0, ptr::null_mut(), ptr::null_mut(), &mut flags, ptr::null_mut(), 0) == FALSE {
+			return Err(io::Error::last_os_error());
+		}
+	}
+
+	Ok(flags & FILE_READ_ONLY_VOLUME != 0)
+}
+
+/// Returns true if `metadata`, read without following links, is a symlink to a directory or a
+/// junction. Such links are reparse points with the directory attribute, and are removed like
+/// directories.
+pub fn is_dir_link(metadata: &fs::Metadata) -> bool {
+	metadata.file_type().is_symlink() && metadata.file_attributes() & FILE_ATTRIBUTE_DIRECTORY != 0
+}
+
+/// Rejects paths naming a device or a named pipe rather than a file, like `\\.\PhysicalDrive0`,
+/// `\\.\pipe\name` or `NUL`, with an `Unsupported` error. Such paths cannot be renamed, and
+/// copying them would read or write the device itself. A path on a drive reached through the
+/// device namespace, like `\\.\C:\dir\file`, is a file.
+///
+/// The legacy device names, like `NUL` or `COM1`, are devices in any directory unless the path has
+/// the `\\?\` prefix.
+pub fn check_device(path: &Path) -> io::Result<()> {
+	let (device, verbatim) = match path.components().next() {
+		Some(Component::Prefix(prefix)) => match prefix.kind() {
+			// `\\.\C:\dir\file` is a file on drive `C:`, but `\\.\C:` is the volume itself
+			Prefix::DeviceNS(name) => (!is_drive(name) || path.file_name().is_none(), false),
+			Prefix::Verbatim(name) => (name.eq_ignore_ascii_case("pipe") || name.eq_ignore_ascii_case("GLOBALROOT"), true),
+			prefix => (false, prefix.is_verbatim()),
+		},
+		_ => (false, false),
+	};
+
+	if device || (!verbatim && path.file_name().is_some_and(is_device_name)) {
+		let message = format!("Cannot swap {:?}, which is a device rather than a file", path);
+		return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+	}
+	Ok(())
+}
+
+/// Returns true if `name` is a drive letter followed by a colon, like `C:`.
+fn is_drive(name: &OsStr) -> bool {
+	let bytes = name.as_encoded_bytes();
+	bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Returns true if `name` is a legacy device name, ignoring case, an extension and trailing
+/// spaces.
+fn is_device_name(name: &OsStr) -> bool {
+	const DEVICES: [&str; 6] = ["CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$"];
+
+	let name = name.to_string_lossy();
+	let stem = name.split('.').next().unwrap_or_default().trim_end_matches(' ').to_ascii_uppercase();
+	if DEVICES.contains(&stem.as_str()) {
+		return true;
+	}
+
+	// COM1 to COM9 and LPT1 to LPT9
+	let bytes = stem.as_bytes();
+	bytes.len() == 4 && (stem.starts_with("COM") || stem.starts_with("LPT")) && (b'1'..=b'9').contains(&bytes[3])
+}
+
+/// Flushes the content and metadata of file or directory at `path` to disk.
+///
+/// `FILE_FLAG_BACKUP_SEMANTICS` is required to open a handle to a directory, and
+/// `FILE_FLAG_OPEN_REPARSE_POINT` makes sure a symlink or a junction is flushed rather than its
+/// target.
+pub fn sync(path: &Path) -> io::Result<()> {
+	fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+		.open(path)?
+		.sync_all()
+}
+
+/// Locks `file` exclusively with `LockFileEx`, waiting until no other process holds a lock on
+/// it. The lock is released when the file is closed.
+pub fn lock_exclusive(file: &fs::File) -> io::Result<()> {
+	use self::winapi::um::fileapi::LockFileEx;
+	use self::winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, OVERLAPPED};
+
+	unsafe {
+		let mut overlapped: OVERLAPPED = mem::zeroed();
+		if LockFileEx(file.as_raw_handle() as HANDLE, LOCKFILE_EXCLUSIVE_LOCK, 0, !0, !0, &mut overlapped) == FALSE {
+			return Err(io::Error::last_os_error());
+		}
+	}
+	Ok(())
+}
+
+/// Ownership is not preserved on Windows.
+pub fn set_owner(_path: &Path, _metadata: &fs::Metadata) -> io::Result<()> {
+	Ok(())
+}
+
+pub fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+	Err(io::Error::other("Reflinks are not supported by the current platform"))
+}
+
+/// The `FILE_ALLOCATED_RANGE_BUFFER` used by `FSCTL_QUERY_ALLOCATED_RANGES`, which `winapi` does
+/// not define.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct AllocatedRange {
+	offset: i64,
+	length: i64,
+}
+
+/// Returns the ranges of `file` holding data, queried with `FSCTL_QUERY_ALLOCATED_RANGES`, or
+/// `None` if the file is not sparse.
+pub fn data_ranges(file: &fs::File) -> io::Result<Option<Vec<Range<u64>>>> {
+	let metadata = file.metadata()?;
+	if metadata.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE == 0 {
+		return Ok(None);
+	}
+
+	let mut ranges = Vec::new();
+	let mut offset = 0;
+	while offset < metadata.len() {
+		let mut query = AllocatedRange { offset: offset as i64, length: (metadata.len() - offset) as i64 };
+		let mut found = [AllocatedRange::default(); 64];
+		let mut returned: DWORD = 0;
+		let more = unsafe {
+			let result = DeviceIoControl(
+				file.as_raw_handle() as HANDLE, FSCTL_QUERY_ALLOCATED_RANGES,
+				&mut query as *mut _ as LPVOID, mem::size_of_val(&query) as DWORD,
+				found.as_mut_ptr() as LPVOID, mem::size_of_val(&found) as DWORD,
+				&mut returned, ptr::null_mut()
+			);
+			// the ranges are returned in batches which fit in `found`
+			if result == FALSE && GetLastError() != ERROR_MORE_DATA {
+				return Err(io::Error::last_os_error());
+			}
+			result == FALSE
+		};
+
+		let count = returned as usize / mem::size_of::<AllocatedRange>();
+		for range in &found[..count] {
+			ranges.push(range.offset as u64..(range.offset + range.length) as u64);
+		}
+		match ranges.last() {
+			Some(range) if more && count > 0 => offset = range.end,
+			_ => break,
+		}
+	}
+	Ok(Some(ranges))
+}
+
+/// Marks `file` as sparse with `FSCTL_SET_SPARSE`, so that the ranges which are not written
+/// are not allocated.
+pub fn set_sparse(file: &fs::File) -> io::Result<()> {
+	let mut returned: DWORD = 0;
+	unsafe {
+		let result = DeviceIoControl(
+			file.as_raw_handle() as HANDLE, FSCTL_SET_SPARSE,
+			ptr::null_mut(), 0, ptr::null_mut(), 0, &mut returned, ptr::null_mut()
+		);
+		if result == FALSE {
+			return Err(io::Error::last_os_error());
+		}
+	}
+	Ok(())
+}
+
+/// Applies the discretionary ACL of `from` to `to`. The owner and group are not copied, since
+/// changing them requires privileges.
+#[cfg(feature = "acl")]
+pub fn copy_security(from: &Path, to: &Path) -> io::Result<()> {
+	use self::winapi::shared::winerror::ERROR_SUCCESS;
+	use self::winapi::um::accctrl::SE_FILE_OBJECT;
+	use self::winapi::um::aclapi::{GetNamedSecurityInfoW, SetNamedSecurityInfoW};
+	use self::winapi::um::winbase::LocalFree;
+	use self::winapi::um::winnt::{DACL_SECURITY_INFORMATION, PACL, PSECURITY_DESCRIPTOR};
+
+	let from: Vec<u16> = from.as_os_str().encode_wide().chain(Some(0)).collect();
+	let mut to: Vec<u16> = to.as_os_str().encode_wide().chain(Some(0)).collect();
+
+	unsafe {
+		let mut dacl: PACL = ptr::null_mut();
+		let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+		let code = GetNamedSecurityInfoW(
+			from.as_ptr(), SE_FILE_OBJECT, DACL_SECURITY_INFORMATION,
+			ptr::null_mut(), ptr::null_mut(), &mut dacl, ptr::null_mut(), &mut descriptor
+		);
+		if code != ERROR_SUCCESS {
+			return Err(io::Error::from_raw_os_error(code as i32));
+		}
+
+		// `dacl` points into `descriptor`, which must be freed after it's applied
+		let code = SetNamedSecurityInfoW(
+			to.as_mut_ptr(), SE_FILE_OBJECT, DACL_SECURITY_INFORMATION,
+			ptr::null_mut(), ptr::null_mut(), dacl, ptr::null_mut()
+		);
+		LocalFree(descriptor);
+		if code != ERROR_SUCCESS {
+			return Err(io::Error::from_raw_os_error(code as i32));
+		}
+	}
+
+	Ok(())
 }
@@ -0,0 +1,122 @@
+extern crate libc;
+
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::{ffi, io, ptr};
+
+#[cfg(all(feature = "xattr", target_os = "linux"))]
+unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_char, size: libc::size_t) -> libc::ssize_t {
+	libc::llistxattr(path, buf, size)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn get(path: *const libc::c_char, name: *const libc::c_char, buf: *mut libc::c_void, size: libc::size_t) -> libc::ssize_t {
+	libc::lgetxattr(path, name, buf, size)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set(path: *const libc::c_char, name: *const libc::c_char, value: *const libc::c_void, size: libc::size_t) -> libc::c_int {
+	libc::lsetxattr(path, name, value, size, 0)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_char, size: libc::size_t) -> libc::ssize_t {
+	libc::listxattr(path, buf, size, libc::XATTR_NOFOLLOW)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn get(path: *const libc::c_char, name: *const libc::c_char, buf: *mut libc::c_void, size: libc::size_t) -> libc::ssize_t {
+	libc::getxattr(path, name, buf, size, 0, libc::XATTR_NOFOLLOW)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn set(path: *const libc::c_char, name: *const libc::c_char, value: *const libc::c_void, size: libc::size_t) -> libc::c_int {
+	libc::setxattr(path, name, value, size, 0, libc::XATTR_NOFOLLOW)
+}
+
+/// Calls `f` with a buffer large enough for its result, retrying if the result grows in between.
+fn read_to_vec<F>(f: F) -> io::Result<Vec<u8>> where F: Fn(*mut u8, usize) -> libc::ssize_t {
+	loop {
+		let size = f(ptr::null_mut(), 0);
+		if size < 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		let mut buf = vec![0u8; size as usize];
+		let size = f(buf.as_mut_ptr(), buf.len());
+		if size >= 0 {
+			buf.truncate(size as usize);
+			return Ok(buf);
+		}
+
+		let err = io::Error::last_os_error();
+		if err.raw_os_error() != Some(libc::ERANGE) {
+			return Err(err);
+		}
+	}
+}
+
+fn is_unsupported(err: &io::Error) -> bool {
+	matches!(err.raw_os_error(), Some(libc::ENOTSUP) | Some(libc::EPERM))
+}
+
+/// Copies the extended attributes of `from` to `to`, without following symlinks.
+///
+/// Attributes which the filesystem of `to` does not support, or which the process is not
+/// allowed to set, are skipped.
+#[cfg(feature = "xattr")]
+pub fn copy_xattrs(from: &Path, to: &Path) -> io::Result<()> {
+	let from = ffi::CString::new(from.as_os_str().as_bytes())?;
+	let to = ffi::CString::new(to.as_os_str().as_bytes())?;
+
+	let names = match read_to_vec(|buf, size| unsafe { list(from.as_ptr(), buf as *mut libc::c_char, size) }) {
+		Ok(names) => names,
+		Err(ref err) if is_unsupported(err) => return Ok(()),
+		Err(err) => return Err(err),
+	};
+
+	for name in names.split(|&c| c == 0).filter(|name| !name.is_empty()) {
+		let name = ffi::CString::new(name)?;
+		let value = read_to_vec(|buf, size| unsafe { get(from.as_ptr(), name.as_ptr(), buf as *mut libc::c_void, size) })?;
+		if unsafe { set(to.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len()) } != 0 {
+			let err = io::Error::last_os_error();
+			if !is_unsupported(&err) {
+				return Err(err);
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Name of the extended attribute holding the SELinux security context.
+#[cfg(all(feature = "selinux", target_os = "linux"))]
+const SELINUX_CONTEXT: &[u8] = b"security.selinux";
+
+/// Copies the SELinux security context of `from` to `to`, without following symlinks.
+///
+/// Nothing is copied if `from` has no context. If the filesystem of `to` does not support
+/// contexts, or if the process is not allowed to relabel it, `to` keeps the context it was
+/// created with and a warning is logged.
+#[cfg(all(feature = "selinux", target_os = "linux"))]
+pub fn copy_selinux_context(from: &Path, to: &Path) -> io::Result<()> {
+	let c_from = ffi::CString::new(from.as_os_str().as_bytes())?;
+	let c_to = ffi::CString::new(to.as_os_str().as_bytes())?;
+	let name = ffi::CString::new(SELINUX_CONTEXT)?;
+
+	let context = match read_to_vec(|buf, size| unsafe { get(c_from.as_ptr(), name.as_ptr(), buf as *mut libc::c_void, size) }) {
+		Ok(context) => context,
+		Err(ref err) if err.raw_os_error() == Some(libc::ENODATA) || is_unsupported(err) => return Ok(()),
+		Err(err) => return Err(err),
+	};
+
+	if unsafe { set(c_to.as_ptr(), name.as_ptr(), context.as_ptr() as *const libc::c_void, context.len()) } != 0 {
+		let err = io::Error::last_os_error();
+		if !is_unsupported(&err) && err.raw_os_error() != Some(libc::EACCES) {
+			return Err(err);
+		}
+		swap_log!(warn, "Preserving the SELinux context failed"; from = ?from, to = ?to, error = %err);
+	}
+
+	Ok(())
+}
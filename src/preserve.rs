@@ -0,0 +1,109 @@
+//! Restoring the attributes of swapped paths.
+
+use std::{fs, io};
+use std::path::Path;
+use filetime::{self, FileTime};
+use options::SwapOptions;
+use platform;
+
+/// Attributes of both swapped paths, captured according to `SwapOptions`.
+pub(crate) struct Preserved {
+	permissions: Option<(Permissions, Permissions)>,
+	times: Option<(Times, Times)>,
+}
+
+impl Preserved {
+	/// Captures attributes of `a` and `b` before the swap.
+	pub fn capture(a: &Path, b: &Path, options: &SwapOptions) -> io::Result<Self> {
+		let permissions = if options.preserve_permissions {
+			Some((Permissions::capture(a)?, Permissions::capture(b)?))
+		} else {
+			None
+		};
+
+		let times = if options.preserve_times {
+			Some((Times::capture(a)?, Times::capture(b)?))
+		} else {
+			None
+		};
+
+		Ok(Preserved { permissions, times })
+	}
+
+	/// Restores attributes of `a` and `b` after the swap.
+	pub fn restore(&self, a: &Path, b: &Path) -> io::Result<()> {
+		// permissions stay with the path
+		if let Some((ref permissions_a, ref permissions_b)) = self.permissions {
+			permissions_a.apply(a)?;
+			permissions_b.apply(b)?;
+		}
+
+		// times follow the content, so `b` gets the times of `a`
+		if let Some((ref times_a, ref times_b)) = self.times {
+			times_b.apply(a)?;
+			times_a.apply(b)?;
+		}
+
+		Ok(())
+	}
+
+	/// Restores attributes of `b` after `a` was renamed over it. The times moved with the content.
+	pub fn restore_replaced(&self, b: &Path) -> io::Result<()> {
+		if let Some((_, ref permissions_b)) = self.permissions {
+			permissions_b.apply(b)?;
+		}
+		Ok(())
+	}
+}
+
+/// Permissions and ownership of a path captured before the swap.
+struct Permissions {
+	metadata: fs::Metadata,
+}
+
+impl Permissions {
+	/// Captures permissions of `path`, without following symlinks.
+	fn capture(path: &Path) -> io::Result<Self> {
+		Ok(Permissions {
+			metadata: fs::symlink_metadata(path)?,
+		})
+	}
+
+	/// Applies captured permissions to `path`.
+	///
+	/// Symlinks are skipped. Changing the ownership requires privileges, so it's done only if
+	/// permitted.
+	fn apply(&self, path: &Path) -> io::Result<()> {
+		if self.metadata.file_type().is_symlink() || fs::symlink_metadata(path)?.file_type().is_symlink() {
+			return Ok(());
+		}
+
+		fs::set_permissions(path, self.metadata.permissions())?;
+		match platform::set_owner(path, &self.metadata) {
+			Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => Ok(()),
+			result => result,
+		}
+	}
+}
+
+/// Access and modification times of a path captured before the swap.
+struct Times {
+	accessed: FileTime,
+	modified: FileTime,
+}
+
+impl Times {
+	/// Captures times of `path`, without following symlinks.
+	fn capture(path: &Path) -> io::Result<Self> {
+		let metadata = fs::symlink_metadata(path)?;
+		Ok(Times {
+			accessed: FileTime::from_last_access_time(&metadata),
+			modified: FileTime::from_last_modification_time(&metadata),
+		})
+	}
+
+	/// Applies captured times to `path`, without following symlinks.
+	fn apply(&self, path: &Path) -> io::Result<()> {
+		filetime::set_symlink_file_times(path, self.accessed, self.modified)
+	}
+}
@@ -0,0 +1,80 @@
+//! Read-only files on Windows, which cannot be replaced, cleared during the whole swap including
+//! its nonatomic and copy fallbacks.
+
+use std::io;
+use std::path::Path;
+use error::SwapError;
+#[cfg(windows)]
+use platform;
+
+/// Runs `swap` of `a` and `b` with their read-only attributes cleared, and restores them whether
+/// it succeeds or fails.
+pub(crate) fn cleared<T, F>(a: &Path, b: &Path, swap: F) -> Result<T, SwapError> where F: FnOnce() -> Result<T, SwapError> {
+	let readonly = ReadOnly::clear(a, b).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	match swap() {
+		Ok(value) => {
+			readonly.restore(a, b).map_err(SwapError::Swapped)?;
+			Ok(value)
+		},
+		Err(err) => {
+			readonly.restore_logged(a, b);
+			Err(err)
+		},
+	}
+}
+
+/// Read-only attributes of the swapped paths, which stay with the paths rather than the content.
+struct ReadOnly {
+	a: bool,
+	b: bool,
+}
+
+impl ReadOnly {
+	/// Clears the read-only attribute of `a` and `b`. If `b` fails, `a` is set read-only again.
+	fn clear(a: &Path, b: &Path) -> io::Result<Self> {
+		let readonly_a = clear_readonly(a)?;
+		let readonly_b = match clear_readonly(b) {
+			Ok(readonly_b) => readonly_b,
+			Err(err) => {
+				ReadOnly { a: readonly_a, b: false }.restore_logged(a, b);
+				return Err(err);
+			},
+		};
+		Ok(ReadOnly { a: readonly_a, b: readonly_b })
+	}
+
+	/// Sets the attribute again on the paths which had it, whatever they now hold.
+	fn restore(&self, a: &Path, b: &Path) -> io::Result<()> {
+		let restored_a = if self.a { set_readonly(a) } else { Ok(()) };
+		let restored_b = if self.b { set_readonly(b) } else { Ok(()) };
+		restored_a.and(restored_b)
+	}
+
+	/// Restores the attributes like `restore` after the swap failed, logging a failure rather
+	/// than hiding the error of the swap.
+	fn restore_logged(&self, a: &Path, b: &Path) {
+		if let Err(err) = self.restore(a, b) {
+			swap_log!(warn, "Restoring read-only attributes failed"; a = ?a, b = ?b, error = %err);
+		}
+	}
+}
+
+#[cfg(windows)]
+fn clear_readonly(path: &Path) -> io::Result<bool> {
+	platform::clear_readonly(path)
+}
+
+#[cfg(not(windows))]
+fn clear_readonly(_path: &Path) -> io::Result<bool> {
+	Ok(false)
+}
+
+#[cfg(windows)]
+fn set_readonly(path: &Path) -> io::Result<()> {
+	platform::set_readonly(path)
+}
+
+#[cfg(not(windows))]
+fn set_readonly(_path: &Path) -> io::Result<()> {
+	Ok(())
+}
@@ -0,0 +1,139 @@
+//! Moving a path over another one, whose content is discarded.
+
+use std::{fs, io};
+use std::path::Path;
+use copy::{remove_leftover, CopyControl};
+use error::{SwapError, Side};
+use filesystem::StdFs;
+use options::SwapOptions;
+use preserve::Preserved;
+use {lock, move_over, overwrite, platform, swap_checked, sync_content, sync_parents, sync_tree, timeout, validate, SwapKind, SwapReport};
+
+/// Moves `src` to `dst`, replacing `dst` if it exists, like `fs::rename` with the fallbacks of
+/// `swap_auto`.
+pub fn replace<A, B>(src: A, dst: B) -> Result<SwapKind, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	replace_with(src, dst, &SwapOptions::new())
+}
+
+/// Moves `src` to `dst` using given `options`, replacing `dst` if it exists. `src` is missing
+/// afterwards.
+///
+/// Files, symlinks and empty directories are renamed over `dst`, which replaces it atomically.
+/// A non-empty directory cannot be renamed over, so both paths are swapped with the strategy
+/// selected by `options`, and the original content of `dst`, left at `src`, is then removed. If
+/// this fails, `SwapError::Swapped` is returned, `dst` being replaced already. If the paths are on different
+/// filesystems, `src` is copied next to `dst` and renamed over it, so `dst` is never missing,
+/// and then removed. If `dst` is missing, `src` is moved like with `SwapOptions::allow_missing`.
+///
+/// With `preserve_permissions`, `dst` keeps its permissions. Fails with
+/// `SwapError::NotFound { which: Side::A }` if `src` does not exist. Replacing a path with itself,
+/// or with another hardlink to the same file, does nothing. Paths are resolved like with `swap`.
+pub fn replace_with<A, B>(src: A, dst: B, options: &SwapOptions) -> Result<SwapKind, SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let src = &*validate::resolve(src.as_ref())?;
+	let dst = &*validate::resolve(dst.as_ref())?;
+	swap_span!("replace_with", src, dst);
+
+	let result = replace_unlogged(src, dst, options);
+	match result {
+		Ok(kind) => swap_log!(debug, "Replaced path"; src = ?src, dst = ?dst, strategy = ?kind),
+		Err(ref err) => swap_log!(warn, "Replace failed"; src = ?src, dst = ?dst, error = %err),
+	}
+	result
+}
+
+fn replace_unlogged(src: &Path, dst: &Path, options: &SwapOptions) -> Result<SwapKind, SwapError> {
+	if let Some(limit) = options.timeout {
+		let (src, dst) = (src.to_path_buf(), dst.to_path_buf());
+		let options = SwapOptions { timeout: None, ..options.clone() };
+		return timeout::run(limit, move || replace_unlogged(&src, &dst, &options));
+	}
+
+	let _locks = if options.serialize { Some(lock::DirLocks::acquire_checked(src, dst)?) } else { None };
+
+	match validate::missing_side(src, dst)? {
+		Some(Side::A) => return Err(SwapError::NotFound { which: Side::A }),
+		Some(Side::B) => return move_over(src, dst, src, dst, options).map(|report| report.kind),
+		None => (),
+	}
+
+	let entries = validate::check(src, dst)?;
+	if entries.same_file {
+		return Ok(SwapKind::Atomic);
+	}
+
+	let same_device = platform::same_device_metadata(src, &entries.a, dst, &entries.b).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	if !same_device {
+		if options.require_atomic {
+			return Err(SwapError::CrossDevice);
+		}
+		let kind = overwrite_copied(src, dst, options)?;
+		remove_replaced(src, options)?;
+		return Ok(kind);
+	}
+
+	if !entries.b.is_dir() || is_empty_dir(dst).map_err(|err| SwapError::from_unmodified(err, src, dst))? {
+		return renamed(src, dst, options);
+	}
+
+	let kind = swap_checked(&StdFs, src, dst, &entries, options)?.kind;
+	remove_replaced(src, options)?;
+	Ok(kind)
+}
+
+/// Returns true if `dir` can be replaced by renaming a directory over it. Windows never renames
+/// a directory over another one.
+fn is_empty_dir(dir: &Path) -> io::Result<bool> {
+	if cfg!(windows) {
+		return Ok(false);
+	}
+	Ok(fs::read_dir(dir)?.next().is_none())
+}
+
+/// Renames `src` over `dst`, located on the same filesystem.
+fn renamed(src: &Path, dst: &Path, options: &SwapOptions) -> Result<SwapKind, SwapError> {
+	if options.sync_data {
+		sync_tree(src).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	} else if options.durable {
+		sync_content(src).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	}
+
+	let preserved = Preserved::capture(src, dst, options).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	fs::rename(src, dst).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	preserved.restore_replaced(dst).map_err(SwapError::Swapped)?;
+
+	if options.durable {
+		sync_parents(src, dst).map_err(SwapError::Swapped)?;
+	}
+
+	Ok(SwapKind::Atomic)
+}
+
+/// Removes `src`, holding the original content of the replaced path.
+fn remove_replaced(src: &Path, options: &SwapOptions) -> Result<(), SwapError> {
+	remove_leftover(&StdFs, src).map_err(SwapError::Swapped)?;
+	if options.durable {
+		sync_parents(src, src).map_err(SwapError::Swapped)?;
+	}
+	Ok(())
+}
+
+/// Overwrites `dst` with a copy of `src`, located on another filesystem, leaving `src` in place.
+fn overwrite_copied(src: &Path, dst: &Path, options: &SwapOptions) -> Result<SwapKind, SwapError> {
+	if options.sync_data {
+		sync_tree(src).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	} else if options.durable {
+		sync_content(src).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	}
+
+	// the permissions of `dst` are applied to the copy, and the times of `src`
+	let preserved = Preserved::capture(src, dst, options).map_err(|err| SwapError::from_unmodified(err, src, dst))?;
+	let stats = overwrite::overwrite(&StdFs, src, dst, None, &mut CopyControl::new(options))?;
+	preserved.restore(src, dst).map_err(SwapError::Swapped)?;
+
+	if options.durable {
+		sync_content(dst).map_err(SwapError::Swapped)?;
+		sync_parents(dst, dst).map_err(SwapError::Swapped)?;
+	}
+
+	Ok(SwapReport::copied(&stats).kind)
+}
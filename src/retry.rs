@@ -0,0 +1,113 @@
+use std::{fs, io};
+use std::cell::Cell;
+use std::path::Path;
+use std::time::Duration;
+use clock::{Clock, SystemClock};
+use filesystem::FileSystem;
+use options::SwapOptions;
+use platform;
+
+/// Retry policy for operations failing with transient errors.
+#[derive(Clone, Copy)]
+pub(crate) struct Retry {
+	count: u32,
+	backoff: Duration,
+	is_transient: fn(&io::Error) -> bool,
+}
+
+impl Retry {
+	pub(crate) fn new(count: u32, backoff: Duration, is_transient: fn(&io::Error) -> bool) -> Self {
+		Retry { count, backoff, is_transient }
+	}
+
+	pub(crate) fn from_options(options: &SwapOptions) -> Self {
+		Retry::new(options.retries, options.retry_backoff, platform::is_transient)
+	}
+
+	/// Runs `f` until it succeeds, fails with an error which is not transient or runs out of
+	/// retries.
+	pub(crate) fn run<T, F>(&self, f: F) -> io::Result<T> where F: FnMut() -> io::Result<T> {
+		self.run_counted(f).0
+	}
+
+	/// Runs `f` like `run`, and also returns how many times it was retried.
+	pub(crate) fn run_counted<T, F>(&self, f: F) -> (io::Result<T>, u32) where F: FnMut() -> io::Result<T> {
+		self.run_counted_with(&SystemClock, f)
+	}
+
+	/// Runs `f` like `run_counted`, waiting the backoff with `clock`.
+	pub(crate) fn run_counted_with<T, F, C>(&self, clock: &C, mut f: F) -> (io::Result<T>, u32) where F: FnMut() -> io::Result<T>, C: Clock {
+		let mut backoff = self.backoff;
+		let mut attempt = 0;
+		loop {
+			match f() {
+				Err(ref err) if attempt < self.count && (self.is_transient)(err) => {
+					swap_log!(warn, "Transient error, retrying"; attempt = %(attempt + 1), backoff = ?backoff, error = %err);
+					clock.sleep(backoff);
+					backoff *= 2;
+					attempt += 1;
+				},
+				result => return (result, attempt),
+			}
+		}
+	}
+}
+
+/// Filesystem retrying the renames, links and removals of another one, waiting the backoff with
+/// `clock`.
+pub(crate) struct RetryingFs<'a, F: FileSystem + 'a, C: Clock + 'a = SystemClock> {
+	fs: &'a F,
+	retry: Retry,
+	clock: &'a C,
+	retried: Cell<u32>,
+}
+
+impl<'a, F: FileSystem> RetryingFs<'a, F> {
+	pub(crate) fn new(fs: &'a F, retry: Retry) -> Self {
+		RetryingFs::with_clock(fs, retry, &SystemClock)
+	}
+}
+
+impl<'a, F: FileSystem, C: Clock> RetryingFs<'a, F, C> {
+	pub(crate) fn with_clock(fs: &'a F, retry: Retry, clock: &'a C) -> Self {
+		RetryingFs { fs, retry, clock, retried: Cell::new(0) }
+	}
+
+	/// Runs `f` with the retry policy, adding its retries to `retried`.
+	pub(crate) fn run<T, G>(&self, f: G) -> io::Result<T> where G: FnMut() -> io::Result<T> {
+		let (result, retried) = self.retry.run_counted_with(self.clock, f);
+		self.retried.set(self.retried.get() + retried);
+		result
+	}
+
+	/// Returns how many times the operations run so far were retried in total.
+	pub(crate) fn retried(&self) -> u32 {
+		self.retried.get()
+	}
+}
+
+impl<'a, F: FileSystem, C: Clock> FileSystem for RetryingFs<'a, F, C> {
+	fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+		self.run(|| self.fs.rename(from, to))
+	}
+
+	fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+		self.run(|| self.fs.rename_noreplace(from, to))
+	}
+
+	fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+		self.fs.metadata(path)
+	}
+
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+		self.run(|| self.fs.hard_link(from, to))
+	}
+
+	fn remove_file(&self, path: &Path) -> io::Result<()> {
+		self.run(|| self.fs.remove_file(path))
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+		self.run(|| self.fs.remove_dir_all(path))
+	}
+}
@@ -0,0 +1,127 @@
+//! Renames which are reverted unless they're committed.
+
+use std::{io, mem};
+use std::path::Path;
+use error::{CorruptedState, SwapError};
+use filesystem::FileSystem;
+
+/// Completed renames, reverted in reverse order when dropped unless they're committed.
+///
+/// Reverting on drop makes sure that an early return never leaves a path moved to a temp path.
+pub(crate) struct Renames<'a, F: FileSystem + 'a> {
+	fs: &'a F,
+	done: Vec<(&'a Path, &'a Path)>,
+	/// Last rename which failed.
+	failed: Option<(&'a Path, &'a Path)>,
+	/// Renames left in place by a rollback which failed, the last one being the failed revert.
+	stranded: Vec<(&'a Path, &'a Path)>,
+}
+
+impl<'a, F: FileSystem> Renames<'a, F> {
+	pub(crate) fn new(fs: &'a F) -> Self {
+		Renames {
+			fs,
+			done: Vec::new(),
+			failed: None,
+			stranded: Vec::new(),
+		}
+	}
+
+	/// Renames `from` to `to`, and records it if it succeeds.
+	pub(crate) fn rename(&mut self, from: &'a Path, to: &'a Path) -> io::Result<()> {
+		let result = self.fs.rename(from, to);
+		self.record(from, to, result)
+	}
+
+	/// Renames `from` to `to` unless `to` exists, and records it if it succeeds.
+	pub(crate) fn rename_noreplace(&mut self, from: &'a Path, to: &'a Path) -> io::Result<()> {
+		let result = self.fs.rename_noreplace(from, to);
+		self.record(from, to, result)
+	}
+
+	fn record(&mut self, from: &'a Path, to: &'a Path, result: io::Result<()>) -> io::Result<()> {
+		if let Err(err) = result {
+			self.failed = Some((from, to));
+			return Err(err);
+		}
+		swap_log!(trace, "Renamed path"; from = ?from, to = ?to);
+		self.done.push((from, to));
+		Ok(())
+	}
+
+	/// Reverts the completed renames in reverse order. Stops at the first revert which fails, the
+	/// renames which were not reverted are then kept to describe the state with `corrupted`.
+	pub(crate) fn rollback(&mut self) -> io::Result<()> {
+		if !self.done.is_empty() {
+			swap_metric!("fs_swap.recovery");
+		}
+		while let Some((from, to)) = self.done.pop() {
+			if let Err(err) = self.fs.rename(to, from) {
+				swap_log!(error, "Reverting rename failed"; from = ?from, to = ?to, error = %err);
+				self.done.push((from, to));
+				self.stranded = mem::take(&mut self.done);
+				return Err(err);
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns `SwapError::Corrupted` for renames which failed with `error` and whose rollback
+	/// failed with `rollback_error`, describing where the renamed paths were left.
+	pub(crate) fn corrupted(&self, error: io::Error, rollback_error: io::Error, temp_path: Option<&Path>) -> SwapError {
+		let mut state = CorruptedState {
+			temp_path: temp_path.map(Path::to_path_buf),
+			failed_rename: self.failed.map(|(from, to)| (from.to_path_buf(), to.to_path_buf())),
+			failed_revert: self.stranded.last().map(|&(from, to)| (to.to_path_buf(), from.to_path_buf())),
+			..CorruptedState::default()
+		};
+		for &(from, to) in &self.stranded {
+			// content which was already moved is moved further
+			match state.moved.iter_mut().find(|moved| moved.1 == from) {
+				Some(moved) => moved.1 = to.to_path_buf(),
+				None => state.moved.push((from.to_path_buf(), to.to_path_buf())),
+			}
+		}
+		state.moved.retain(|moved| moved.0 != moved.1);
+		SwapError::Corrupted { error, rollback_error, state: Box::new(state) }
+	}
+
+	/// Keeps the completed renames.
+	pub(crate) fn commit(mut self) {
+		self.done.clear();
+	}
+}
+
+impl<'a, F: FileSystem> Drop for Renames<'a, F> {
+	fn drop(&mut self) {
+		if !self.done.is_empty() {
+			swap_log!(warn, "Renames were not completed, reverting them"; count = %self.done.len());
+			let _ = self.rollback();
+		}
+	}
+}
+
+/// Renames each `(from, to)` pair in order.
+///
+/// If any rename fails, all previous renames are reverted in reverse order. If the first rename
+/// fails, `SwapError::Io` is returned.
+pub(crate) fn rename_all<F>(fs: &F, renames: &[(&Path, &Path)]) -> Result<(), SwapError> where F: FileSystem {
+	let mut done = Renames::new(fs);
+	for (i, &(from, to)) in renames.iter().enumerate() {
+		if let Err(err) = done.rename(from, to) {
+			if i == 0 {
+				return Err(SwapError::Io(err));
+			}
+
+			// let's try to recover the previous state
+			swap_log!(warn, "Renaming failed, reverting previous renames"; from = ?from, to = ?to, error = %err);
+			return match done.rollback() {
+				Ok(_) => Err(SwapError::RolledBack(err)),
+				Err(rollback_error) => Err(done.corrupted(err, rollback_error, None)),
+			};
+		}
+	}
+
+	done.commit();
+	Ok(())
+}
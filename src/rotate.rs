@@ -0,0 +1,106 @@
+use std::{fs, io};
+use std::path::Path;
+use error::{CorruptedState, SwapError};
+use filesystem::{FileSystem, StdFs};
+use rollback::rename_all;
+use {platform, swap, tmp_path_in_dir, validate};
+
+/// Rotates the content of paths `a`, `b` and `c`: `b` gets the content of `a`, `c` the content
+/// of `b` and `a` the content of `c`.
+///
+/// The rotation is nonatomic and uses a single temp file in the parent directory of `a`. If any
+/// rename fails, the original arrangement is restored. Paths must be distinct, like in `rotate`.
+pub fn swap3<A, B, C>(a: A, b: B, c: C) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path>, C: AsRef<Path> {
+	rotate_nonatomic(&[a.as_ref(), b.as_ref(), c.as_ref()])
+}
+
+/// Rotates the content of `paths`, so that `paths[i]` gets the content of `paths[i - 1]` and
+/// `paths[0]` the content of the last path.
+///
+/// Rotating less than two paths does nothing and rotating two paths is the same as `swap`.
+///
+/// Otherwise, the rotation is nonatomic. The last path is moved to a temp file in the parent
+/// directory of `paths[0]`, then each path is moved to the next one, starting from the end, and
+/// finally the temp file is moved to `paths[0]`. If any of these renames fails, the completed
+/// ones are reverted in reverse order and `SwapError::RolledBack` is returned. If reverting also
+/// fails, `SwapError::Corrupted` is returned and the content of the last path may be left in the
+/// temp file.
+///
+/// Before renaming anything, fails with an error of kind `InvalidInput` if two paths refer to the
+/// same path, even through a different spelling or a symlinked parent directory.
+pub fn rotate<P>(paths: &[P]) -> Result<(), SwapError> where P: AsRef<Path> {
+	match paths.len() {
+		0 | 1 => Ok(()),
+		2 => swap(&paths[0], &paths[1]),
+		_ => rotate_nonatomic(&paths.iter().map(AsRef::as_ref).collect::<Vec<_>>()),
+	}
+}
+
+fn rotate_nonatomic(paths: &[&Path]) -> Result<(), SwapError> {
+	validate::check_distinct(paths.iter().map(|path| vec![*path])).map_err(|(_, err)| err)?;
+
+	let parent_dir = paths[0].parent()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find a parent directory"))?;
+	let tmp = tmp_path_in_dir(parent_dir)?;
+	let last = paths[paths.len() - 1];
+
+	let mut renames = Vec::with_capacity(paths.len() + 1);
+	renames.push((last, tmp.as_path()));
+	renames.extend(paths.windows(2).rev().map(|pair| (pair[0], pair[1])));
+	renames.push((tmp.as_path(), paths[0]));
+
+	rename_all(&StdFs, &renames)
+}
+
+/// Promotes `next` to `current`, keeping the previous content of `current` in `old`, like in a
+/// blue/green deploy: `current` gets the content of `next`, `old` the content of `current`, and
+/// `next` the previous content of `old`, or is left missing if `old` does not exist.
+///
+/// `current` is swapped with `next` first, with `swap`, so it's replaced atomically where `swap`
+/// is atomic and is never missing. The previous content of `current` is then moved from `next`
+/// to `old`, by swapping them, or with a rename if `old` does not exist, which fails if `old` is
+/// created meanwhile. If this fails, `next` and `current` are swapped back and
+/// `SwapError::RolledBack` is returned, or `SwapError::Corrupted` if swapping them back also
+/// fails, in which case the previous content of `current` is left in `next`.
+///
+/// `next` and `current` must exist. Paths must be distinct, like in `rotate`, and on the same
+/// filesystem, since `old` is renamed rather than copied: `SwapError::CrossDevice` is returned
+/// otherwise.
+pub fn promote<N, C, O>(next: N, current: C, old: O) -> Result<(), SwapError> where N: AsRef<Path>, C: AsRef<Path>, O: AsRef<Path> {
+	let next = next.as_ref();
+	let current = current.as_ref();
+	let old = old.as_ref();
+	swap_span!("promote", next, current);
+
+	validate::check_distinct([next, current, old].iter().map(|path| vec![*path])).map_err(|(_, err)| err)?;
+	swap(next, current)?;
+
+	let retired = match fs::symlink_metadata(old) {
+		Err(ref err) if err.kind() == io::ErrorKind::NotFound => StdFs.rename_noreplace(next, old).map_err(|err| match err {
+			ref err if platform::is_cross_device(err) => SwapError::CrossDevice,
+			err => SwapError::Io(err),
+		}),
+		_ => swap(next, old),
+	};
+	let err = match retired {
+		Ok(_) => return Ok(()),
+		Err(err @ SwapError::Corrupted { .. }) => return Err(err),
+		Err(err) => io::Error::from(err),
+	};
+
+	swap_log!(warn, "Moving current to old failed, restoring current"; next = ?next, current = ?current, old = ?old, error = %err);
+	match swap(next, current) {
+		Ok(_) => Err(SwapError::RolledBack(err)),
+		Err(rollback_error) => {
+			swap_log!(error, "Restoring current failed, content of current is in next"; next = ?next, current = ?current, error = %rollback_error);
+			// `current` already has the content of `next`, only its previous content is misplaced
+			let state = CorruptedState {
+				moved: vec![(current.to_path_buf(), next.to_path_buf())],
+				failed_rename: Some((next.to_path_buf(), old.to_path_buf())),
+				failed_revert: Some((next.to_path_buf(), current.to_path_buf())),
+				..CorruptedState::default()
+			};
+			Err(SwapError::Corrupted { error: err, rollback_error: rollback_error.into(), state: Box::new(state) })
+		},
+	}
+}
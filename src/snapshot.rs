@@ -0,0 +1,49 @@
+//! Recording the state of the swapped paths.
+
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+use validate::Entries;
+
+/// Size and modification time of a swapped path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathState {
+	/// Size of the path, as returned by `fs::Metadata::len`.
+	pub len: u64,
+	/// Modification time of the path, if the platform provides it.
+	pub modified: Option<SystemTime>,
+}
+
+impl PathState {
+	fn from_metadata(metadata: &fs::Metadata) -> Self {
+		PathState {
+			len: metadata.len(),
+			modified: metadata.modified().ok(),
+		}
+	}
+}
+
+/// States of both swapped paths at a given time. Symlinks are not followed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Snapshot {
+	/// State of path `a`, or `None` if it did not exist or could not be read.
+	pub a: Option<PathState>,
+	/// State of path `b`, or `None` if it did not exist or could not be read.
+	pub b: Option<PathState>,
+}
+
+impl Snapshot {
+	/// Reads the current states of `a` and `b`.
+	pub(crate) fn capture(a: &Path, b: &Path) -> Self {
+		let state = |path: &Path| fs::symlink_metadata(path).ok().map(|metadata| PathState::from_metadata(&metadata));
+		Snapshot { a: state(a), b: state(b) }
+	}
+
+	/// Returns the states of the paths from the metadata read while validating them.
+	pub(crate) fn from_entries(entries: &Entries) -> Self {
+		Snapshot {
+			a: Some(PathState::from_metadata(&entries.a)),
+			b: Some(PathState::from_metadata(&entries.b)),
+		}
+	}
+}
@@ -0,0 +1,83 @@
+//! Helpers for the tests of crates swapping paths, available with the `testing` feature.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use error::SwapError;
+use filesystem::FailingFs;
+use options::SwapOptions;
+use {nonatomic, swap, validate};
+
+/// Content of a path: the data of a file, the target of a symlink, or the content of each entry
+/// of a directory, keyed by their path relative to it.
+type Tree = BTreeMap<PathBuf, Vec<u8>>;
+
+fn read_tree(root: &Path, path: &Path, tree: &mut Tree) {
+	let metadata = fs::symlink_metadata(path).unwrap_or_else(|err| panic!("Reading {:?} failed: {}", path, err));
+	let relative = path.strip_prefix(root)
+		.unwrap_or_else(|err| panic!("{:?} is not inside {:?}: {}", path, root, err))
+		.to_path_buf();
+	if metadata.file_type().is_symlink() {
+		let target = fs::read_link(path).unwrap_or_else(|err| panic!("Reading link {:?} failed: {}", path, err));
+		tree.insert(relative, target.to_string_lossy().into_owned().into_bytes());
+	} else if metadata.is_dir() {
+		let entries = fs::read_dir(path).unwrap_or_else(|err| panic!("Reading directory {:?} failed: {}", path, err));
+		for entry in entries {
+			let entry = entry.unwrap_or_else(|err| panic!("Reading directory {:?} failed: {}", path, err));
+			read_tree(root, &entry.path(), tree);
+		}
+	} else {
+		let data = fs::read(path).unwrap_or_else(|err| panic!("Reading {:?} failed: {}", path, err));
+		tree.insert(relative, data);
+	}
+}
+
+fn tree(path: &Path) -> Tree {
+	let mut tree = Tree::new();
+	read_tree(path, path, &mut tree);
+	tree
+}
+
+/// Panics if the content of file `path` is not `expected`.
+pub fn assert_contents<P, C>(path: P, expected: C) where P: AsRef<Path>, C: AsRef<[u8]> {
+	let path = path.as_ref();
+	let data = fs::read(path).unwrap_or_else(|err| panic!("Reading {:?} failed: {}", path, err));
+	assert!(data == expected.as_ref(), "Unexpected content of {:?}: {:?}, expected {:?}",
+		path, String::from_utf8_lossy(&data), String::from_utf8_lossy(expected.as_ref()));
+}
+
+/// Panics if the contents of files `a` and `b` are not `expected_a` and `expected_b`.
+pub fn assert_swapped<A, B, C, D>(a: A, b: B, expected_a: C, expected_b: D) where A: AsRef<Path>, B: AsRef<Path>, C: AsRef<[u8]>, D: AsRef<[u8]> {
+	assert_contents(a, expected_a);
+	assert_contents(b, expected_b);
+}
+
+/// Swaps `a` and `b` with `swap` and checks that their contents were exchanged, then swaps them
+/// back and checks that they were restored. Panics if a swap fails or a check does not hold.
+///
+/// `a` and `b` may be files, symlinks or directories, whose entries are compared recursively.
+pub fn swap_roundtrip<A, B>(a: A, b: B) where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	let tree_a = tree(a);
+	let tree_b = tree(b);
+
+	swap(a, b).unwrap_or_else(|err| panic!("Swapping {:?} and {:?} failed: {}", a, b, err));
+	assert!(tree(a) == tree_b, "{:?} does not have the previous content of {:?}", a, b);
+	assert!(tree(b) == tree_a, "{:?} does not have the previous content of {:?}", b, a);
+
+	swap(a, b).unwrap_or_else(|err| panic!("Swapping {:?} and {:?} back failed: {}", a, b, err));
+	assert!(tree(a) == tree_a, "{:?} was not restored", a);
+	assert!(tree(b) == tree_b, "{:?} was not restored", b);
+}
+
+/// Swaps `a` and `b` like `swap_nonatomic`, failing the renames whose entry of `failures` is
+/// true, in order, with an injected error. Renames past the end of `failures` succeed.
+///
+/// Lets the recovery from a failed nonatomic swap be tested without making a real rename fail.
+pub fn swap_nonatomic_failing<A, B>(a: A, b: B, failures: &[bool]) -> Result<(), SwapError> where A: AsRef<Path>, B: AsRef<Path> {
+	let a = a.as_ref();
+	let b = b.as_ref();
+	let entries = validate::check(a, b)?;
+	nonatomic(&FailingFs::new(failures), a, b, &entries, &SwapOptions::new()).map(|_| ())
+}
@@ -0,0 +1,38 @@
+//! Bounding the time spent waiting for a swap, see `SwapOptions::timeout`.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use std::{panic, thread};
+use clock::{Clock, SystemClock};
+use error::SwapError;
+
+/// Runs `f` on a new thread and waits at most `timeout` for its result.
+///
+/// Returns `SwapError::TimedOut` if it takes longer, in which case the thread is detached and
+/// keeps running `f` until it returns. A panic of `f` is propagated to the caller.
+pub(crate) fn run<T, F>(timeout: Duration, f: F) -> Result<T, SwapError> where T: Send + 'static, F: FnOnce() -> Result<T, SwapError> + Send + 'static {
+	run_with(&SystemClock, timeout, f)
+}
+
+/// Runs `f` like `run`, waiting for its result with `clock`.
+pub(crate) fn run_with<T, F, C>(clock: &C, timeout: Duration, f: F) -> Result<T, SwapError> where T: Send + 'static, F: FnOnce() -> Result<T, SwapError> + Send + 'static, C: Clock {
+	let (sender, receiver) = mpsc::channel();
+	let handle = thread::Builder::new()
+		.name("fs-swap-timeout".into())
+		.spawn(move || {
+			// the receiver is gone if the caller stopped waiting
+			let _ = sender.send(f());
+		})?;
+
+	match clock.recv_timeout(&receiver, timeout) {
+		Ok(result) => result,
+		Err(RecvTimeoutError::Timeout) => {
+			swap_log!(warn, "Swap timed out, it is left running in the background"; timeout = ?timeout);
+			Err(SwapError::TimedOut)
+		},
+		Err(RecvTimeoutError::Disconnected) => match handle.join() {
+			Err(payload) => panic::resume_unwind(payload),
+			Ok(_) => unreachable!("the result is sent before the thread exits; qed"),
+		},
+	}
+}
@@ -0,0 +1,166 @@
+//! Checks performed before modifying any of the swapped paths.
+
+use std::collections::HashMap;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::{env, fs, io};
+use std::path::{self, Component, Path, PathBuf};
+use canonicalize_parent;
+use error::{SwapError, Side};
+use filesystem::{FileSystem, StdFs};
+use platform;
+
+/// Metadata of the swapped paths, read once by `check` and reused by the swap, so that the
+/// paths are not inspected again.
+pub(crate) struct Entries {
+	pub(crate) a: fs::Metadata,
+	pub(crate) b: fs::Metadata,
+	/// Whether `a` and `b` are the same file, like two hardlinks to it, so swapping them does
+	/// nothing.
+	pub(crate) same_file: bool,
+}
+
+fn is_separator(byte: u8) -> bool {
+	path::is_separator(byte as char)
+}
+
+/// Returns `path` without its trailing separators, so that `dir/` refers to the entry `dir`, for
+/// the renames as well as for the metadata. A root keeps its separator.
+fn trim_separators(path: &Path) -> &Path {
+	let bytes = path.as_os_str().as_encoded_bytes();
+	let mut len = bytes.len();
+	while len > 1 && is_separator(bytes[len - 1]) {
+		len -= 1;
+	}
+
+	if len == bytes.len() {
+		return path;
+	}
+
+	// splitting before an ASCII separator keeps the encoded bytes valid
+	let trimmed = Path::new(unsafe { OsStr::from_encoded_bytes_unchecked(&bytes[..len]) });
+	match trimmed.components().next_back() {
+		Some(Component::Normal(_)) | Some(Component::CurDir) | Some(Component::ParentDir) => trimmed,
+		_ => path,
+	}
+}
+
+/// Returns `path` without its trailing separators, and joined to the current directory if it's
+/// relative, so that the parent of each path is well defined and the paths are not affected if
+/// the current directory changes during the swap. Empty paths are kept, to be rejected by the
+/// checks.
+pub(crate) fn resolve(path: &Path) -> io::Result<Cow<'_, Path>> {
+	let path = trim_separators(path);
+	if path.is_relative() && !path.as_os_str().is_empty() {
+		return Ok(Cow::Owned(env::current_dir()?.join(path)));
+	}
+	Ok(Cow::Borrowed(path))
+}
+
+/// Rejects empty paths, paths containing a NUL byte, which no syscall accepts, and paths ending
+/// with `.` or `..`, which do not name an entry of their parent. On Windows, paths naming a device
+/// or a named pipe are rejected too.
+pub(crate) fn check_path(path: &Path) -> Result<(), SwapError> {
+	let bytes = path.as_os_str().as_encoded_bytes();
+	if bytes.is_empty() {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Path is empty").into());
+	}
+
+	if bytes.contains(&0) {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Path contains a NUL byte").into());
+	}
+
+	#[cfg(windows)]
+	platform::check_device(path)?;
+
+	let last = trim_separators(path).as_os_str().as_encoded_bytes()
+		.rsplit(|&byte| is_separator(byte))
+		.next();
+	if matches!(last, Some(b".") | Some(b"..")) {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Path ends with `.` or `..`").into());
+	}
+
+	Ok(())
+}
+
+fn metadata<F>(fs: &F, path: &Path, side: Side) -> Result<fs::Metadata, SwapError> where F: FileSystem {
+	fs.metadata(path).map_err(|err| match err.kind() {
+		io::ErrorKind::NotFound => SwapError::NotFound { which: side },
+		_ => SwapError::Io(err),
+	})
+}
+
+/// Checks that `a` and `b` are valid paths, exist and can be swapped, and returns their metadata.
+pub(crate) fn check(a: &Path, b: &Path) -> Result<Entries, SwapError> {
+	check_in(&StdFs, a, b)
+}
+
+/// Checks paths `a` and `b` like `check`, reading their metadata from `fs`.
+pub(crate) fn check_in<F>(fs: &F, a: &Path, b: &Path) -> Result<Entries, SwapError> where F: FileSystem {
+	check_path(a)?;
+	check_path(b)?;
+
+	let meta_a = metadata(fs, a, Side::A)?;
+	let meta_b = metadata(fs, b, Side::B)?;
+	if meta_a.is_dir() != meta_b.is_dir() {
+		return Err(SwapError::TypeMismatch);
+	}
+
+	if meta_a.is_dir() {
+		check_nested(a, b)?;
+	}
+
+	let same_file = platform::same_file(a, &meta_a, b, &meta_b).map_err(|err| SwapError::from_unmodified(err, a, b))?;
+	Ok(Entries { a: meta_a, b: meta_b, same_file })
+}
+
+/// Rejects directories `a` and `b` if one is inside the other, since moving it would also move
+/// the other one. Their parents are canonicalized, so that `..` and symlinks are resolved.
+pub(crate) fn check_nested(a: &Path, b: &Path) -> Result<(), SwapError> {
+	let key_a = entry_key(a);
+	let key_b = entry_key(b);
+	if key_a != key_b && (key_a.starts_with(&key_b) || key_b.starts_with(&key_a)) {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot swap a path with its descendant").into());
+	}
+	Ok(())
+}
+
+/// Checks that `a` and `b` are valid paths and returns which one is missing, if only one is.
+///
+/// Fails with `SwapError::NotFound` if both are missing.
+pub(crate) fn missing_side(a: &Path, b: &Path) -> Result<Option<Side>, SwapError> {
+	check_path(a)?;
+	check_path(b)?;
+
+	match (metadata(&StdFs, a, Side::A), metadata(&StdFs, b, Side::B)) {
+		(Ok(_), Ok(_)) => Ok(None),
+		(Err(SwapError::NotFound { .. }), Ok(_)) => Ok(Some(Side::A)),
+		(Ok(_), Err(SwapError::NotFound { .. })) => Ok(Some(Side::B)),
+		(Err(err), _) | (_, Err(err)) => Err(err),
+	}
+}
+
+/// Returns the path which identifies the directory entry of `path`. Its parent is canonicalized,
+/// but not a symlink in the last component, since symlinks themselves are swapped.
+fn entry_key(path: &Path) -> PathBuf {
+	canonicalize_parent(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Checks that no directory entry is used by two groups of paths, even if it's spelled
+/// differently. Returns the index of the first group using an entry of a previous group.
+///
+/// Paths within the same group may refer to the same entry.
+pub(crate) fn check_distinct<'a, I>(groups: I) -> Result<(), (usize, SwapError)> where I: IntoIterator<Item = Vec<&'a Path>> {
+	let mut seen: HashMap<PathBuf, &Path> = HashMap::new();
+	for (i, group) in groups.into_iter().enumerate() {
+		let keys: Vec<_> = group.iter().map(|path| (entry_key(path), *path)).collect();
+		for &(ref key, path) in &keys {
+			if let Some(previous) = seen.get(key) {
+				let message = format!("Paths {:?} and {:?} refer to the same entry", previous, path);
+				return Err((i, io::Error::new(io::ErrorKind::InvalidInput, message).into()));
+			}
+		}
+		seen.extend(keys);
+	}
+	Ok(())
+}
@@ -0,0 +1,105 @@
+//! Recovery of `swap_nonatomic` when one of its renames fails, through the `testing` feature.
+
+#![cfg(feature = "testing")]
+
+extern crate fs_swap;
+extern crate tempdir;
+
+use std::fs;
+#[cfg(unix)]
+use std::io;
+use std::path::Path;
+#[cfg(unix)]
+use fs_swap::swap_nonatomic;
+use fs_swap::SwapError;
+use fs_swap::testing::{assert_swapped, swap_nonatomic_failing};
+use tempdir::TempDir;
+
+fn write_files(a: &Path, b: &Path) {
+	fs::write(a, "foo").unwrap_or_else(|err| panic!("Writing {:?} failed: {}", a, err));
+	fs::write(b, "bar").unwrap_or_else(|err| panic!("Writing {:?} failed: {}", b, err));
+}
+
+// `a` was moved to the temp path, and is moved back once the rename of `b` to `a` failed
+#[test]
+fn rollback_after_moving_b_failed() {
+	let dir = TempDir::new("").unwrap();
+	let path_a = dir.path().join("file_a");
+	let path_b = dir.path().join("file_b");
+	write_files(&path_a, &path_b);
+
+	match swap_nonatomic_failing(&path_a, &path_b, &[false, true]) {
+		Err(ref err @ SwapError::RolledBack(_)) => assert!(err.is_unmodified()),
+		result => panic!("unexpected result: {:?}", result),
+	}
+	assert_swapped(&path_a, &path_b, "foo", "bar");
+	assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+}
+
+// `b` was moved to `a`, and both are moved back once the rename of the temp path to `b` failed
+#[test]
+fn rollback_after_moving_tmp_failed() {
+	let dir = TempDir::new("").unwrap();
+	let path_a = dir.path().join("file_a");
+	let path_b = dir.path().join("file_b");
+	write_files(&path_a, &path_b);
+
+	match swap_nonatomic_failing(&path_a, &path_b, &[false, false, true]) {
+		Err(ref err @ SwapError::RolledBack(_)) => assert!(err.is_unmodified()),
+		result => panic!("unexpected result: {:?}", result),
+	}
+	assert_swapped(&path_a, &path_b, "foo", "bar");
+	assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+	swap_nonatomic_failing(&path_a, &path_b, &[]).unwrap();
+	assert_swapped(&path_a, &path_b, "bar", "foo");
+}
+
+/// Makes a directory read-only, and writable again when dropped, so that it can be removed even
+/// if the test fails.
+#[cfg(unix)]
+struct ReadOnlyDir<'a>(&'a Path);
+
+#[cfg(unix)]
+impl<'a> ReadOnlyDir<'a> {
+	fn new(path: &'a Path) -> Self {
+		use std::os::unix::fs::PermissionsExt;
+
+		fs::set_permissions(path, fs::Permissions::from_mode(0o555)).unwrap();
+		ReadOnlyDir(path)
+	}
+}
+
+#[cfg(unix)]
+impl<'a> Drop for ReadOnlyDir<'a> {
+	fn drop(&mut self) {
+		use std::os::unix::fs::PermissionsExt;
+
+		let _ = fs::set_permissions(self.0, fs::Permissions::from_mode(0o755));
+	}
+}
+
+// the rename of `b` to `a` fails for real, since `b` cannot be moved out of its read-only
+// directory, while `a` was already moved to the temp path
+#[cfg(unix)]
+#[test]
+fn rollback_after_read_only_dir() {
+	let dir_a = TempDir::new("a").unwrap();
+	let dir_b = TempDir::new("b").unwrap();
+	let path_a = dir_a.path().join("file_a");
+	let path_b = dir_b.path().join("file_b");
+	write_files(&path_a, &path_b);
+
+	let _read_only = ReadOnlyDir::new(dir_b.path());
+	// permissions are not enforced for privileged users
+	if fs::File::create(dir_b.path().join("probe")).is_ok() {
+		return;
+	}
+
+	match swap_nonatomic(&path_a, &path_b) {
+		Err(SwapError::RolledBack(ref err)) if err.kind() == io::ErrorKind::PermissionDenied => (),
+		result => panic!("unexpected result: {:?}", result),
+	}
+	assert_swapped(&path_a, &path_b, "foo", "bar");
+	assert_eq!(1, fs::read_dir(dir_a.path()).unwrap().count());
+}